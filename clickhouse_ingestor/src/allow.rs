@@ -0,0 +1,154 @@
+//! `NATS_SUBJECT_ALLOW`: subjects the ingestor may insert from, as NATS patterns
+//! (`*` matches one token, a trailing `>` one or more), e.g. `WALLET.updates.*`.
+//!
+//! A safety net when `NATS_SUBJECT` is a wildcard over a shared namespace: messages
+//! on any other subject are dropped (and dead-lettered with a DLQ) before they
+//! reach the batch.
+//!
+//! `CH_SUBJECT_TABLES` routes by the same patterns: `pattern=table[:transform],...`
+//! (transforms as in `CH_TABLES`) inserts rows from the first matching subject into
+//! that table instead of `CH_TABLE` / `CH_TABLES`, e.g.
+//! `WALLET.updates.usdc=usdc_updates,WALLET.updates.*=other_updates`. Rows from
+//! subjects matching no route still go to the default tables.
+
+use crate::tables::{self, TableSpec};
+
+pub struct SubjectAllow {
+    patterns: Vec<String>,
+    dropped: u64,
+}
+
+impl SubjectAllow {
+    /// Parse a comma-separated pattern list.
+    pub fn parse(raw: &str) -> anyhow::Result<Self> {
+        let patterns: Vec<String> = raw.split(',').map(str::trim).filter(|s| !s.is_empty()).map(String::from).collect();
+        anyhow::ensure!(!patterns.is_empty(), "NATS_SUBJECT_ALLOW lists no subjects");
+        for p in &patterns {
+            validate("NATS_SUBJECT_ALLOW", p)?;
+        }
+        Ok(SubjectAllow { patterns, dropped: 0 })
+    }
+
+    pub fn describe(&self) -> String {
+        self.patterns.join(", ")
+    }
+
+    /// Whether `subject` matches a pattern; counts it as dropped if not.
+    pub fn check(&mut self, subject: &str) -> bool {
+        let ok = self.patterns.iter().any(|p| matches(p, subject));
+        if !ok {
+            self.dropped += 1;
+        }
+        ok
+    }
+
+    pub fn dropped(&self) -> u64 {
+        self.dropped
+    }
+}
+
+/// `CH_SUBJECT_TABLES`: which table, if any, rows from a subject go to.
+pub struct SubjectTables {
+    patterns: Vec<String>,
+}
+
+impl SubjectTables {
+    /// Parse `pattern=table[:transform],...`; the specs are the routes' tables, in the
+    /// order [`SubjectTables::route`] numbers them.
+    pub fn parse(raw: &str) -> anyhow::Result<(Self, Vec<TableSpec>)> {
+        let mut patterns = Vec::new();
+        let mut specs = Vec::new();
+        for entry in raw.split(',').map(str::trim).filter(|s| !s.is_empty()) {
+            let Some((pattern, table)) = entry.split_once('=') else {
+                anyhow::bail!("CH_SUBJECT_TABLES entry {entry:?} is not pattern=table");
+            };
+            let pattern = pattern.trim();
+            validate("CH_SUBJECT_TABLES", pattern)?;
+            // entries are comma-split already, so this is exactly one table
+            specs.extend(tables::parse(table)?);
+            patterns.push(pattern.to_string());
+        }
+        anyhow::ensure!(!patterns.is_empty(), "CH_SUBJECT_TABLES lists no routes");
+        Ok((SubjectTables { patterns }, specs))
+    }
+
+    /// The first route whose pattern matches `subject`.
+    pub fn route(&self, subject: &str) -> Option<usize> {
+        self.patterns.iter().position(|p| matches(p, subject))
+    }
+}
+
+fn validate(var: &str, pattern: &str) -> anyhow::Result<()> {
+    let tokens: Vec<&str> = pattern.split('.').collect();
+    anyhow::ensure!(
+        tokens.iter().all(|t| !t.is_empty()) && !tokens[..tokens.len() - 1].contains(&">"),
+        "{var} pattern {pattern:?} is not a valid subject pattern"
+    );
+    Ok(())
+}
+
+fn matches(pattern: &str, subject: &str) -> bool {
+    let mut subject = subject.split('.');
+    for want in pattern.split('.') {
+        match (want, subject.next()) {
+            (">", Some(_)) => return true,
+            ("*", Some(_)) => {}
+            (want, Some(got)) if want == got => {}
+            _ => return false,
+        }
+    }
+    subject.next().is_none()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tables::Transform;
+
+    #[test]
+    fn unexpected_subject_is_dropped_and_counted() {
+        let mut allow = SubjectAllow::parse("WALLET.updates.*, WALLET.events").unwrap();
+        assert!(allow.check("WALLET.updates.usdc"));
+        assert!(allow.check("WALLET.events"));
+        assert!(!allow.check("WALLET.updates"), "* needs a token");
+        assert!(!allow.check("WALLET.updates.usdc.extra"));
+        assert!(!allow.check("OTHER.updates.usdc"));
+        assert_eq!(allow.dropped(), 3);
+    }
+
+    #[test]
+    fn trailing_wildcard_matches_one_or_more_tokens() {
+        let mut allow = SubjectAllow::parse("WALLET.>").unwrap();
+        assert!(allow.check("WALLET.a"));
+        assert!(allow.check("WALLET.a.b.c"));
+        assert!(!allow.check("WALLET"));
+    }
+
+    #[test]
+    fn invalid_patterns_are_rejected() {
+        assert!(SubjectAllow::parse("").is_err());
+        assert!(SubjectAllow::parse("WALLET..x").is_err());
+        assert!(SubjectAllow::parse("WALLET.>.x").is_err());
+    }
+
+    #[test]
+    fn subjects_route_to_the_first_matching_table() {
+        let (routes, specs) =
+            SubjectTables::parse("WALLET.updates.usdc=usdc_updates, WALLET.updates.*=other_updates:latest").unwrap();
+        assert_eq!(routes.route("WALLET.updates.usdc"), Some(0));
+        assert_eq!(routes.route("WALLET.updates.sol"), Some(1));
+        assert_eq!(routes.route("WALLET.events"), None);
+        assert_eq!(specs[0].name, "usdc_updates");
+        assert_eq!(specs[0].transform, Transform::Raw);
+        assert_eq!(specs[1].name, "other_updates");
+        assert_eq!(specs[1].transform, Transform::Latest);
+    }
+
+    #[test]
+    fn malformed_routes_are_rejected() {
+        assert!(SubjectTables::parse("WALLET.updates").is_err());
+        assert!(SubjectTables::parse("WALLET.updates=a,b").is_err(), "a comma starts the next route");
+        assert!(SubjectTables::parse("WALLET..x=t").is_err());
+        assert!(SubjectTables::parse("WALLET.x=bad-name").is_err());
+    }
+}
@@ -0,0 +1,106 @@
+//! PAYLOAD_FORMAT=borsh: rows from a plugin with `payload_format = "borsh"`, turned
+//! back into the JSON rows every output path (and the DLQ) works with.
+
+use anyhow::{Context, Result};
+use borsh::BorshDeserialize;
+use serde::Serialize;
+
+// the plugin's BORSH_ROW_VERSION this mirror matches
+const VERSION: u8 = 2;
+
+/// Mirror of the plugin's `Row` in its field order (keep in sync; the Borsh layout
+/// has no names, so every field must be here even if ClickHouse ignores it).
+#[derive(BorshDeserialize, Serialize)]
+#[cfg_attr(test, derive(borsh::BorshSerialize, serde::Deserialize))]
+struct BorshRow {
+    ts: String,
+    slot: u64,
+    write_ver: u64,
+    pubkey: String,
+    lamports: u128,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    data: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    data_encoding: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    data_truncated: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    data_len: Option<u64>,
+    #[serde(rename = "final", skip_serializing_if = "Option::is_none")]
+    is_final: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    txn_index: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    leader: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    source_host: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    run_id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    voter: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    stake: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    activation_epoch: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    deactivation_epoch: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    token_mint: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    token_owner: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    token_amount: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    token_ui_amount: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    slot_time: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    node_pubkey: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    commission: Option<u8>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    last_vote_slot: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    credits: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    epoch: Option<u64>,
+}
+
+/// Decode one Borsh message (a version byte, then the row) into a JSON row.
+pub fn to_json(payload: &[u8]) -> Result<String> {
+    let (&version, body) = payload.split_first().context("empty message")?;
+    anyhow::ensure!(version == VERSION, "Borsh row version {version}, this ingestor reads version {VERSION}");
+    let row = BorshRow::try_from_slice(body).context("not a Borsh row")?;
+    serde_json::to_string(&row).context("row to JSON")
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::{Value, json};
+
+    use super::*;
+
+    #[test]
+    fn rows_round_trip_through_borsh() {
+        let row = json!({
+            "ts": "2025-11-13 22:15:33",
+            "slot": 312_000_123,
+            "write_ver": 7,
+            "pubkey": "9xQeWvG816bUx9EPjHmaT23yvVM2ZWbrrpZb9PusVFin",
+            "lamports": 2_039_280,
+            "final": true,
+            "token_mint": "EPjFWdd5AufqSSqeM2qN1xzybapC8G4wEGGkZwyTDt1v",
+            "token_amount": 0,
+            "token_ui_amount": 1.5,
+            "commission": 5,
+        });
+        let borsh_row: BorshRow = serde_json::from_value(row.clone()).unwrap();
+        let payload = [vec![VERSION], borsh::to_vec(&borsh_row).unwrap()].concat();
+        assert_eq!(serde_json::from_str::<Value>(&to_json(&payload).unwrap()).unwrap(), row);
+
+        // another version's rows, and anything cut short, are refused
+        assert!(to_json(&[[VERSION + 1].as_slice(), &payload[1..]].concat()).is_err());
+        assert!(to_json(&payload[..payload.len() - 1]).is_err());
+        assert!(to_json(&[]).is_err());
+    }
+}
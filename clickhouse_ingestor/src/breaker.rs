@@ -0,0 +1,104 @@
+//! Circuit breaker around ClickHouse inserts (`CB_FAILURES`, `CB_COOLDOWN_MS`).
+//!
+//! With the breaker on, an insert that fails for lack of a healthy ClickHouse
+//! (transport error or 5xx after `CH_INSERT_RETRIES`) keeps its rows in the batch
+//! instead of dead-lettering them; 4xx rejections are dead-lettered as before.
+//! After `CB_FAILURES` consecutive failures the breaker opens: nothing is read from
+//! NATS (JetStream messages stay on the server) and nothing is sent for the
+//! cool-down. Then it half-opens: the next timer flush retries the kept batch,
+//! closing the breaker on success and reopening it on failure. Retried batches
+//! carry the same insert_deduplication_token, so a table that did take them isn't
+//! written twice.
+
+use std::time::{Duration, Instant};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum State {
+    Closed,
+    Open,
+    HalfOpen,
+}
+
+pub struct Breaker {
+    threshold: u32,
+    cooldown: Duration,
+    failures: u32,
+    opened: Option<Instant>,
+}
+
+impl Breaker {
+    pub fn new(threshold: u32, cooldown: Duration) -> Self {
+        Breaker { threshold: threshold.max(1), cooldown, failures: 0, opened: None }
+    }
+
+    pub fn state(&self) -> State {
+        match self.opened {
+            None => State::Closed,
+            Some(at) if at.elapsed() < self.cooldown => State::Open,
+            Some(_) => State::HalfOpen,
+        }
+    }
+
+    /// Read from NATS only while closed; a half-open breaker first proves the
+    /// kept batch can be written.
+    pub fn allows_consume(&self) -> bool {
+        self.state() == State::Closed
+    }
+
+    /// Whether a size-triggered flush may run now. After a failure, retries wait for
+    /// the timer so a backlog doesn't retry on every incoming message.
+    pub fn allows_eager_flush(&self) -> bool {
+        self.failures == 0
+    }
+
+    /// Whether the timer flush may run now.
+    pub fn allows_flush(&self) -> bool {
+        self.state() != State::Open
+    }
+
+    /// Feed the outcome of one flush; returns the new state when it changed.
+    pub fn record(&mut self, ok: bool) -> Option<State> {
+        let before = self.state();
+        if ok {
+            self.failures = 0;
+            self.opened = None;
+        } else {
+            self.failures = self.failures.saturating_add(1);
+            if self.failures >= self.threshold {
+                self.opened = Some(Instant::now());
+            }
+        }
+        let after = self.state();
+        (after != before).then_some(after)
+    }
+
+    pub fn failures(&self) -> u32 {
+        self.failures
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn opens_after_repeated_failures_and_closes_on_a_good_retry() {
+        let cooldown = Duration::from_millis(50);
+        let mut breaker = Breaker::new(2, cooldown);
+        assert_eq!(breaker.record(false), None);
+        assert!(breaker.allows_consume() && !breaker.allows_eager_flush());
+        assert_eq!(breaker.record(false), Some(State::Open));
+        assert!(!breaker.allows_consume() && !breaker.allows_flush());
+
+        std::thread::sleep(cooldown);
+        assert_eq!(breaker.state(), State::HalfOpen);
+        assert!(breaker.allows_flush() && !breaker.allows_consume());
+        // a failed probe starts another cool-down
+        assert_eq!(breaker.record(false), Some(State::Open));
+
+        std::thread::sleep(cooldown);
+        assert_eq!(breaker.record(true), Some(State::Closed));
+        assert!(breaker.allows_consume() && breaker.allows_eager_flush());
+        assert_eq!(breaker.failures(), 0);
+    }
+}
@@ -0,0 +1,101 @@
+//! Per-pubkey coalescing (`COALESCE_MS`): within one window only the latest row
+//! per pubkey, by `(slot, write_ver)`, is kept; the survivors are released when
+//! the window closes, whether or not a batch is full.
+
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+/// The row fields coalescing orders by.
+#[derive(Deserialize)]
+struct RowKey {
+    pubkey: String,
+    slot: u64,
+    write_ver: u64,
+}
+
+pub struct Coalescer {
+    window: Duration,
+    opened: Instant,
+    latest: HashMap<String, ((u64, u64), String)>,
+    // rows that don't look like account rows are never merged
+    passthrough: Vec<String>,
+    absorbed: u64,
+}
+
+impl Coalescer {
+    pub fn new(window: Duration) -> Self {
+        Coalescer { window, opened: Instant::now(), latest: HashMap::new(), passthrough: Vec::new(), absorbed: 0 }
+    }
+
+    /// Move every row out of `buf` into the window.
+    pub fn absorb(&mut self, buf: &mut Vec<String>) {
+        if self.is_empty() {
+            self.opened = Instant::now();
+        }
+        for line in buf.drain(..) {
+            let Ok(key) = serde_json::from_str::<RowKey>(&line) else {
+                self.passthrough.push(line);
+                continue;
+            };
+            self.absorbed += 1;
+            let order = (key.slot, key.write_ver);
+            match self.latest.get_mut(&key.pubkey) {
+                // ties keep the newer arrival: same (slot, write_ver) is a redelivery
+                Some((seen, row)) if order >= *seen => {
+                    *seen = order;
+                    *row = line;
+                }
+                Some(_) => {}
+                None => {
+                    self.latest.insert(key.pubkey, (order, line));
+                }
+            }
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.latest.is_empty() && self.passthrough.is_empty()
+    }
+
+    pub fn window_elapsed(&self) -> bool {
+        !self.is_empty() && self.opened.elapsed() >= self.window
+    }
+
+    /// Release the window's surviving rows into `buf`.
+    pub fn drain_into(&mut self, buf: &mut Vec<String>) {
+        let kept = self.latest.len();
+        buf.append(&mut self.passthrough);
+        buf.extend(self.latest.drain().map(|(_, (_, row))| row));
+        if self.absorbed > kept as u64 {
+            println!("coalesced {} row(s) into {kept}", self.absorbed);
+        }
+        self.absorbed = 0;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn row(pubkey: &str, slot: u64, write_ver: u64) -> String {
+        format!(r#"{{"pubkey":"{pubkey}","slot":{slot},"write_ver":{write_ver},"lamports":{}}}"#, slot * 10 + write_ver)
+    }
+
+    #[test]
+    fn window_keeps_the_latest_row_per_pubkey() {
+        let mut c = Coalescer::new(Duration::from_millis(20));
+        c.absorb(&mut vec![row("a", 5, 1), row("b", 5, 1), row("a", 6, 0)]);
+        // older than what the window holds, so dropped
+        let mut buf = vec![row("a", 5, 9), row("b", 5, 2), "not a row".to_string()];
+        c.absorb(&mut buf);
+        assert!(buf.is_empty() && !c.is_empty());
+        assert!(!c.window_elapsed());
+        std::thread::sleep(Duration::from_millis(25));
+        assert!(c.window_elapsed());
+        c.drain_into(&mut buf);
+        buf.sort();
+        assert_eq!(buf, vec!["not a row".to_string(), row("a", 6, 0), row("b", 5, 2)]);
+        assert!(c.is_empty() && !c.window_elapsed());
+    }
+}
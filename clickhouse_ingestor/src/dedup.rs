@@ -0,0 +1,95 @@
+//! In-process duplicate suppression (`DEDUP_MAX`): rows whose
+//! `(pubkey, slot, write_ver)` was already seen by this process are dropped.
+//!
+//! This only catches redeliveries within one ingestor run and within the last
+//! `DEDUP_MAX` distinct rows; across restarts rely on a `ReplacingMergeTree`
+//! ordered by `(pubkey, slot, write_ver)`.
+
+use serde::Deserialize;
+use std::collections::{HashSet, VecDeque};
+
+/// The row fields that identify one account write.
+#[derive(Deserialize)]
+struct RowKey {
+    pubkey: String,
+    slot: u64,
+    write_ver: u64,
+}
+
+type Key = (String, u64, u64);
+
+pub struct SeenSet {
+    cap: usize,
+    seen: HashSet<Key>,
+    // insertion order, for FIFO eviction
+    order: VecDeque<Key>,
+    dropped: u64,
+}
+
+impl SeenSet {
+    pub fn new(cap: usize) -> Self {
+        SeenSet { cap: cap.max(1), seen: HashSet::new(), order: VecDeque::new(), dropped: 0 }
+    }
+
+    /// Drop duplicates among `buf[from..]`, remembering the rest. Rows that
+    /// don't look like account rows are always kept.
+    pub fn filter_from(&mut self, buf: &mut Vec<String>, from: usize) {
+        let fresh: Vec<String> = buf.drain(from..).collect();
+        for line in fresh {
+            let Ok(k) = serde_json::from_str::<RowKey>(&line) else {
+                buf.push(line);
+                continue;
+            };
+            if self.insert((k.pubkey, k.slot, k.write_ver)) {
+                buf.push(line);
+            } else {
+                self.dropped += 1;
+            }
+        }
+    }
+
+    /// Remember `key`; false if it was already seen.
+    fn insert(&mut self, key: Key) -> bool {
+        if self.seen.contains(&key) {
+            return false;
+        }
+        if self.order.len() >= self.cap
+            && let Some(oldest) = self.order.pop_front()
+        {
+            self.seen.remove(&oldest);
+        }
+        self.seen.insert(key.clone());
+        self.order.push_back(key);
+        true
+    }
+
+    pub fn dropped(&self) -> u64 {
+        self.dropped
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn row(pubkey: &str, slot: u64, write_ver: u64, source_host: &str) -> String {
+        format!(r#"{{"pubkey":"{pubkey}","slot":{slot},"write_ver":{write_ver},"source_host":"{source_host}"}}"#)
+    }
+
+    #[test]
+    fn rows_seen_before_are_dropped_whatever_the_run() {
+        let mut seen = SeenSet::new(2);
+        let mut buf = vec![row("a", 1, 1, "v1"), row("a", 1, 2, "v1")];
+        seen.filter_from(&mut buf, 0);
+        assert_eq!(buf.len(), 2);
+        // a restarted plugin re-emits (a, 1, 1); only the rows after `from` are checked
+        buf.extend([row("a", 1, 1, "v2"), row("b", 1, 1, "v2"), "{}".to_string()]);
+        seen.filter_from(&mut buf, 2);
+        assert_eq!(buf, vec![row("a", 1, 1, "v1"), row("a", 1, 2, "v1"), row("b", 1, 1, "v2"), "{}".to_string()]);
+        assert_eq!(seen.dropped(), 1);
+        // at the cap the oldest key is forgotten, so it passes again
+        let mut again = vec![row("a", 1, 1, "v3")];
+        seen.filter_from(&mut again, 0);
+        assert_eq!(again.len(), 1);
+    }
+}
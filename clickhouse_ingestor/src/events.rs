@@ -0,0 +1,182 @@
+//! Plugin events on `NATS_EVENTS_SUBJECT` (the plugin's `events_subject`), written
+//! to ClickHouse once every row received before them has been written.
+//!
+//! `CH_CHECKPOINT_TABLE`: the highest rooted slot whose rows are all in ClickHouse.
+//! With the plugin's `publish_rooted_slots`, every rooted slot arrives as
+//! `{"type":"rooted_slot","slot":N}`, behind that slot's rows, and the ingestor
+//! inserts `max_rooted_slot` whenever it moved, so readers can query
+//! `WHERE slot <= (SELECT max(max_rooted_slot) FROM wallet_checkpoints)` and only see
+//! complete, final slots:
+//!
+//! ```sql
+//! CREATE TABLE wallet_checkpoints
+//! (
+//!     `ts`              DateTime DEFAULT now(),
+//!     `max_rooted_slot` UInt64
+//! )
+//! ENGINE = MergeTree
+//! ORDER BY ts;
+//! ```
+//!
+//! `CH_SLOT_STATS_TABLE`: with the plugin's `publish_slot_stats`, one
+//! `{"type":"slot_stats","slot":N,"matched_count":M}` per rooted slot, inserted as is.
+//! Joining it against the row table flags slots where ClickHouse has fewer rows than
+//! the plugin published:
+//!
+//! ```sql
+//! CREATE TABLE slot_stats
+//! (
+//!     `ts`            DateTime DEFAULT now(),
+//!     `slot`          UInt64,
+//!     `matched_count` UInt64
+//! )
+//! ENGINE = ReplacingMergeTree
+//! ORDER BY slot;
+//! ```
+//!
+//! Rows and events share one connection (or one JetStream consumer, which then must
+//! capture the events subject too), which is what keeps them in order.
+
+use serde::Deserialize;
+
+use crate::{ClickHouse, flush};
+
+// slot stats kept for retry while ClickHouse rejects them; the oldest go first
+const MAX_PENDING_STATS: usize = 10_000;
+
+#[derive(Deserialize)]
+struct Event {
+    #[serde(rename = "type")]
+    kind: String,
+    slot: Option<u64>,
+    matched_count: Option<u64>,
+}
+
+pub struct PluginEvents {
+    checkpoint_url: Option<String>,
+    max_rooted: u64,
+    written: u64,
+    stats_url: Option<String>,
+    // JSONEachRow lines not yet inserted
+    stats: Vec<String>,
+}
+
+fn insert_url(ch_http: &str, db: &str, table: &str, columns: &str) -> String {
+    format!("{ch_http}/?query=INSERT%20INTO%20{db}.{table}%20({columns})%20FORMAT%20JSONEachRow")
+}
+
+impl PluginEvents {
+    pub fn new(ch_http: &str, db: &str, checkpoint_table: Option<&str>, stats_table: Option<&str>) -> Self {
+        PluginEvents {
+            checkpoint_url: checkpoint_table.map(|t| insert_url(ch_http, db, t, "max_rooted_slot")),
+            max_rooted: 0,
+            written: 0,
+            stats_url: stats_table.map(|t| insert_url(ch_http, db, t, "slot,matched_count")),
+            stats: Vec::new(),
+        }
+    }
+
+    /// Take in one plugin event; types without a table are ignored.
+    pub fn on_event(&mut self, payload: &[u8]) {
+        let event = match serde_json::from_slice::<Event>(payload) {
+            Ok(event) => event,
+            Err(e) => {
+                eprintln!("ignoring unparseable plugin event: {e}");
+                return;
+            }
+        };
+        match (event.kind.as_str(), event.slot) {
+            ("rooted_slot", Some(slot)) if self.checkpoint_url.is_some() => {
+                self.max_rooted = self.max_rooted.max(slot);
+            }
+            ("slot_stats", Some(slot)) if self.stats_url.is_some() => {
+                if self.stats.len() >= MAX_PENDING_STATS {
+                    self.stats.remove(0);
+                }
+                let count = event.matched_count.unwrap_or(0);
+                self.stats.push(format!("{{\"slot\":{slot},\"matched_count\":{count}}}"));
+            }
+            _ => {}
+        }
+    }
+
+    /// Insert what arrived since the last call; call only once every row received so
+    /// far has been written. Failed inserts are logged and retried on the next call.
+    pub async fn write(&mut self, ch: &ClickHouse) {
+        if let Some(url) = &self.stats_url
+            && !self.stats.is_empty()
+        {
+            let body = (self.stats.join("\n") + "\n").into_bytes();
+            match flush(ch, url, body, None).await {
+                Ok(None) => self.stats.clear(),
+                Ok(Some(_)) => {}
+                Err(e) => eprintln!("slot stats insert error: {e:#}"),
+            }
+        }
+        if let Some(url) = &self.checkpoint_url
+            && self.max_rooted > self.written
+        {
+            let body = format!("{{\"max_rooted_slot\":{}}}\n", self.max_rooted).into_bytes();
+            match flush(ch, url, body, None).await {
+                Ok(None) => self.written = self.max_rooted,
+                Ok(Some(_)) => {}
+                Err(e) => eprintln!("checkpoint insert error: {e:#}"),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::Ordering;
+
+    use super::*;
+    use crate::tests::{clickhouse, mock_clickhouse};
+
+    #[tokio::test]
+    async fn checkpoint_is_written_only_when_the_rooted_slot_moves() {
+        let mock = mock_clickhouse(200).await;
+        let ch = clickhouse(&mock.url, false);
+        let base = mock.url.split("/?").next().unwrap();
+        let mut events = PluginEvents::new(base, "default", Some("wallet_checkpoints"), None);
+        for slot in [10, 12, 11] {
+            events.on_event(format!(r#"{{"type":"rooted_slot","slot":{slot}}}"#).as_bytes());
+        }
+        // without a stats table, slot stats are not kept
+        events.on_event(br#"{"type":"slot_stats","slot":12,"matched_count":3}"#);
+        events.write(&ch).await;
+        // nothing new: no insert
+        events.on_event(br#"{"type":"rooted_slot","slot":9}"#);
+        events.write(&ch).await;
+
+        // a rejected checkpoint is retried on the next write
+        mock.status.store(500, Ordering::SeqCst);
+        events.on_event(br#"{"type":"rooted_slot","slot":13}"#);
+        events.write(&ch).await;
+        mock.status.store(200, Ordering::SeqCst);
+        events.write(&ch).await;
+
+        let bodies: Vec<String> = mock.bodies.lock().unwrap().iter().map(|b| String::from_utf8_lossy(b).into()).collect();
+        assert_eq!(bodies, ["{\"max_rooted_slot\":12}\n", "{\"max_rooted_slot\":13}\n", "{\"max_rooted_slot\":13}\n"]);
+    }
+
+    #[tokio::test]
+    async fn slot_stats_go_to_their_own_table_and_are_retried() {
+        let mock = mock_clickhouse(500).await;
+        let ch = clickhouse(&mock.url, false);
+        let base = mock.url.split("/?").next().unwrap();
+        let mut events = PluginEvents::new(base, "default", None, Some("slot_stats"));
+        events.on_event(br#"{"type":"slot_stats","slot":10,"matched_count":3}"#);
+        // without a checkpoint table, rooted slots are not kept
+        events.on_event(br#"{"type":"rooted_slot","slot":10}"#);
+        events.write(&ch).await;
+        mock.status.store(200, Ordering::SeqCst);
+        events.on_event(br#"{"type":"slot_stats","slot":11,"matched_count":1}"#);
+        events.write(&ch).await;
+        events.write(&ch).await;
+
+        let bodies: Vec<String> = mock.bodies.lock().unwrap().iter().map(|b| String::from_utf8_lossy(b).into()).collect();
+        let both = "{\"slot\":10,\"matched_count\":3}\n{\"slot\":11,\"matched_count\":1}\n";
+        assert_eq!(bodies, ["{\"slot\":10,\"matched_count\":3}\n", both]);
+    }
+}
@@ -0,0 +1,166 @@
+//! PAYLOAD_FORMAT=flatbuffers: rows from a plugin with `payload_format = "flatbuffers"`
+//! (the `wallet.Row` table of proto/wallet_row.fbs), verified and turned back into the
+//! JSON rows every output path (and the DLQ) works with.
+
+use anyhow::{Context, Result};
+use flatbuffers::{Follow, ForwardsUOffset, InvalidFlatbuffer, Table, VOffsetT, Verifiable, Verifier};
+use serde_json::{Map, Value};
+
+// the schema's file_identifier, at bytes 4..8
+const FILE_IDENTIFIER: &str = "WROW";
+
+#[derive(Clone, Copy)]
+enum Type {
+    Str,
+    Bool,
+    U8,
+    U64,
+    F64,
+}
+
+/// The schema's fields in id order (keep in sync with proto/wallet_row.fbs): JSON name,
+/// type, and whether every row has it (required strings; scalars that default to 0).
+/// Fields a newer plugin appends are skipped until they are added here.
+const FIELDS: &[(&str, Type, bool)] = &[
+    ("ts", Type::Str, true),
+    ("slot", Type::U64, true),
+    ("write_ver", Type::U64, true),
+    ("pubkey", Type::Str, true),
+    ("lamports", Type::U64, true),
+    ("data", Type::Str, false),
+    ("data_encoding", Type::Str, false),
+    ("data_truncated", Type::Bool, false),
+    ("data_len", Type::U64, false),
+    ("final", Type::Bool, false),
+    ("txn_index", Type::U64, false),
+    ("leader", Type::Str, false),
+    ("source_host", Type::Str, false),
+    ("run_id", Type::Str, false),
+    ("voter", Type::Str, false),
+    ("stake", Type::U64, false),
+    ("activation_epoch", Type::U64, false),
+    ("deactivation_epoch", Type::U64, false),
+    ("token_mint", Type::Str, false),
+    ("token_owner", Type::Str, false),
+    ("token_amount", Type::U64, false),
+    ("token_ui_amount", Type::F64, false),
+    ("slot_time", Type::Str, false),
+    ("node_pubkey", Type::Str, false),
+    ("commission", Type::U8, false),
+    ("last_vote_slot", Type::U64, false),
+    ("credits", Type::U64, false),
+    ("epoch", Type::U64, false),
+];
+
+// vtable slot of the field with this id
+fn slot(id: usize) -> VOffsetT {
+    flatbuffers::field_index_to_field_offset(id as VOffsetT)
+}
+
+struct RowTable<'a>(Table<'a>);
+
+impl<'a> Follow<'a> for RowTable<'a> {
+    type Inner = Self;
+
+    unsafe fn follow(buf: &'a [u8], loc: usize) -> Self {
+        // SAFETY: guaranteed by the caller, as for Table::new
+        RowTable(unsafe { Table::new(buf, loc) })
+    }
+}
+
+impl Verifiable for RowTable<'_> {
+    fn run_verifier(v: &mut Verifier<'_, '_>, pos: usize) -> Result<(), InvalidFlatbuffer> {
+        let mut table = v.visit_table(pos)?;
+        for (id, &(name, ty, always)) in FIELDS.iter().enumerate() {
+            table = match ty {
+                Type::Str => table.visit_field::<ForwardsUOffset<&str>>(name, slot(id), always)?,
+                Type::Bool => table.visit_field::<bool>(name, slot(id), false)?,
+                Type::U8 => table.visit_field::<u8>(name, slot(id), false)?,
+                Type::U64 => table.visit_field::<u64>(name, slot(id), false)?,
+                Type::F64 => table.visit_field::<f64>(name, slot(id), false)?,
+            };
+        }
+        table.finish();
+        Ok(())
+    }
+}
+
+/// Verify one FlatBuffers message and turn it into a JSON row.
+pub fn to_json(payload: &[u8]) -> Result<String> {
+    anyhow::ensure!(
+        payload.len() >= 8 && flatbuffers::buffer_has_identifier(payload, FILE_IDENTIFIER, false),
+        "no {FILE_IDENTIFIER} file identifier"
+    );
+    let row = flatbuffers::root::<RowTable>(payload).context("not a wallet.Row")?;
+    let mut out = Map::new();
+    for (id, &(name, ty, always)) in FIELDS.iter().enumerate() {
+        let zero = always.then_some(0);
+        // SAFETY: root() verified every field against its type in FIELDS
+        let value = unsafe {
+            match ty {
+                Type::Str => row.0.get::<ForwardsUOffset<&str>>(slot(id), None).map(Value::from),
+                Type::Bool => row.0.get::<bool>(slot(id), None).map(Value::from),
+                Type::U8 => row.0.get::<u8>(slot(id), None).map(Value::from),
+                Type::U64 => row.0.get::<u64>(slot(id), zero).map(Value::from),
+                Type::F64 => row.0.get::<f64>(slot(id), None).map(Value::from),
+            }
+        };
+        if let Some(value) = value {
+            out.insert(name.to_string(), value);
+        }
+    }
+    serde_json::to_string(&out).context("row to JSON")
+}
+
+#[cfg(test)]
+mod tests {
+    use flatbuffers::FlatBufferBuilder;
+
+    use super::*;
+
+    // a row as the plugin writes it: required fields, one optional string, a set zero
+    fn row() -> Vec<u8> {
+        let mut fbb = FlatBufferBuilder::new();
+        let ts = fbb.create_string("2025-11-13 22:15:33");
+        let pubkey = fbb.create_string("9xQeWvG816bUx9EPjHmaT23yvVM2ZWbrrpZb9PusVFin");
+        let mint = fbb.create_string("EPjFWdd5AufqSSqeM2qN1xzybapC8G4wEGGkZwyTDt1v");
+        let table = fbb.start_table();
+        fbb.push_slot_always(slot(0), ts);
+        fbb.push_slot_always(slot(1), 312_000_123u64);
+        fbb.push_slot_always(slot(2), 7u64);
+        fbb.push_slot_always(slot(3), pubkey);
+        fbb.push_slot_always(slot(4), 2_039_280u64);
+        fbb.push_slot_always(slot(18), mint);
+        fbb.push_slot_always(slot(20), 0u64);
+        let table = fbb.end_table(table);
+        fbb.finish(table, Some(FILE_IDENTIFIER));
+        fbb.finished_data().to_vec()
+    }
+
+    #[test]
+    fn plugin_row_turns_back_into_json() {
+        let json: Value = serde_json::from_str(&to_json(&row()).unwrap()).unwrap();
+        assert_eq!(
+            json,
+            serde_json::json!({
+                "ts": "2025-11-13 22:15:33",
+                "slot": 312_000_123,
+                "write_ver": 7,
+                "pubkey": "9xQeWvG816bUx9EPjHmaT23yvVM2ZWbrrpZb9PusVFin",
+                "lamports": 2_039_280,
+                "token_mint": "EPjFWdd5AufqSSqeM2qN1xzybapC8G4wEGGkZwyTDt1v",
+                "token_amount": 0,
+            })
+        );
+    }
+
+    #[test]
+    fn foreign_or_damaged_buffers_are_rejected() {
+        let mut other = row();
+        other[4..8].copy_from_slice(b"NOPE");
+        assert!(to_json(&other).is_err());
+        let truncated = row();
+        assert!(to_json(&truncated[..truncated.len() / 2]).is_err());
+        assert!(to_json(b"WROW").is_err());
+    }
+}
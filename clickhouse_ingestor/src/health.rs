@@ -0,0 +1,220 @@
+//! Liveness/readiness probes and a few Prometheus gauges over plain HTTP
+//! (`HEALTH_ADDR`, e.g. `0.0.0.0:8080`):
+//!
+//! - `/healthz`: 200 while the process runs
+//! - `/readyz`: 200 while the NATS connection is up and the ClickHouse circuit
+//!   breaker (if any) is closed, 503 otherwise, so the pod is taken out of service
+//!   during outages instead of idling silently
+//! - `/metrics`: Prometheus text format
+//!
+//! Consumer lag, when known: `ingestor_consumer_lag_messages` is the JetStream
+//! consumer's unacked backlog (pending + delivered-but-unacked, polled from the
+//! server), `ingestor_consumer_lag_seconds` the age of the newest message when it
+//! was read, from its JetStream publish time or, on core NATS, the plugin's
+//! `Published-At` header (`publish_timestamp_header`). Both are omitted until seen.
+
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicU8, AtomicU64, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use anyhow::{Context, Result};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+
+use crate::breaker::State;
+
+#[derive(Default)]
+pub struct Health {
+    nats_connected: AtomicBool,
+    nats_disconnects: AtomicU64,
+    messages: AtomicU64,
+    // breaker::State as 0 closed / 1 open / 2 half-open
+    breaker: AtomicU8,
+    // consumer lag; 0 = not known (yet), otherwise the value + 1
+    lag_messages: AtomicU64,
+    lag_ms: AtomicU64,
+}
+
+impl Health {
+    /// Track connection state from the async-nats event callback. The client
+    /// reconnects on its own after `Disconnected`; `Connected` follows when it does.
+    pub fn on_nats_event(&self, event: &async_nats::Event) {
+        match event {
+            async_nats::Event::Connected => {
+                if !self.nats_connected.swap(true, Ordering::SeqCst) {
+                    println!("NATS connected");
+                }
+            }
+            async_nats::Event::Disconnected => {
+                if self.nats_connected.swap(false, Ordering::SeqCst) {
+                    self.nats_disconnects.fetch_add(1, Ordering::Relaxed);
+                    eprintln!("NATS disconnected; reconnecting (readyz now failing)");
+                }
+            }
+            other => eprintln!("NATS event: {other}"),
+        }
+    }
+
+    pub fn set_connected(&self) {
+        self.nats_connected.store(true, Ordering::SeqCst);
+    }
+
+    pub fn set_breaker(&self, state: State) {
+        let v = match state {
+            State::Closed => 0,
+            State::Open => 1,
+            State::HalfOpen => 2,
+        };
+        self.breaker.store(v, Ordering::Relaxed);
+    }
+
+    pub fn on_message(&self) {
+        self.messages.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// The JetStream consumer's backlog: messages not yet acked.
+    pub fn set_lag_messages(&self, pending: u64) {
+        self.lag_messages.store(pending.saturating_add(1), Ordering::Relaxed);
+    }
+
+    /// A message published at `published_ms` (unix milliseconds) was just read.
+    pub fn observe_published(&self, published_ms: u64) {
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_millis() as u64;
+        self.lag_ms.store(now.saturating_sub(published_ms).saturating_add(1), Ordering::Relaxed);
+    }
+
+    /// (messages, seconds) behind, each when known, for the stats log.
+    pub fn lag(&self) -> (Option<u64>, Option<f64>) {
+        let known = |v: u64| v.checked_sub(1);
+        (
+            known(self.lag_messages.load(Ordering::Relaxed)),
+            known(self.lag_ms.load(Ordering::Relaxed)).map(|ms| ms as f64 / 1000.0),
+        )
+    }
+
+    /// Serve the probes on `addr` in the background.
+    pub async fn serve(self: Arc<Self>, addr: &str) -> Result<()> {
+        let listener = TcpListener::bind(addr).await.with_context(|| format!("bind HEALTH_ADDR {addr}"))?;
+        println!("Health endpoints on http://{addr} (/healthz, /readyz, /metrics)");
+        tokio::spawn(async move {
+            loop {
+                match listener.accept().await {
+                    Ok((stream, _)) => {
+                        let health = self.clone();
+                        tokio::spawn(async move { health.respond(stream).await });
+                    }
+                    Err(e) => eprintln!("health accept failed: {e}"),
+                }
+            }
+        });
+        Ok(())
+    }
+
+    async fn respond(&self, mut stream: TcpStream) {
+        // only the request line matters; 1 KiB is plenty for a probe
+        let mut buf = [0u8; 1024];
+        let n = match stream.read(&mut buf).await {
+            Ok(n) => n,
+            Err(_) => return,
+        };
+        let request = String::from_utf8_lossy(&buf[..n]);
+        let path = request.split_whitespace().nth(1).unwrap_or("/");
+        let connected = self.nats_connected.load(Ordering::SeqCst);
+        let breaker_closed = self.breaker.load(Ordering::Relaxed) == 0;
+        let (status, body) = match path {
+            "/healthz" => ("200 OK", "ok\n".to_string()),
+            "/readyz" if !connected => ("503 Service Unavailable", "NATS disconnected\n".to_string()),
+            "/readyz" if !breaker_closed => ("503 Service Unavailable", "ClickHouse circuit breaker open\n".to_string()),
+            "/readyz" => ("200 OK", "ready\n".to_string()),
+            "/metrics" => ("200 OK", self.metrics(connected)),
+            _ => ("404 Not Found", "not found\n".to_string()),
+        };
+        let response = format!(
+            "HTTP/1.1 {status}\r\nContent-Type: text/plain; charset=utf-8\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+            body.len()
+        );
+        let _ = stream.write_all(response.as_bytes()).await;
+    }
+
+    fn metrics(&self, connected: bool) -> String {
+        let mut out = format!(
+            "# TYPE ingestor_nats_connected gauge\ningestor_nats_connected {}\n\
+             # TYPE ingestor_nats_disconnects_total counter\ningestor_nats_disconnects_total {}\n\
+             # TYPE ingestor_messages_received_total counter\ningestor_messages_received_total {}\n\
+             # TYPE ingestor_clickhouse_breaker_state gauge\ningestor_clickhouse_breaker_state {}\n",
+            u8::from(connected),
+            self.nats_disconnects.load(Ordering::Relaxed),
+            self.messages.load(Ordering::Relaxed),
+            self.breaker.load(Ordering::Relaxed),
+        );
+        let (messages, seconds) = self.lag();
+        if let Some(messages) = messages {
+            out.push_str(&format!("# TYPE ingestor_consumer_lag_messages gauge\ningestor_consumer_lag_messages {messages}\n"));
+        }
+        if let Some(seconds) = seconds {
+            out.push_str(&format!("# TYPE ingestor_consumer_lag_seconds gauge\ningestor_consumer_lag_seconds {seconds:.3}\n"));
+        }
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // one HTTP probe of `path` answered by `health`, as the status line
+    async fn probe(health: &Health, path: &str) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let mut client = TcpStream::connect(listener.local_addr().unwrap()).await.unwrap();
+        let (server, _) = listener.accept().await.unwrap();
+        client.write_all(format!("GET {path} HTTP/1.1\r\n\r\n").as_bytes()).await.unwrap();
+        health.respond(server).await;
+        let mut response = String::new();
+        client.read_to_string(&mut response).await.unwrap();
+        response.lines().next().unwrap_or_default().to_string()
+    }
+
+    #[tokio::test]
+    async fn disconnect_fails_readyz_until_reconnected() {
+        let health = Health::default();
+        health.set_connected();
+        assert_eq!(probe(&health, "/readyz").await, "HTTP/1.1 200 OK");
+
+        health.on_nats_event(&async_nats::Event::Disconnected);
+        // a repeated event while still down is not a second disconnect
+        health.on_nats_event(&async_nats::Event::Disconnected);
+        assert_eq!(probe(&health, "/readyz").await, "HTTP/1.1 503 Service Unavailable");
+        assert_eq!(probe(&health, "/healthz").await, "HTTP/1.1 200 OK");
+        let metrics = health.metrics(health.nats_connected.load(Ordering::SeqCst));
+        assert!(metrics.contains("ingestor_nats_connected 0\n"));
+        assert!(metrics.contains("ingestor_nats_disconnects_total 1\n"));
+
+        health.on_nats_event(&async_nats::Event::Connected);
+        assert_eq!(probe(&health, "/readyz").await, "HTTP/1.1 200 OK");
+    }
+
+    #[test]
+    fn lag_gauges_appear_once_known() {
+        let health = Health::default();
+        // nothing polled or read yet: no lag lines at all rather than a misleading 0
+        assert_eq!(health.lag(), (None, None));
+        assert!(!health.metrics(true).contains("ingestor_consumer_lag"));
+
+        health.set_lag_messages(0);
+        assert!(health.metrics(true).contains("\ningestor_consumer_lag_messages 0\n"));
+        health.set_lag_messages(42);
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_millis() as u64;
+        health.observe_published(now - 2_500);
+        let metrics = health.metrics(true);
+        assert!(metrics.contains("# TYPE ingestor_consumer_lag_messages gauge\ningestor_consumer_lag_messages 42\n"), "{metrics}");
+        let seconds = metrics
+            .lines()
+            .find_map(|l| l.strip_prefix("ingestor_consumer_lag_seconds "))
+            .and_then(|v| v.parse::<f64>().ok())
+            .unwrap();
+        assert!((2.5..3.5).contains(&seconds), "{metrics}");
+        // a publish time ahead of our clock is no lag, not an underflow
+        health.observe_published(now + 60_000);
+        assert_eq!(health.lag().1, Some(0.0));
+    }
+}
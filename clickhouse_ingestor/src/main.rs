@@ -1,9 +1,40 @@
 use anyhow::{Context, Result};
+use async_nats::jetstream::message::Acker;
 use futures_util::StreamExt; // for sub.next().await
 use reqwest::Client;
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
 use std::env;
-use std::time::Duration;
-use tokio::time::{interval, MissedTickBehavior};
+use std::io::Write as _;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::time::sleep_until;
+
+mod allow;
+mod borsh_row;
+mod breaker;
+mod coalesce;
+mod dedup;
+mod events;
+mod flatbuffer_row;
+mod health;
+mod otel;
+mod parquet_out;
+mod row;
+mod rowbinary;
+mod shard;
+mod tables;
+mod verify;
+
+use allow::{SubjectAllow, SubjectTables};
+use breaker::Breaker;
+use coalesce::Coalescer;
+use dedup::SeenSet;
+use events::PluginEvents;
+use health::Health;
+use parquet_out::ParquetOutput;
+use tables::{TableSpec, Transform};
+use verify::Verifier;
 
 #[tokio::main]
 async fn main() -> Result<()> {
@@ -15,94 +46,1656 @@ async fn main() -> Result<()> {
     let ch_pass    = env::var("CH_PASS").unwrap_or_else(|_| "dev".into());
     let ch_db      = env::var("CH_DB").unwrap_or_else(|_| "default".into());
     let ch_table   = env::var("CH_TABLE").unwrap_or_else(|_| "wallet_account_updates".into());
+    // several target tables with per-table transforms (see tables.rs); replaces CH_TABLE
+    let ch_tables  = env::var("CH_TABLES").ok().filter(|s| !s.is_empty());
     let batch_size = env::var("BATCH_SIZE").ok().and_then(|s| s.parse().ok()).unwrap_or(200usize);
+    // tune the batch size (starting at BATCH_SIZE) so inserts take about this long;
+    // unset = fixed BATCH_SIZE. Bounds default to BATCH_SIZE/10 and BATCH_SIZE*10.
+    let target_insert = env::var("TARGET_INSERT_MS").ok().and_then(|s| s.parse().ok()).filter(|&ms: &u64| ms > 0);
+    let batch_min  = env::var("BATCH_SIZE_MIN").ok().and_then(|s| s.parse().ok()).unwrap_or((batch_size / 10).max(1));
+    let batch_max  = env::var("BATCH_SIZE_MAX").ok().and_then(|s| s.parse().ok()).unwrap_or(batch_size.saturating_mul(10));
     let flush_ms   = env::var("FLUSH_MS").ok().and_then(|s| s.parse().ok()).unwrap_or(500u64);
+    // adaptive flush bounds; both default to FLUSH_MS (fixed interval)
+    let flush_min  = env::var("FLUSH_MS_MIN").ok().and_then(|s| s.parse().ok()).unwrap_or(flush_ms);
+    let flush_max  = env::var("FLUSH_MS_MAX").ok().and_then(|s| s.parse().ok()).unwrap_or(flush_ms);
+    let nats_tls   = env::var("NATS_TLS").map(|s| s == "1" || s.eq_ignore_ascii_case("true")).unwrap_or(false);
+    let nats_ca    = env::var("NATS_TLS_CA").ok();
+    // shown in the server's connz/monitoring, so several ingestors can be told apart
+    let nats_name  = env::var("NATS_CONNECTION_NAME").ok().filter(|s| !s.is_empty())
+        .unwrap_or_else(|| format!("clickhouse-ingestor-{}", hostname()));
+    let dlq_subj   = env::var("DLQ_SUBJECT").ok().filter(|s| !s.is_empty());
+    // consume from this JetStream stream through a durable pull consumer instead of plain
+    // subscriptions; messages are acked only once their rows reached the output
+    let js_stream  = env::var("NATS_STREAM").ok().filter(|s| !s.is_empty());
+    let js_consumer = env::var("NATS_CONSUMER").unwrap_or_else(|_| "clickhouse_ingestor".into());
+    // unacked messages the server lets us hold; keep it >= BATCH_SIZE or batches only
+    // fill on the timer. Applies when the consumer is created, not to an existing one.
+    let max_ack_pending = env::var("NATS_MAX_ACK_PENDING").ok().and_then(|s| s.parse().ok()).unwrap_or(1000i64);
+    let fetch_batch = env::var("NATS_FETCH_BATCH").ok().and_then(|s| s.parse().ok()).unwrap_or(batch_size);
+    // messages the NATS reader task may run ahead of the batching/flushing loop
+    let read_queue = env::var("NATS_READ_QUEUE").ok().and_then(|s| s.parse().ok()).unwrap_or(1000usize).max(1);
+    // keep only the latest row per pubkey within each window; 0/unset = off
+    let coalesce   = env::var("COALESCE_MS").ok().and_then(|s| s.parse().ok()).filter(|&ms: &u64| ms > 0);
+    // drop rows whose (pubkey, slot, write_ver) this process already saw, remembering
+    // up to DEDUP_MAX keys; 0/unset = off
+    let dedup_max  = env::var("DEDUP_MAX").ok().and_then(|s| s.parse().ok()).filter(|&n: &usize| n > 0);
+    // serve /healthz, /readyz and /metrics here; unset = off
+    let health_addr = env::var("HEALTH_ADDR").ok().filter(|s| !s.is_empty());
+    // append every received payload (one line each) to this file, for the replay bin
+    let record_path = env::var("RECORD_PATH").ok().filter(|s| !s.is_empty());
+    // "clickhouse" (default) or "parquet"
+    let sink       = env::var("SINK").unwrap_or_else(|_| "clickhouse".into());
+    // "JSONEachRow" (default) or "RowBinary" (see rowbinary.rs for the required column types)
+    let ch_format  = env::var("CH_FORMAT").unwrap_or_else(|_| "JSONEachRow".into());
+    // extra ClickHouse settings for every insert, as `name=value&name=value`. Useful ones:
+    //   input_format_skip_unknown_fields=1   new Row fields don't fail inserts (schema evolution)
+    //   max_insert_block_size=1048576        rows per block ClickHouse forms from one insert
+    //   async_insert=1&wait_for_async_insert=1  let the server batch small inserts
+    //   insert_quorum=2                      replicated tables: ack only after 2 replicas
+    let ch_settings = env::var("CH_SETTINGS").ok().filter(|s| !s.is_empty());
+    // retry failed inserts (transport errors, 5xx) this many times; each batch carries an
+    // insert_deduplication_token so a retry of an insert that did land is dropped by ClickHouse
+    let ch_retries = env::var("CH_INSERT_RETRIES").ok().and_then(|s| s.parse().ok()).unwrap_or(2u32);
+    // per-insert timeout: CH_TIMEOUT_MS plus CH_TIMEOUT_PER_MB_MS for every MiB of body.
+    // A timed-out insert is retried (and deduplicated if it did land), so too short a value
+    // multiplies the load. Row-only batches are small and 10s is plenty; with include_data
+    // batches of several MiB, something like CH_TIMEOUT_PER_MB_MS=2000 keeps big inserts alive.
+    let ch_timeout = env::var("CH_TIMEOUT_MS").ok().and_then(|s| s.parse().ok()).unwrap_or(10_000u64);
+    let ch_timeout_per_mb = env::var("CH_TIMEOUT_PER_MB_MS").ok().and_then(|s| s.parse().ok()).unwrap_or(0u64);
+    // "json" (default), "borsh" or "flatbuffers", matching the plugin's payload_format
+    let payload_format = env::var("PAYLOAD_FORMAT").unwrap_or_else(|_| "json".into());
+    // forward NATS payloads into the insert body untouched: no per-row JSON check, no
+    // dedup/coalescing. Faster, but one malformed row fails its whole batch in
+    // ClickHouse (dead-lettered as a unit) unless CH_SETTINGS allows errors, e.g.
+    // input_format_allow_errors_num=10. BATCH_SIZE then counts messages, not rows.
+    let passthrough = env::var("CH_PASSTHROUGH").map(|s| s == "1" || s.eq_ignore_ascii_case("true")).unwrap_or(false);
+    // circuit breaker (see breaker.rs): open after this many consecutive failed flushes; 0 = off
+    let cb_failures = env::var("CB_FAILURES").ok().and_then(|s| s.parse().ok()).unwrap_or(0u32);
+    let cb_cooldown = env::var("CB_COOLDOWN_MS").ok().and_then(|s| s.parse().ok()).unwrap_or(30_000u64);
+    // plugin events (events_subject): rooted slots feed CH_CHECKPOINT_TABLE, slot stats
+    // CH_SLOT_STATS_TABLE, see events.rs
+    let events_subj = env::var("NATS_EVENTS_SUBJECT").ok().filter(|s| !s.is_empty());
+    let checkpoint_table = env::var("CH_CHECKPOINT_TABLE").ok().filter(|s| !s.is_empty());
+    let slot_stats_table = env::var("CH_SLOT_STATS_TABLE").ok().filter(|s| !s.is_empty());
+    // only insert from subjects matching these patterns, see allow.rs
+    let subject_allow = env::var("NATS_SUBJECT_ALLOW").ok().filter(|s| !s.is_empty());
+    // send rows from subjects matching a pattern to that pattern's table, see allow.rs
+    let subject_tables = env::var("CH_SUBJECT_TABLES").ok().filter(|s| !s.is_empty());
+    // insert each row straight into its shard's local table instead of a Distributed
+    // table on CH_HTTP: the shards' HTTP endpoints, in cluster order, see shard.rs
+    let ch_shards  = env::var("CH_SHARDS").ok().filter(|s| !s.is_empty());
+    // export ClickHouse insert spans for the plugin's traced messages, see otel.rs
+    let tracing_on = env::var("TRACING_ENABLED").map(|s| s == "1" || s.eq_ignore_ascii_case("true")).unwrap_or(false);
 
     println!(
-        "Ingestor up. NATS={} subject={} → ClickHouse={}/{}.{} (batch={}, flush={}ms)",
-        nats_url, subject, ch_http, ch_db, ch_table, batch_size, flush_ms
+        "Ingestor up. NATS={} subject={} → ClickHouse={}/{}.{} (batch={}, flush={}ms, sink={})",
+        nats_url, subject, ch_http, ch_db, ch_table, batch_size, flush_ms, sink
     );
+    let _tracing = if tracing_on {
+        println!("Exporting ClickHouse insert spans over OTLP/HTTP");
+        Some(otel::Tracing::init()?)
+    } else {
+        None
+    };
 
     // -------- connections --------
-    let nc = async_nats::connect(&nats_url).await
-        .with_context(|| format!("connect NATS {nats_url}"))?;
-    let mut sub = nc.subscribe(subject.clone()).await
-        .with_context(|| format!("subscribe {subject}"))?;
+    let health = Arc::new(Health::default());
+    let events = health.clone();
+    let mut nats_opts = async_nats::ConnectOptions::new().event_callback(move |event| {
+        let events = events.clone();
+        async move { events.on_nats_event(&event) }
+    }).name(&nats_name);
+    if nats_tls {
+        nats_opts = nats_opts.require_tls(true);
+    }
+    if let Some(ca) = &nats_ca {
+        nats_opts = nats_opts.add_root_certificates(ca.into());
+    }
+    let nc = nats_opts.connect(&nats_url).await
+        .with_context(|| format!("connect NATS {nats_url} (tls={nats_tls}, ca={nats_ca:?})"))?;
+    health.set_connected();
+    println!("Connected to NATS {nats_url} as {nats_name}");
+    if let Some(addr) = &health_addr {
+        health.clone().serve(addr).await?;
+    }
+    // NATS_SUBJECT may list several subjects (comma-separated) and/or wildcards
+    // (e.g. "WALLET.>"); all subscriptions feed the same batch.
+    let mut subjects: Vec<String> = subject.split(',').map(str::trim).filter(|s| !s.is_empty()).map(String::from).collect();
+    anyhow::ensure!(!subjects.is_empty(), "NATS_SUBJECT lists no subjects");
+    subjects.extend(events_subj.clone());
+    let sub: Inbound = match &js_stream {
+        None => subscribe_all(&nc, &subjects).await?,
+        Some(stream) => {
+            println!(
+                "Consuming JetStream stream {stream} as {js_consumer} (max_ack_pending={max_ack_pending}, fetch_batch={fetch_batch})"
+            );
+            let js = async_nats::jetstream::new(nc.clone());
+            let consumer = js.get_stream(stream).await
+                .with_context(|| format!("get JetStream stream {stream}"))?
+                .get_or_create_consumer(&js_consumer, async_nats::jetstream::consumer::pull::Config {
+                    durable_name: Some(js_consumer.clone()),
+                    filter_subjects: subjects.clone(),
+                    ack_policy: async_nats::jetstream::consumer::AckPolicy::Explicit,
+                    max_ack_pending,
+                    ..Default::default()
+                })
+                .await
+                .with_context(|| format!("get or create consumer {js_consumer} on {stream}"))?;
+            let messages = consumer.stream().max_messages_per_batch(fetch_batch).messages().await
+                .with_context(|| format!("pull from consumer {js_consumer}"))?;
+            let lag = health.clone();
+            let mut polled = consumer.clone();
+            tokio::spawn(async move {
+                let mut every = tokio::time::interval(LAG_POLL_EVERY);
+                loop {
+                    every.tick().await;
+                    poll_lag(&mut polled, &lag).await;
+                }
+            });
+            let published = health.clone();
+            Box::pin(messages.filter_map(move |res| {
+                let published = published.clone();
+                async move {
+                    match res {
+                        Ok(msg) => {
+                            if let Ok(info) = msg.info() {
+                                published.observe_published((info.published.unix_timestamp_nanos() / 1_000_000) as u64);
+                            }
+                            let (msg, acker) = msg.split();
+                            Some((msg, Some(acker)))
+                        }
+                        Err(e) => {
+                            eprintln!("JetStream pull error: {e}");
+                            None
+                        }
+                    }
+                }
+            }))
+        }
+    };
+    let mut inbound = spawn_reader(sub, read_queue, health.clone());
+    // JetStream messages received since the last completed write; acked after it
+    let mut unacked: Vec<Acker> = Vec::new();
+    // messages received per concrete subject, logged every STATS_EVERY
+    let mut per_subject: BTreeMap<String, u64> = BTreeMap::new();
+    let mut last_stats = Instant::now();
+    let mut dlq = dlq_subj.map(|subject| {
+        println!("Dead-lettering unparseable/failed rows to {subject}");
+        DeadLetter { nc: nc.clone(), subject, count: 0 }
+    });
 
+    // the timeout is set per insert, see InsertTimeout
     let client = Client::builder()
-        .timeout(Duration::from_secs(10))
         .build()
         .context("build reqwest client")?;
 
-    let insert_url = format!(
-        "{}/?query=INSERT%20INTO%20{}.{}%20FORMAT%20JSONEachRow",
-        ch_http, ch_db, ch_table
-    );
+    let format = match ch_format.as_str() {
+        "JSONEachRow" => InsertFormat::JsonEachRow,
+        "RowBinary" => InsertFormat::RowBinary,
+        other => anyhow::bail!("unknown CH_FORMAT {other:?} (expected JSONEachRow or RowBinary)"),
+    };
+    // binary payloads are decoded to JSON rows as they arrive
+    let binary_rows: Option<(&str, DecodeRow)> = match payload_format.as_str() {
+        "json" => None,
+        "borsh" => Some(("Borsh", borsh_row::to_json)),
+        "flatbuffers" => Some(("FlatBuffers", flatbuffer_row::to_json)),
+        other => anyhow::bail!("unknown PAYLOAD_FORMAT {other:?} (expected json, borsh or flatbuffers)"),
+    };
+    if let Some((name, _)) = binary_rows {
+        println!("Decoding {name} rows from NATS (PAYLOAD_FORMAT={payload_format})");
+    }
+    let specs = match &ch_tables {
+        Some(raw) => tables::parse(raw)?,
+        None => vec![TableSpec { name: ch_table, transform: Transform::Raw }],
+    };
+    let settings = ch_settings.as_deref().map(settings_query).transpose()?;
+    if let Some(settings) = &settings {
+        println!("ClickHouse insert settings: {settings}");
+    }
+    let endpoints = match &ch_shards {
+        Some(raw) => {
+            anyhow::ensure!(sink == "clickhouse", "CH_SHARDS needs SINK=clickhouse");
+            let shards = shard::parse(raw)?;
+            println!("Sharding rows by sipHash64(pubkey) over {} shard(s): {}", shards.len(), shards.join(", "));
+            shards
+        }
+        None => vec![ch_http.clone()],
+    };
+    let target = |spec: TableSpec| {
+        let mut insert_urls = Vec::with_capacity(endpoints.len());
+        for endpoint in &endpoints {
+            let mut insert_url = match format {
+                InsertFormat::JsonEachRow => format!(
+                    "{}/?query=INSERT%20INTO%20{}.{}%20FORMAT%20JSONEachRow",
+                    endpoint, ch_db, spec.name
+                ),
+                InsertFormat::RowBinary => format!(
+                    "{}/?query=INSERT%20INTO%20{}.{}%20({})%20FORMAT%20RowBinary",
+                    endpoint, ch_db, spec.name, url_escape(rowbinary::COLUMNS)
+                ),
+            };
+            if let Some(settings) = &settings {
+                insert_url.push('&');
+                insert_url.push_str(settings);
+            }
+            insert_urls.push(insert_url);
+        }
+        Target { table: spec.name, transform: spec.transform, insert_urls }
+    };
+    let mut targets = Vec::with_capacity(specs.len());
+    for spec in specs {
+        if ch_tables.is_some() {
+            println!("Inserting into {}.{} ({:?})", ch_db, spec.name, spec.transform);
+        }
+        targets.push(target(spec));
+    }
+    let (routes, route_targets) = match &subject_tables {
+        Some(raw) => {
+            anyhow::ensure!(sink == "clickhouse", "CH_SUBJECT_TABLES needs SINK=clickhouse");
+            // the coalescing window holds rows without knowing their subject
+            anyhow::ensure!(coalesce.is_none(), "CH_SUBJECT_TABLES can't be combined with COALESCE_MS");
+            let (routes, specs) = SubjectTables::parse(raw)?;
+            println!("Routing subjects to tables: {raw}");
+            (Some(routes), specs.into_iter().map(target).collect())
+        }
+        None => (None, Vec::new()),
+    };
+    // rows for each CH_SUBJECT_TABLES route, flushed alongside buf
+    let mut routed: Vec<Vec<String>> = vec![Vec::new(); route_targets.len()];
+
+    if passthrough {
+        anyhow::ensure!(
+            sink == "clickhouse" && matches!(format, InsertFormat::JsonEachRow),
+            "CH_PASSTHROUGH needs SINK=clickhouse and CH_FORMAT=JSONEachRow"
+        );
+        anyhow::ensure!(
+            coalesce.is_none() && dedup_max.is_none(),
+            "CH_PASSTHROUGH can't be combined with COALESCE_MS or DEDUP_MAX (both parse rows)"
+        );
+        anyhow::ensure!(
+            targets.iter().chain(&route_targets).all(|t| t.transform == Transform::Raw),
+            "CH_PASSTHROUGH needs every CH_TABLES and CH_SUBJECT_TABLES entry to be raw"
+        );
+        // the shard comes from each row's pubkey
+        anyhow::ensure!(ch_shards.is_none(), "CH_PASSTHROUGH can't be combined with CH_SHARDS");
+        println!("Passing NATS payloads straight through to ClickHouse (no row validation)");
+    }
+
+    let mut plugin_events = None;
+    for (var, table) in [("CH_CHECKPOINT_TABLE", &checkpoint_table), ("CH_SLOT_STATS_TABLE", &slot_stats_table)] {
+        let Some(table) = table else { continue };
+        anyhow::ensure!(sink == "clickhouse", "{var} needs SINK=clickhouse");
+        anyhow::ensure!(
+            table.bytes().all(|b| b.is_ascii_alphanumeric() || b == b'_'),
+            "{var} {table:?} is not a plain table name"
+        );
+        let Some(events) = &events_subj else { anyhow::bail!("{var} needs NATS_EVENTS_SUBJECT") };
+        println!("Writing plugin events from {events} into {ch_db}.{table} ({var})");
+        plugin_events = Some(PluginEvents::new(&ch_http, &ch_db, checkpoint_table.as_deref(), slot_stats_table.as_deref()));
+    }
+
+    let mut output = match sink.as_str() {
+        "clickhouse" => Output::ClickHouse(ClickHouse {
+            client, targets, routes: route_targets, format, user: ch_user, pass: ch_pass, retries: ch_retries, keep_failed: cb_failures > 0,
+            at_least_once: js_stream.is_some(),
+            timeout: InsertTimeout { base: Duration::from_millis(ch_timeout), per_mb: Duration::from_millis(ch_timeout_per_mb) },
+        }),
+        "parquet" => Output::Parquet(Box::new(ParquetOutput::from_env().await?)),
+        other => anyhow::bail!("unknown SINK {other:?} (expected clickhouse or parquet)"),
+    };
 
     // -------- batching --------
     let mut buf: Vec<String> = Vec::with_capacity(batch_size);
-    let mut ticker = interval(Duration::from_millis(flush_ms));
-    // if your code stalls, prevent “burst” catch-up; or keep default
-    ticker.set_missed_tick_behavior(MissedTickBehavior::Skip);
+    let mut flush_every = FlushInterval::new(flush_ms, flush_min, flush_max);
+    let mut batch = BatchSize::new(batch_size, batch_min, batch_max, target_insert);
+    let mut next_tick = tokio::time::Instant::now() + flush_every.current;
+    // trace context of the first traced message in the pending batch (plugin tracing_enabled)
+    let mut batch_trace: Option<opentelemetry::Context> = None;
+    let verifier = Verifier::from_env()?;
+    if let Some(v) = &verifier {
+        println!("Verifying message signatures ({})", v.describe());
+    }
+    let mut unverified = 0u64;
+    let mut allow = subject_allow.as_deref().map(SubjectAllow::parse).transpose()?;
+    if let Some(allow) = &allow {
+        println!("Inserting only from subjects matching {}", allow.describe());
+    }
+    let mut seen = dedup_max.map(|n| {
+        println!("Dropping in-process duplicates (remembering {n} row keys)");
+        SeenSet::new(n)
+    });
+    let mut recorder = match &record_path {
+        Some(path) => {
+            println!("Recording received messages to {path}");
+            Some(Recorder::open(path)?)
+        }
+        None => None,
+    };
+    let mut breaker = (cb_failures > 0).then(|| {
+        println!("Circuit breaker: open after {cb_failures} failed flushes, cool down {cb_cooldown}ms");
+        Breaker::new(cb_failures, Duration::from_millis(cb_cooldown))
+    });
+    let mut coalescer = coalesce.map(|ms| {
+        println!("Coalescing rows per pubkey over {ms}ms windows");
+        Coalescer::new(Duration::from_millis(ms))
+    });
 
     loop {
         tokio::select! {
-            maybe_msg = sub.next() => {
-                if let Some(msg) = maybe_msg {
-                    // payload is UTF-8 JSON from your plugin
-                    match String::from_utf8(msg.payload.to_vec()) {
+            maybe_msg = inbound.recv(), if breaker.as_ref().is_none_or(Breaker::allows_consume) => {
+                if let Some((mut msg, acker)) = maybe_msg {
+                    unacked.extend(acker);
+                    *per_subject.entry(msg.subject.to_string()).or_default() += 1;
+                    health.on_message();
+                    // from here on (recording, signature checks, decoding) payloads are plain
+                    if let Err(reason) = decompress(&mut msg) {
+                        eprintln!("dropping message on {}: {reason}", msg.subject);
+                        if let Some(dlq) = dlq.as_mut() {
+                            dlq.send(msg.payload.to_vec(), &reason).await;
+                        }
+                        continue;
+                    }
+                    if let Some(r) = recorder.as_mut() {
+                        r.record(&msg.payload);
+                    }
+                    if let Some(allow) = allow.as_mut()
+                        && events_subj.as_deref() != Some(msg.subject.as_str())
+                        && !allow.check(&msg.subject)
+                    {
+                        if allow.dropped().is_power_of_two() {
+                            eprintln!("dropping message on unexpected subject {} ({} so far)", msg.subject, allow.dropped());
+                        }
+                        if let Some(dlq) = dlq.as_mut() {
+                            dlq.send(msg.payload.to_vec(), "subject not in NATS_SUBJECT_ALLOW").await;
+                        }
+                        continue;
+                    }
+                    if events_subj.as_deref() == Some(msg.subject.as_str()) {
+                        if let Some(e) = plugin_events.as_mut() {
+                            e.on_event(&msg.payload);
+                        }
+                        continue;
+                    }
+                    let route = routes.as_ref().and_then(|r| r.route(&msg.subject));
+                    if let Some(v) = &verifier
+                        && let Err(reason) = v.check(msg.headers.as_ref(), &msg.payload)
+                    {
+                        unverified += 1;
+                        if unverified.is_power_of_two() {
+                            eprintln!("dropping unverified message on {}: {reason} ({unverified} so far)", msg.subject);
+                        }
+                        if let Some(dlq) = dlq.as_mut() {
+                            dlq.send(msg.payload.to_vec(), reason).await;
+                        }
+                        continue;
+                    }
+                    if batch_trace.is_none() {
+                        batch_trace = msg.headers.as_ref().and_then(otel::parent);
+                    }
+                    // payload is UTF-8 JSON from your plugin (or Borsh / FlatBuffers, decoded to JSON here)
+                    let text = if let Some((name, to_json)) = binary_rows {
+                        to_json(&msg.payload).map_err(|e| format!("invalid {name} row: {e:#}"))
+                    } else {
+                        String::from_utf8(msg.payload.to_vec()).map_err(|e| format!("non-utf8 payload: {e}"))
+                    };
+                    match text {
+                        // the plugin's payloads are already JSONEachRow lines (batches newline-joined)
+                        Ok(s) if passthrough => {
+                            let dest = match route { Some(i) => &mut routed[i], None => &mut buf };
+                            dest.push(s);
+                            if dest.len() >= batch.current && breaker.as_ref().is_none_or(Breaker::allows_eager_flush) {
+                                let (rows, started) = (dest.len(), Instant::now());
+                                let ok = output.write(route, dest, batch_trace.take(), dlq.as_mut()).await?;
+                                record_flush(breaker.as_mut(), &health, ok);
+                                batch.on_insert(rows, started.elapsed(), ok);
+                                flush_every.on_full_batch();
+                                if rows_settled(&buf, &routed, coalescer.as_ref()) {
+                                    ack_all(&mut unacked).await;
+                                }
+                            }
+                        }
                         Ok(s) => {
-                            // Optional: validate JSON is minimally correct
-                            // serde_json::from_str::<serde_json::Value>(&s).ok();
-                            buf.push(s);
-                            if buf.len() >= batch_size {
-                                flush(&client, &insert_url, &ch_user, &ch_pass, &mut buf).await?;
+                            let dest = match route { Some(i) => &mut routed[i], None => &mut buf };
+                            let before = dest.len();
+                            let invalid = push_rows(dest, &s);
+                            if let Some(seen) = seen.as_mut() {
+                                seen.filter_from(dest, before);
+                            }
+                            if !invalid.is_empty() {
+                                eprintln!("dropping {} non-JSON row(s) from NATS", invalid.len());
+                                if let Some(dlq) = dlq.as_mut() {
+                                    dlq.send_all(&invalid, "invalid JSON").await;
+                                }
+                            }
+                            // (never with routes, so dest is buf)
+                            if let Some(c) = coalescer.as_mut() {
+                                c.absorb(dest);
+                                if c.window_elapsed() {
+                                    c.drain_into(dest);
+                                }
+                            }
+                            if dest.len() >= batch.current && breaker.as_ref().is_none_or(Breaker::allows_eager_flush) {
+                                let (rows, started) = (dest.len(), Instant::now());
+                                let ok = output.write(route, dest, batch_trace.take(), dlq.as_mut()).await?;
+                                record_flush(breaker.as_mut(), &health, ok);
+                                batch.on_insert(rows, started.elapsed(), ok);
+                                flush_every.on_full_batch();
+                                if rows_settled(&buf, &routed, coalescer.as_ref()) {
+                                    ack_all(&mut unacked).await;
+                                }
+                            }
+                        }
+                        Err(reason) => {
+                            eprintln!("dropping message from NATS: {reason}");
+                            if let Some(dlq) = dlq.as_mut() {
+                                dlq.send(msg.payload.to_vec(), &reason).await;
                             }
                         }
-                        Err(e) => eprintln!("non-utf8 message from NATS: {e:?}"),
                     }
                 } else {
-                    // stream closed
+                    // all subscriptions closed
                     eprintln!("NATS subscription closed; exiting.");
                     break;
                 }
             }
-            _ = ticker.tick() => {
-                if !buf.is_empty() {
-                    flush(&client, &insert_url, &ch_user, &ch_pass, &mut buf).await?;
+            _ = sleep_until(next_tick) => {
+                if let Some(c) = coalescer.as_mut()
+                    && c.window_elapsed()
+                {
+                    c.drain_into(&mut buf);
+                }
+                flush_every.on_tick(buf.len(), batch.current);
+                if !buf.is_empty() && breaker.as_ref().is_none_or(Breaker::allows_flush) {
+                    let (rows, started) = (buf.len(), Instant::now());
+                    let ok = output.write(None, &mut buf, batch_trace.take(), dlq.as_mut()).await?;
+                    record_flush(breaker.as_mut(), &health, ok);
+                    batch.on_insert(rows, started.elapsed(), ok);
+                }
+                for (i, rows) in routed.iter_mut().enumerate() {
+                    if !rows.is_empty() && breaker.as_ref().is_none_or(Breaker::allows_flush) {
+                        let ok = output.write(Some(i), rows, batch_trace.take(), dlq.as_mut()).await?;
+                        record_flush(breaker.as_mut(), &health, ok);
+                    }
+                }
+                if rows_settled(&buf, &routed, coalescer.as_ref()) {
+                    if let (Some(e), Output::ClickHouse(ch)) = (plugin_events.as_mut(), &output) {
+                        e.write(ch).await;
+                    }
+                    ack_all(&mut unacked).await;
+                }
+                next_tick = tokio::time::Instant::now() + flush_every.current;
+                output.tick().await?;
+                if let Some(r) = recorder.as_mut() {
+                    r.flush();
+                }
+                if last_stats.elapsed() >= STATS_EVERY {
+                    println!("messages per subject: {per_subject:?}");
+                    if let Some(seen) = &seen {
+                        println!("duplicates dropped: {}", seen.dropped());
+                    }
+                    if let Some(allow) = &allow {
+                        println!("unexpected-subject messages dropped: {}", allow.dropped());
+                    }
+                    let (messages, seconds) = health.lag();
+                    if messages.is_some() || seconds.is_some() {
+                        println!("consumer lag: {messages:?} message(s), {seconds:?}s");
+                    }
+                    last_stats = Instant::now();
                 }
             }
         }
     }
 
-    Ok(())
+    if let Some(c) = coalescer.as_mut() {
+        c.drain_into(&mut buf);
+    }
+    if !buf.is_empty() && !output.write(None, &mut buf, batch_trace.take(), dlq.as_mut()).await? {
+        eprintln!("final flush failed; {} row(s) not written", buf.len());
+    }
+    for (i, rows) in routed.iter_mut().enumerate() {
+        if !rows.is_empty() && !output.write(Some(i), rows, batch_trace.take(), dlq.as_mut()).await? {
+            eprintln!("final flush failed; {} row(s) not written", rows.len());
+        }
+    }
+    if rows_settled(&buf, &routed, None) {
+        if let (Some(e), Output::ClickHouse(ch)) = (plugin_events.as_mut(), &output) {
+            e.write(ch).await;
+        }
+        ack_all(&mut unacked).await;
+    }
+    println!("messages per subject: {per_subject:?}");
+    if let Some(r) = recorder.as_mut() {
+        r.flush();
+    }
+    output.finish().await
 }
 
-async fn flush(
-    client: &reqwest::Client,
+/// Feed a flush outcome to the breaker (if any) and log/publish state changes.
+fn record_flush(breaker: Option<&mut Breaker>, health: &Health, ok: bool) {
+    let Some(breaker) = breaker else { return };
+    if let Some(state) = breaker.record(ok) {
+        match state {
+            breaker::State::Open => eprintln!(
+                "circuit breaker OPEN after {} failed flushes: pausing NATS consumption",
+                breaker.failures()
+            ),
+            breaker::State::Closed => println!("circuit breaker closed: ClickHouse healthy again"),
+            breaker::State::HalfOpen => {}
+        }
+    }
+    health.set_breaker(breaker.state());
+}
+
+/// Whether every row received so far was written or dead-lettered, so the messages
+/// they came in may be acked: none wait in a buffer or the coalescing window.
+fn rows_settled(buf: &[String], routed: &[Vec<String>], coalescer: Option<&Coalescer>) -> bool {
+    buf.is_empty() && routed.iter().all(Vec::is_empty) && coalescer.is_none_or(Coalescer::is_empty)
+}
+
+/// Incoming messages, with the JetStream acker when consuming from `NATS_STREAM`.
+type Inbound = std::pin::Pin<Box<dyn futures_util::Stream<Item = (async_nats::Message, Option<Acker>)> + Send>>;
+
+/// Core NATS: one subscription per subject, merged into one stream. select_all polls
+/// them round-robin so a busy subject can't starve the others.
+async fn subscribe_all(nc: &async_nats::Client, subjects: &[String]) -> Result<Inbound> {
+    let mut subs = Vec::new();
+    for subj in subjects {
+        let s = nc.subscribe(subj.clone()).await
+            .with_context(|| format!("subscribe {subj}"))?;
+        subs.push(s);
+    }
+    Ok(Box::pin(futures_util::stream::select_all(subs).map(|msg| (msg, None))))
+}
+
+/// Ask the server for the consumer's backlog (pending + delivered-but-unacked).
+async fn poll_lag(consumer: &mut async_nats::jetstream::consumer::PullConsumer, health: &Health) {
+    match consumer.info().await {
+        Ok(info) => health.set_lag_messages(info.num_pending + info.num_ack_pending as u64),
+        Err(e) => eprintln!("JetStream consumer info failed (lag not updated): {e}"),
+    }
+}
+
+/// The reader task only moves messages into a bounded channel of `queue` slots; the
+/// main loop batches and flushes. While a flush is in flight the reader keeps going
+/// until the channel is full, then stops pulling from NATS: core subscriptions buffer
+/// (and eventually drop as a slow consumer) in the client, JetStream just stops fetching.
+fn spawn_reader(
+    mut sub: Inbound,
+    queue: usize,
+    health: Arc<Health>,
+) -> tokio::sync::mpsc::Receiver<(async_nats::Message, Option<Acker>)> {
+    let (inbound_tx, inbound) = tokio::sync::mpsc::channel(queue);
+    tokio::spawn(async move {
+        while let Some(item) = sub.next().await {
+            // core NATS: the plugin's publish_timestamp_header (JetStream is timed above)
+            if let Some(ms) = item.0.headers.as_ref()
+                .and_then(|h| h.get("Published-At"))
+                .and_then(|v| v.as_str().parse().ok())
+            {
+                health.observe_published(ms);
+            }
+            if inbound_tx.send(item).await.is_err() {
+                break;
+            }
+        }
+    });
+    inbound
+}
+
+/// Ack every message whose rows have been written (or dead-lettered). Anything not
+/// acked, e.g. rows of a failed insert still waiting for a retry when the process
+/// ends, is redelivered after the consumer's `ack_wait`: at-least-once, so the target
+/// table should tolerate repeats.
+async fn ack_all(unacked: &mut Vec<Acker>) {
+    for acker in unacked.drain(..) {
+        if let Err(e) = acker.ack().await {
+            eprintln!("JetStream ack failed (message will be redelivered): {e}");
+        }
+    }
+}
+
+/// `RECORD_PATH`: raw NATS payloads appended one per line, in arrival order. Plugin
+/// batches are already newline-joined rows, so the file is plain NDJSON that
+/// `cargo run --bin replay` (plugin crate) republishes. Write errors are logged and
+/// never stop ingestion.
+struct Recorder {
+    out: std::io::BufWriter<std::fs::File>,
+    path: String,
+    errors: u64,
+}
+
+impl Recorder {
+    fn open(path: &str) -> Result<Self> {
+        let file = std::fs::OpenOptions::new().create(true).append(true).open(path)
+            .with_context(|| format!("open RECORD_PATH {path}"))?;
+        Ok(Recorder { out: std::io::BufWriter::new(file), path: path.to_string(), errors: 0 })
+    }
+
+    fn record(&mut self, payload: &[u8]) {
+        let res = self.out.write_all(payload).and_then(|()| self.out.write_all(b"\n"));
+        self.check(res);
+    }
+
+    fn flush(&mut self) {
+        let res = self.out.flush();
+        self.check(res);
+    }
+
+    fn check(&mut self, res: std::io::Result<()>) {
+        if let Err(e) = res {
+            self.errors += 1;
+            if self.errors.is_power_of_two() {
+                eprintln!("recording to {} failed: {e} ({} so far)", self.path, self.errors);
+            }
+        }
+    }
+}
+
+/// Timer-flush interval that adapts to throughput within `[min, max]`: it halves
+/// when batches fill up before the timer (high load, so timer flushes stay
+/// rare and the inserts that do happen are full), and grows by half on ticks that
+/// find the batch under a quarter full (idle, so fewer tiny/empty inserts).
+struct FlushInterval {
+    current: Duration,
+    min: Duration,
+    max: Duration,
+}
+
+impl FlushInterval {
+    fn new(start_ms: u64, min_ms: u64, max_ms: u64) -> Self {
+        let min = Duration::from_millis(min_ms.min(max_ms).max(1));
+        let max = Duration::from_millis(max_ms.max(min_ms).max(1));
+        FlushInterval { current: Duration::from_millis(start_ms).clamp(min, max), min, max }
+    }
+
+    fn on_full_batch(&mut self) {
+        let next = (self.current / 2).max(self.min);
+        if next != self.current {
+            self.current = next;
+            println!("flush interval ↓ {}ms (batches filling up)", next.as_millis());
+        }
+    }
+
+    fn on_tick(&mut self, buffered: usize, batch_size: usize) {
+        if buffered * 4 >= batch_size {
+            return;
+        }
+        let next = (self.current + self.current / 2).min(self.max);
+        if next != self.current {
+            self.current = next;
+            println!("flush interval ↑ {}ms (mostly idle)", next.as_millis());
+        }
+    }
+}
+
+/// `TARGET_INSERT_MS`: the batch size, kept within `[min, max]`. An insert over the
+/// target shrinks it to the rows that would have fit in the target at that speed (but
+/// at most by half per insert); a successful full batch that took under half the
+/// target grows it by a quarter. Without a target it stays put.
+struct BatchSize {
+    current: usize,
+    min: usize,
+    max: usize,
+    target: Option<Duration>,
+}
+
+impl BatchSize {
+    fn new(start: usize, min: usize, max: usize, target_ms: Option<u64>) -> Self {
+        let (min, max) = (min.min(max).max(1), max.max(min).max(1));
+        let target = target_ms.map(Duration::from_millis);
+        if let Some(target) = target {
+            println!("Batch size tuned for {}ms inserts, between {min} and {max} rows", target.as_millis());
+        }
+        BatchSize { current: if target.is_some() { start.clamp(min, max) } else { start }, min, max, target }
+    }
+
+    fn on_insert(&mut self, rows: usize, took: Duration, ok: bool) {
+        let Some(target) = self.target else { return };
+        let next = if took > target {
+            let scaled = (rows as f64 * target.as_secs_f64() / took.as_secs_f64()) as usize;
+            scaled.max(self.current / 2).max(self.min)
+        } else if ok && rows >= self.current && took < target / 2 {
+            (self.current + (self.current / 4).max(1)).min(self.max)
+        } else {
+            return;
+        };
+        if next != self.current {
+            let arrow = if next < self.current { '↓' } else { '↑' };
+            println!(
+                "batch size {arrow} {next} ({rows} rows took {}ms, target {}ms)",
+                took.as_millis(),
+                target.as_millis()
+            );
+            self.current = next;
+        }
+    }
+}
+
+const STATS_EVERY: Duration = Duration::from_secs(60);
+/// How often the JetStream consumer is asked for its backlog.
+const LAG_POLL_EVERY: Duration = Duration::from_secs(10);
+
+// a compressed message may not inflate beyond this (a gzip bomb would otherwise fill memory)
+const MAX_INFLATED_BYTES: u64 = 64 * 1024 * 1024;
+
+/// Undo the plugin's `compress_min_bytes`: a payload marked `Content-Encoding: gzip`
+/// is replaced by its decompressed bytes. Anything else is left alone.
+fn decompress(msg: &mut async_nats::Message) -> std::result::Result<(), String> {
+    let Some(encoding) = msg.headers.as_ref().and_then(|h| h.get("Content-Encoding")) else { return Ok(()) };
+    if encoding.as_str() != "gzip" {
+        return Err(format!("unsupported Content-Encoding {:?}", encoding.as_str()));
+    }
+    use std::io::Read;
+    let mut plain = Vec::new();
+    match flate2::read::GzDecoder::new(&msg.payload[..]).take(MAX_INFLATED_BYTES + 1).read_to_end(&mut plain) {
+        Ok(n) if n as u64 > MAX_INFLATED_BYTES => Err(format!("gzip payload inflates beyond {MAX_INFLATED_BYTES} bytes")),
+        Ok(_) => {
+            msg.payload = plain.into();
+            Ok(())
+        }
+        Err(e) => Err(format!("invalid gzip payload: {e}")),
+    }
+}
+
+/// A message carries one JSON row, or several newline-separated rows when the
+/// plugin batches; each row becomes its own `buf` entry so the JSONEachRow join stays valid.
+/// Lines that aren't JSON are returned instead of buffered, so one bad row can't fail a batch.
+fn push_rows<'a>(buf: &mut Vec<String>, payload: &'a str) -> Vec<&'a str> {
+    let mut invalid = Vec::new();
+    for line in payload.lines().map(str::trim).filter(|line| !line.is_empty()) {
+        if serde_json::from_str::<serde::de::IgnoredAny>(line).is_ok() {
+            buf.push(line.to_owned());
+        } else {
+            invalid.push(line);
+        }
+    }
+    invalid
+}
+
+/// Optional dead-letter publisher: messages the ingestor can't parse or insert are
+/// re-published to `DLQ_SUBJECT` with a `Dlq-Reason` header describing the failure.
+struct DeadLetter {
+    nc: async_nats::Client,
+    subject: String,
+    count: u64,
+}
+
+impl DeadLetter {
+    /// Publish one payload to the DLQ. Failures are logged, never propagated.
+    async fn send(&mut self, payload: Vec<u8>, reason: &str) {
+        // header values must be single-line; ClickHouse errors can be long
+        let reason: String = reason.replace(['\r', '\n'], " ").chars().take(512).collect();
+        let mut headers = async_nats::HeaderMap::new();
+        headers.insert("Dlq-Reason", reason.as_str());
+        match self.nc.publish_with_headers(self.subject.clone(), headers, payload.into()).await {
+            Ok(()) => self.count += 1,
+            Err(e) => eprintln!("DLQ publish to {} failed: {e}", self.subject),
+        }
+    }
+
+    async fn send_all<S: AsRef<str>>(&mut self, rows: &[S], reason: &str) {
+        for row in rows {
+            self.send(row.as_ref().as_bytes().to_vec(), reason).await;
+        }
+        println!("dead-lettered {} row(s) to {} ({} total)", rows.len(), self.subject, self.count);
+    }
+}
+
+/// Where batches go: ClickHouse over HTTP (default) or rotated Parquet files.
+enum Output {
+    ClickHouse(ClickHouse),
+    Parquet(Box<ParquetOutput>),
+}
+
+impl Output {
+    /// Write `buf` and clear it; `false` if the rows were kept for a retry (breaker on).
+    /// `route` picks a CH_SUBJECT_TABLES table instead of the default ones. `trace` is
+    /// the batch's trace context, the parent of its insert spans.
+    async fn write(
+        &mut self,
+        route: Option<usize>,
+        buf: &mut Vec<String>,
+        trace: Option<opentelemetry::Context>,
+        dlq: Option<&mut DeadLetter>,
+    ) -> Result<bool> {
+        match self {
+            Output::ClickHouse(ch) => {
+                let targets = match route {
+                    Some(i) => std::slice::from_ref(&ch.routes[i]),
+                    None => &ch.targets[..],
+                };
+                flush_batch(ch, targets, buf, trace.as_ref(), dlq).await
+            }
+            Output::Parquet(pq) => {
+                let rejected = pq.append(buf).await?;
+                if !rejected.is_empty() {
+                    eprintln!("dropping {} row(s) not matching the Row schema", rejected.len());
+                    if let Some(dlq) = dlq {
+                        for (row, reason) in &rejected {
+                            dlq.send(row.as_bytes().to_vec(), reason).await;
+                        }
+                    }
+                }
+                buf.clear();
+                Ok(true)
+            }
+        }
+    }
+
+    /// Periodic housekeeping on the flush timer.
+    async fn tick(&mut self) -> Result<()> {
+        match self {
+            Output::ClickHouse(_) => Ok(()),
+            Output::Parquet(pq) => pq.tick().await,
+        }
+    }
+
+    /// Called once on shutdown, after the final write.
+    async fn finish(&mut self) -> Result<()> {
+        match self {
+            Output::ClickHouse(_) => Ok(()),
+            Output::Parquet(pq) => pq.rotate().await,
+        }
+    }
+}
+
+struct ClickHouse {
+    client: reqwest::Client,
+    // one per CH_TABLES entry (or just CH_TABLE); each batch goes to all of them
+    targets: Vec<Target>,
+    // CH_SUBJECT_TABLES: one per route, for the rows of the subjects it matches
+    routes: Vec<Target>,
+    format: InsertFormat,
+    user: String,
+    pass: String,
+    retries: u32,
+    // breaker on: failed batches stay in the buffer instead of going to the DLQ
+    keep_failed: bool,
+    // JetStream (NATS_STREAM): rows that neither landed nor reached the DLQ stay in the
+    // buffer, so their messages stay unacked until a retry succeeds
+    at_least_once: bool,
+    timeout: InsertTimeout,
+}
+
+/// `CH_TIMEOUT_MS` + `CH_TIMEOUT_PER_MB_MS`: inserts of big bodies get longer.
+struct InsertTimeout {
+    base: Duration,
+    per_mb: Duration,
+}
+
+impl InsertTimeout {
+    fn for_body(&self, len: usize) -> Duration {
+        self.base + self.per_mb.mul_f64(len as f64 / (1024.0 * 1024.0))
+    }
+}
+
+struct Target {
+    table: String,
+    transform: Transform,
+    // one per shard (CH_SHARDS), else just the one on CH_HTTP
+    insert_urls: Vec<String>,
+}
+
+/// Turns one binary NATS payload (PAYLOAD_FORMAT) into a JSON row.
+type DecodeRow = fn(&[u8]) -> anyhow::Result<String>;
+
+#[derive(Clone, Copy)]
+enum InsertFormat {
+    JsonEachRow,
+    RowBinary,
+}
+
+/// This host's name, for the default NATS connection name.
+fn hostname() -> String {
+    std::fs::read_to_string("/proc/sys/kernel/hostname")
+        .ok()
+        .or_else(|| env::var("HOSTNAME").ok())
+        .map(|s| s.trim().to_string())
+        .filter(|s| !s.is_empty())
+        .unwrap_or_else(|| "unknown".to_string())
+}
+
+/// Percent-encode everything but RFC 3986 unreserved characters.
+fn url_escape(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    for b in s.bytes() {
+        if b.is_ascii_alphanumeric() || matches!(b, b'-' | b'_' | b'.' | b'~') {
+            out.push(b as char);
+        } else {
+            out.push_str(&format!("%{b:02X}"));
+        }
+    }
+    out
+}
+
+/// Validate `CH_SETTINGS` (`name=value&...`) and re-encode it as query parameters.
+/// Names must be plain identifiers so a typo can't smuggle in another `query=`.
+fn settings_query(raw: &str) -> Result<String> {
+    let mut pairs = Vec::new();
+    for pair in raw.split('&').map(str::trim).filter(|p| !p.is_empty()) {
+        let (name, value) = pair
+            .split_once('=')
+            .with_context(|| format!("CH_SETTINGS entry {pair:?} is not name=value"))?;
+        let name = name.trim();
+        anyhow::ensure!(
+            !name.is_empty() && name.bytes().all(|b| b.is_ascii_alphanumeric() || b == b'_'),
+            "CH_SETTINGS name {name:?} is not a ClickHouse setting name"
+        );
+        anyhow::ensure!(name != "query" && name != "database", "CH_SETTINGS may not set {name:?}");
+        pairs.push(format!("{name}={}", url_escape(value.trim())));
+    }
+    Ok(pairs.join("&"))
+}
+
+/// Flush `buf` to every table in `targets` and clear it. Rows ClickHouse rejects (or that
+/// fail to send while a DLQ is configured) are dead-lettered once per failing table;
+/// without a DLQ a transport error is fatal as before. With `keep_failed`, any failure
+/// leaves `buf` intact for a retry of every table instead, and returns `false`; so does
+/// any failure without a DLQ under `at_least_once`, which must not ack lost rows.
+/// With CH_SHARDS each table's rows are split by shard first, one insert per shard;
+/// a retry splits the same way, so shards that already took theirs deduplicate it.
+async fn flush_batch(
+    ch: &ClickHouse,
+    targets: &[Target],
+    buf: &mut Vec<String>,
+    trace: Option<&opentelemetry::Context>,
+    mut dlq: Option<&mut DeadLetter>,
+) -> Result<bool> {
+    let mut ok = true;
+    for target in targets {
+        if let [insert_url] = target.insert_urls.as_slice() {
+            ok &= match tables::apply(target.transform, buf) {
+                Some(mut rows) => flush_table(ch, target, insert_url, &mut rows, trace, dlq.as_deref_mut()).await?,
+                None => flush_table(ch, target, insert_url, buf, trace, dlq.as_deref_mut()).await?,
+            };
+            continue;
+        }
+        let rows = tables::apply(target.transform, buf);
+        let pieces = shard::split(rows.as_deref().unwrap_or(buf), target.insert_urls.len());
+        for (insert_url, mut rows) in target.insert_urls.iter().zip(pieces) {
+            if !rows.is_empty() {
+                ok &= flush_table(ch, target, insert_url, &mut rows, trace, dlq.as_deref_mut()).await?;
+            }
+        }
+    }
+    if ok || !(ch.keep_failed || ch.at_least_once) {
+        buf.clear();
+    }
+    Ok(ok)
+}
+
+/// Insert `buf` into one table (on one shard); RowBinary rejects are removed from
+/// `buf`. `false` if the insert failed.
+async fn flush_table(
+    ch: &ClickHouse,
+    target: &Target,
     insert_url: &str,
-    ch_user: &str,
-    ch_pass: &str,
     buf: &mut Vec<String>,
-) -> Result<()> {
-    // newline-delimited JSON for JSONEachRow
-    let body = buf.join("\n") + "\n";
-
-    let resp = client
-        .post(insert_url)
-        .basic_auth(ch_user, Some(ch_pass))
-        .body(body)
-        .send()
-        .await
-        .context("POST to ClickHouse")?;
-
-    // IMPORTANT: Response::text() consumes self, so capture status first.
-    let status = resp.status();
-    if !status.is_success() {
+    trace: Option<&opentelemetry::Context>,
+    mut dlq: Option<&mut DeadLetter>,
+) -> Result<bool> {
+    let body = match ch.format {
+        InsertFormat::JsonEachRow => encode_body(ch.format, buf),
+        InsertFormat::RowBinary => {
+            let (body, rejected) = rowbinary::encode(buf);
+            if !rejected.is_empty() {
+                eprintln!("dropping {} row(s) that can't be encoded as RowBinary", rejected.len());
+                if let Some(dlq) = dlq.as_deref_mut() {
+                    for (row, reason) in &rejected {
+                        dlq.send(row.as_bytes().to_vec(), reason).await;
+                    }
+                }
+                let rejected: std::collections::HashSet<&str> = rejected.iter().map(|(row, _)| row.as_str()).collect();
+                buf.retain(|row| !rejected.contains(row.as_str()));
+            }
+            body
+        }
+    };
+    if buf.is_empty() {
+        return Ok(true);
+    }
+    let failures = match flush(ch, insert_url, body, trace).await {
+        Ok(None) => return Ok(true),
+        // retrying the whole batch can't help; smaller pieces may go through
+        Ok(Some((status, reason))) if buf.len() > 1 && too_large(status, &reason) => {
+            eprintln!("batch of {} row(s) for {} too large; inserting it in smaller pieces", buf.len(), target.table);
+            insert_split(ch, insert_url, buf, trace).await
+        }
+        Ok(Some((status, reason))) => vec![(0..buf.len(), Some(status), reason)],
+        Err(e) => vec![(0..buf.len(), None, format!("{e:#}"))],
+    };
+    if failures.is_empty() {
+        return Ok(true);
+    }
+    // ClickHouse being down (5xx after retries, or unreachable) is what the breaker is
+    // for; a 4xx is about the rows themselves and retrying would never succeed
+    let down = |status: &Option<reqwest::StatusCode>| status.is_none_or(|s| s.is_server_error());
+    // (a retry splits the same way, so pieces that already landed are deduplicated)
+    if ch.keep_failed && failures.iter().any(|(_, status, _)| down(status)) {
+        for (_, _, reason) in failures.iter().filter(|(_, status, _)| status.is_none()) {
+            eprintln!("ClickHouse insert error: {reason}");
+        }
+        return Ok(false);
+    }
+    // nowhere to put them: acking would lose the rows, so they wait for a retry
+    if ch.at_least_once && dlq.is_none() {
+        for (rows, _, reason) in &failures {
+            eprintln!("ClickHouse insert of {} row(s) into {} failed: {reason}; left unacked", rows.len(), target.table);
+        }
+        return Ok(false);
+    }
+    for (rows, status, reason) in failures {
+        if status.is_none() {
+            // without a DLQ a transport error stays fatal
+            anyhow::ensure!(dlq.is_some(), "{reason}");
+            eprintln!("ClickHouse insert error: {reason}");
+        }
+        if let Some(dlq) = dlq.as_deref_mut() {
+            dlq.send_all(&buf[rows], &format!("insert into {} failed: {reason}", target.table)).await;
+        }
+    }
+    Ok(true)
+}
+
+/// The insert body for `rows`; for RowBinary they must all be encodable.
+fn encode_body(format: InsertFormat, rows: &[String]) -> Vec<u8> {
+    match format {
+        // newline-delimited JSON for JSONEachRow
+        InsertFormat::JsonEachRow => (rows.join("\n") + "\n").into_bytes(),
+        InsertFormat::RowBinary => rowbinary::encode(rows).0,
+    }
+}
+
+/// Whether ClickHouse (or a proxy in front of it) rejected an insert for its size,
+/// so the same rows in smaller batches would go through.
+fn too_large(status: reqwest::StatusCode, reason: &str) -> bool {
+    const MARKERS: [&str; 5] =
+        ["Max query size exceeded", "TOO_LARGE", "TOO_MANY_BYTES", "TOO_MANY_ROWS", "MEMORY_LIMIT_EXCEEDED"];
+    status == reqwest::StatusCode::PAYLOAD_TOO_LARGE || MARKERS.iter().any(|m| reason.contains(m))
+}
+
+/// Insert `rows` after the whole batch was rejected as too large: halve it, and keep
+/// halving whatever is still too large, down to single rows. Returns the pieces that
+/// still failed (in order), with the status (`None`: transport error) and reason; a
+/// single row that is too large on its own is one of those, for the DLQ.
+async fn insert_split(
+    ch: &ClickHouse,
+    insert_url: &str,
+    rows: &[String],
+    trace: Option<&opentelemetry::Context>,
+) -> Vec<(std::ops::Range<usize>, Option<reqwest::StatusCode>, String)> {
+    let mut failures = Vec::new();
+    let mid = rows.len() / 2;
+    // depth first, second half below the first, so pieces go out in row order
+    let mut pending = vec![mid..rows.len(), 0..mid];
+    while let Some(piece) = pending.pop() {
+        match flush(ch, insert_url, encode_body(ch.format, &rows[piece.clone()]), trace).await {
+            Ok(None) => {}
+            Ok(Some((status, reason))) if piece.len() > 1 && too_large(status, &reason) => {
+                let mid = piece.start + piece.len() / 2;
+                pending.push(mid..piece.end);
+                pending.push(piece.start..mid);
+            }
+            Ok(Some((status, reason))) => failures.push((piece, Some(status), reason)),
+            Err(e) => failures.push((piece, None, format!("{e:#}"))),
+        }
+    }
+    failures
+}
+
+/// POST one batch, retrying transport errors and 5xx responses up to `ch.retries`
+/// times. Returns the status and ClickHouse error text if the insert was finally rejected.
+///
+/// The `insert_deduplication_token` is a hash of the body, so a retry (or a later
+/// replay) of a batch that already landed is skipped by ClickHouse. That only holds
+/// within the table's dedup window: `replicated_deduplication_window` for Replicated*
+/// tables, and `non_replicated_deduplication_window` (0, i.e. off, by default) must be
+/// raised for plain MergeTree tables.
+async fn flush(
+    ch: &ClickHouse,
+    insert_url: &str,
+    body: Vec<u8>,
+    trace: Option<&opentelemetry::Context>,
+) -> Result<Option<(reqwest::StatusCode, String)>> {
+    let token: String = Sha256::digest(&body).iter().take(16).map(|b| format!("{b:02x}")).collect();
+    let url = format!("{insert_url}&insert_deduplication_token={token}");
+    let span = trace.map(|parent| otel::InsertSpan::start(parent, insert_url, body.len()));
+    // ClickHouse joins the trace and records its spans in system.opentelemetry_span_log
+    let traceparent = span.as_ref().and_then(otel::InsertSpan::traceparent);
+    let mut attempt = 0;
+    loop {
+        let mut req = ch.client
+            .post(&url)
+            .timeout(ch.timeout.for_body(body.len()))
+            .basic_auth(&ch.user, Some(&ch.pass));
+        if let Some(tp) = &traceparent {
+            req = req.header("traceparent", tp);
+        }
+        let retry_in = Duration::from_millis(500 * (u64::from(attempt) + 1));
+        let resp = match req.body(body.clone()).send().await.context("POST to ClickHouse") {
+            Ok(resp) => resp,
+            Err(e) if attempt < ch.retries => {
+                attempt += 1;
+                eprintln!("ClickHouse insert error: {e:#}; retry {attempt}/{} in {retry_in:?}", ch.retries);
+                tokio::time::sleep(retry_in).await;
+                continue;
+            }
+            Err(e) => {
+                if let Some(span) = &span {
+                    span.fail(&format!("{e:#}"));
+                }
+                return Err(e);
+            }
+        };
+
+        // IMPORTANT: Response::text() consumes self, so capture status first.
+        let status = resp.status();
+        if status.is_success() {
+            return Ok(None);
+        }
         let txt = resp.text().await.unwrap_or_default();
+        if status.is_server_error() && attempt < ch.retries {
+            attempt += 1;
+            eprintln!("ClickHouse insert failed: {status}; retry {attempt}/{} in {retry_in:?}", ch.retries);
+            tokio::time::sleep(retry_in).await;
+            continue;
+        }
         eprintln!("ClickHouse insert failed: {} :: {}", status, txt);
+        let reason = format!("{status} :: {txt}");
+        if let Some(span) = &span {
+            span.fail(&reason);
+        }
+        return Ok(Some((status, reason)));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+    use std::sync::atomic::{AtomicU16, AtomicU64, AtomicUsize, Ordering};
+    use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+    use tokio::net::TcpListener;
+    use tokio::sync::mpsc::{UnboundedSender, unbounded_channel};
+
+    /// A NATS server stand-in for a single client: answers PINGs, remembers SUBs and
+    /// delivers whatever `publish` is given to the subscription on exactly that subject
+    /// (holding it until the SUB has arrived).
+    struct MockNats {
+        url: String,
+        outbox: UnboundedSender<(String, String)>,
+    }
+
+    impl MockNats {
+        fn publish(&self, subject: &str, payload: &str) {
+            self.outbox.send((subject.to_string(), payload.to_string())).unwrap();
+        }
+    }
+
+    async fn mock_nats() -> MockNats {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let (outbox, mut queued) = unbounded_channel::<(String, String)>();
+        tokio::spawn(async move {
+            let (conn, _) = listener.accept().await.unwrap();
+            let (read, mut write) = conn.into_split();
+            let info = format!(
+                "INFO {{\"server_id\":\"mock\",\"version\":\"2.10.0\",\"host\":\"127.0.0.1\",\"port\":{},\"proto\":1,\"headers\":true,\"max_payload\":1048576}}\r\n",
+                addr.port()
+            );
+            write.write_all(info.as_bytes()).await.unwrap();
+            let mut lines = BufReader::new(read).lines();
+            let mut sids: HashMap<String, String> = HashMap::new();
+            let mut waiting: Vec<(String, String)> = Vec::new();
+            loop {
+                tokio::select! {
+                    line = lines.next_line() => {
+                        let Ok(Some(line)) = line else { return };
+                        let parts: Vec<&str> = line.split(' ').collect();
+                        match parts[0] {
+                            "PING" => write.write_all(b"PONG\r\n").await.unwrap(),
+                            "SUB" => {
+                                sids.insert(parts[1].to_string(), parts[parts.len() - 1].to_string());
+                            }
+                            _ => continue,
+                        }
+                    }
+                    Some(msg) = queued.recv() => waiting.push(msg),
+                }
+                let (ready, held) = waiting.drain(..).partition(|(subject, _)| sids.contains_key(subject));
+                waiting = held;
+                for (subject, payload) in ready {
+                    let frame = format!("MSG {subject} {} {}\r\n{payload}\r\n", sids[&subject], payload.len());
+                    write.write_all(frame.as_bytes()).await.unwrap();
+                }
+            }
+        });
+        MockNats { url: format!("nats://{addr}"), outbox }
+    }
+
+    /// A ClickHouse stand-in that answers every insert with the current `status`, after
+    /// `delay_ms`, and keeps each request body. Bodies over `max_body` bytes get a 413.
+    pub(crate) struct MockClickHouse {
+        pub(crate) url: String,
+        pub(crate) status: Arc<AtomicU16>,
+        pub(crate) delay_ms: Arc<AtomicU64>,
+        pub(crate) max_body: Arc<AtomicUsize>,
+        pub(crate) bodies: Bodies,
+    }
+
+    pub(crate) type Bodies = Arc<std::sync::Mutex<Vec<Vec<u8>>>>;
+
+    impl MockClickHouse {
+        pub(crate) fn inserts(&self) -> usize {
+            self.bodies.lock().unwrap().len()
+        }
     }
 
-    buf.clear();
-    Ok(())
+    pub(crate) async fn mock_clickhouse(status: u16) -> MockClickHouse {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/?query=INSERT%20INTO%20t%20FORMAT%20JSONEachRow", listener.local_addr().unwrap());
+        let (status, delay_ms) = (Arc::new(AtomicU16::new(status)), Arc::new(AtomicU64::new(0)));
+        let (max_body, bodies) = (Arc::new(AtomicUsize::new(usize::MAX)), Bodies::default());
+        let (answer, delay, max, seen) = (status.clone(), delay_ms.clone(), max_body.clone(), Arc::clone(&bodies));
+        tokio::spawn(async move {
+            loop {
+                let (mut conn, _) = listener.accept().await.unwrap();
+                let (answer, delay, max, seen) = (answer.clone(), delay.clone(), max.clone(), Arc::clone(&seen));
+                tokio::spawn(async move {
+                    let mut req = Vec::new();
+                    let mut chunk = [0u8; 4096];
+                    loop {
+                        let Ok(n) = conn.read(&mut chunk).await else { return };
+                        if n == 0 {
+                            return;
+                        }
+                        req.extend_from_slice(&chunk[..n]);
+                        // one request at a time: headers, then Content-Length bytes of body
+                        let Some(end) = req.windows(4).position(|w| w == b"\r\n\r\n") else { continue };
+                        let head = String::from_utf8_lossy(&req[..end]).to_ascii_lowercase();
+                        let len: usize = head
+                            .lines()
+                            .find_map(|l| l.strip_prefix("content-length:"))
+                            .map_or(0, |v| v.trim().parse().unwrap());
+                        if req.len() < end + 4 + len {
+                            continue;
+                        }
+                        seen.lock().unwrap().push(req.drain(..end + 4 + len).skip(end + 4).collect());
+                        tokio::time::sleep(Duration::from_millis(delay.load(Ordering::SeqCst))).await;
+                        let status = if len > max.load(Ordering::SeqCst) { 413 } else { answer.load(Ordering::SeqCst) };
+                        let resp = format!("HTTP/1.1 {status} Mock\r\ncontent-length: 4\r\n\r\nmock");
+                        if conn.write_all(resp.as_bytes()).await.is_err() {
+                            return;
+                        }
+                    }
+                });
+            }
+        });
+        MockClickHouse { url, status, delay_ms, max_body, bodies }
+    }
+
+    pub(crate) fn clickhouse(url: &str, at_least_once: bool) -> ClickHouse {
+        ClickHouse {
+            client: Client::new(),
+            targets: vec![Target { table: "t".into(), transform: Transform::Raw, insert_urls: vec![url.to_string()] }],
+            routes: Vec::new(),
+            format: InsertFormat::JsonEachRow,
+            user: "default".into(),
+            pass: String::new(),
+            retries: 0,
+            keep_failed: false,
+            at_least_once,
+            timeout: InsertTimeout { base: Duration::from_secs(5), per_mb: Duration::ZERO },
+        }
+    }
+
+    fn rows() -> Vec<String> {
+        vec![
+            r#"{"ts":"2025-11-13 22:15:33","slot":1,"write_ver":1,"pubkey":"a","lamports":5}"#.to_string(),
+            r#"{"ts":"2025-11-13 22:15:33","slot":1,"write_ver":2,"pubkey":"b","lamports":6}"#.to_string(),
+        ]
+    }
+
+    #[tokio::test]
+    async fn failed_jetstream_insert_is_kept_unacked_until_a_retry_lands() {
+        let mock = mock_clickhouse(500).await;
+        let ch = clickhouse(&mock.url, true);
+        let mut buf = rows();
+        assert!(!flush_batch(&ch, &ch.targets, &mut buf, None, None).await.unwrap());
+        assert_eq!(buf, rows(), "the failed batch stays buffered for the retry");
+        assert!(!rows_settled(&buf, &[], None), "its messages must not be acked");
+
+        // the next flush redelivers the same rows; once ClickHouse takes them they are acked
+        mock.status.store(200, Ordering::SeqCst);
+        assert!(flush_batch(&ch, &ch.targets, &mut buf, None, None).await.unwrap());
+        assert!(rows_settled(&buf, &[], None));
+        assert_eq!(mock.inserts(), 2);
+    }
+
+    #[tokio::test]
+    async fn rejected_jetstream_insert_without_dlq_is_not_acked() {
+        let mock = mock_clickhouse(400).await;
+        let ch = clickhouse(&mock.url, true);
+        let mut buf = rows();
+        assert!(!flush_batch(&ch, &ch.targets, &mut buf, None, None).await.unwrap());
+        assert!(!rows_settled(&buf, &[], None));
+    }
+
+    #[tokio::test]
+    async fn rejected_core_nats_insert_is_dropped() {
+        // plain subscriptions have nothing to redeliver, so a 4xx batch is given up
+        let mock = mock_clickhouse(400).await;
+        let ch = clickhouse(&mock.url, false);
+        let mut buf = rows();
+        assert!(flush_batch(&ch, &ch.targets, &mut buf, None, None).await.unwrap());
+        assert!(buf.is_empty());
+    }
+
+    #[tokio::test]
+    async fn routed_rows_go_only_to_their_subject_table() {
+        let (default, routed) = (mock_clickhouse(200).await, mock_clickhouse(200).await);
+        let mut ch = clickhouse(&default.url, false);
+        ch.routes = clickhouse(&routed.url, false).targets;
+        let mut output = Output::ClickHouse(ch);
+        let mut buf = rows();
+        assert!(output.write(Some(0), &mut buf, None, None).await.unwrap());
+        assert_eq!((default.inserts(), routed.inserts()), (0, 1));
+        let mut buf = rows();
+        assert!(output.write(None, &mut buf, None, None).await.unwrap());
+        assert_eq!((default.inserts(), routed.inserts()), (1, 1));
+    }
+
+    #[test]
+    fn multi_line_message_becomes_one_row_per_line() {
+        let payload = format!("{}\n\n  {}\r\nnot json\n", rows()[0], rows()[1]);
+        let mut buf = Vec::new();
+        let invalid = push_rows(&mut buf, &payload);
+        assert_eq!(buf, rows());
+        assert_eq!(invalid, ["not json"]);
+    }
+
+    #[tokio::test]
+    async fn two_subjects_feed_the_same_buffer() {
+        let nats = mock_nats().await;
+        let nc = async_nats::connect(&nats.url).await.unwrap();
+        let mut inbound = subscribe_all(&nc, &["WALLET.a".to_string(), "WALLET.b".to_string()]).await.unwrap();
+        let rows = rows();
+        nats.publish("WALLET.a", &rows[0]);
+        nats.publish("WALLET.b", &rows[1]);
+        let (mut buf, mut subjects) = (Vec::new(), Vec::new());
+        for _ in 0..2 {
+            let (msg, acker) = tokio::time::timeout(Duration::from_secs(5), inbound.next()).await.unwrap().unwrap();
+            assert!(acker.is_none());
+            subjects.push(msg.subject.to_string());
+            push_rows(&mut buf, std::str::from_utf8(&msg.payload).unwrap());
+        }
+        subjects.sort();
+        buf.sort();
+        assert_eq!(subjects, ["WALLET.a", "WALLET.b"]);
+        assert_eq!(buf, rows);
+    }
+
+    #[test]
+    fn flush_interval_shrinks_under_load_and_grows_when_idle() {
+        let mut every = FlushInterval::new(1000, 100, 5000);
+        // busy: batches fill before the tick, down to the floor
+        for _ in 0..10 {
+            every.on_full_batch();
+        }
+        assert_eq!(every.current, Duration::from_millis(100));
+        // a tick with a quarter batch or more buffered is not idle
+        every.on_tick(250, 1000);
+        assert_eq!(every.current, Duration::from_millis(100));
+        // quiet: nearly empty ticks, up to the ceiling
+        for _ in 0..20 {
+            every.on_tick(1, 1000);
+        }
+        assert_eq!(every.current, Duration::from_millis(5000));
+    }
+
+    #[tokio::test]
+    async fn every_table_gets_its_own_insert() {
+        let (raw, latest) = (mock_clickhouse(200).await, mock_clickhouse(200).await);
+        let mut ch = clickhouse(&raw.url, false);
+        ch.targets.push(Target { table: "t_latest".into(), transform: Transform::Latest, insert_urls: vec![latest.url.clone()] });
+        let mut buf = rows();
+        assert!(flush_batch(&ch, &ch.targets, &mut buf, None, None).await.unwrap());
+        assert!(buf.is_empty());
+        assert_eq!((raw.inserts(), latest.inserts()), (1, 1));
+    }
+
+    #[tokio::test]
+    async fn passthrough_payloads_are_posted_verbatim() {
+        let mock = mock_clickhouse(200).await;
+        let ch = clickhouse(&mock.url, false);
+        // CH_PASSTHROUGH buffers each payload as is: here a plugin batch of two rows
+        let rows = rows();
+        let mut buf = vec![rows.join("\n"), rows[0].clone()];
+        assert!(flush_batch(&ch, &ch.targets, &mut buf, None, None).await.unwrap());
+        let bodies = mock.bodies.lock().unwrap();
+        assert_eq!(bodies.len(), 1);
+        assert_eq!(String::from_utf8_lossy(&bodies[0]), format!("{}\n{}\n{}\n", rows[0], rows[1], rows[0]));
+    }
+
+    #[tokio::test]
+    async fn latest_table_gets_only_the_newest_row_per_pubkey() {
+        let mock = mock_clickhouse(200).await;
+        let mut ch = clickhouse(&mock.url, false);
+        ch.targets[0].transform = Transform::Latest;
+        let newer_a = r#"{"ts":"2025-11-13 22:15:34","slot":2,"write_ver":1,"pubkey":"a","lamports":7}"#.to_string();
+        let mut buf = rows();
+        buf.push(newer_a.clone());
+        assert!(flush_batch(&ch, &ch.targets, &mut buf, None, None).await.unwrap());
+        let body = String::from_utf8(mock.bodies.lock().unwrap()[0].clone()).unwrap();
+        assert_eq!(body, format!("{}\n{newer_a}\n", rows()[1]));
+    }
+
+    #[tokio::test]
+    async fn a_full_read_queue_stops_the_reader() {
+        let pulled = Arc::new(AtomicUsize::new(0));
+        let counter = pulled.clone();
+        let messages = futures_util::stream::iter(0..10).map(move |i| {
+            counter.fetch_add(1, Ordering::SeqCst);
+            let msg = async_nats::Message {
+                subject: "WALLET.updates".into(),
+                reply: None,
+                payload: format!("{i}").into(),
+                headers: None,
+                status: None,
+                description: None,
+                length: 0,
+            };
+            (msg, None)
+        });
+        let mut inbound = spawn_reader(Box::pin(messages), 2, Arc::new(Health::default()));
+        // a flusher that doesn't read: two queued, one held by the blocked send
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert_eq!(pulled.load(Ordering::SeqCst), 3);
+        let (first, _) = inbound.recv().await.unwrap();
+        assert_eq!(first.payload, "0");
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert_eq!(pulled.load(Ordering::SeqCst), 4, "one slot freed, one more message pulled");
+        let mut rest = Vec::new();
+        while let Some((msg, _)) = inbound.recv().await {
+            rest.push(String::from_utf8(msg.payload.to_vec()).unwrap());
+        }
+        assert_eq!(rest, (1..10).map(|i| i.to_string()).collect::<Vec<_>>());
+    }
+
+    #[tokio::test]
+    async fn too_short_a_timeout_retries_and_a_longer_one_succeeds() {
+        let mock = mock_clickhouse(200).await;
+        mock.delay_ms.store(300, Ordering::SeqCst);
+        let mut ch = clickhouse(&mock.url, false);
+        ch.retries = 1;
+        ch.timeout = InsertTimeout { base: Duration::from_millis(100), per_mb: Duration::ZERO };
+        let body = encode_body(ch.format, &rows());
+        assert!(flush(&ch, &mock.url, body.clone(), None).await.is_err(), "both attempts time out");
+        assert_eq!(mock.inserts(), 2, "the timeout took the retry path");
+
+        ch.timeout.base = Duration::from_secs(2);
+        assert!(flush(&ch, &mock.url, body, None).await.unwrap().is_none());
+        assert_eq!(mock.inserts(), 3);
+
+        // big bodies get more time
+        let timeout = InsertTimeout { base: Duration::from_secs(1), per_mb: Duration::from_secs(2) };
+        assert_eq!(timeout.for_body(512 * 1024), Duration::from_secs(2));
+    }
+
+    #[tokio::test]
+    async fn too_large_a_batch_is_split_until_its_pieces_go_in() {
+        let mock = mock_clickhouse(200).await;
+        // room for two ordinary rows per insert
+        mock.max_body.store(200, Ordering::SeqCst);
+        let ch = clickhouse(&mock.url, false);
+        let row = |n: u64, data: &str| format!(r#"{{"slot":1,"write_ver":{n},"pubkey":"a","lamports":5,"data":"{data}"}}"#);
+        let big = "A".repeat(300);
+        let mut buf = vec![row(1, ""), row(2, ""), row(3, &big), row(4, ""), row(5, "")];
+        let sent = buf.clone();
+        assert!(flush_batch(&ch, &ch.targets, &mut buf, None, None).await.unwrap());
+        assert!(buf.is_empty());
+
+        let bodies = mock.bodies.lock().unwrap().clone();
+        let inserted: Vec<&[u8]> = bodies.iter().filter(|b| b.len() <= 200).map(Vec::as_slice).collect();
+        let expected = [encode_body(ch.format, &sent[0..2]), encode_body(ch.format, &sent[3..5])];
+        assert_eq!(inserted, expected.iter().map(Vec::as_slice).collect::<Vec<_>>());
+        // the whole batch, rows 3-5, then row 3 alone: too large even on its own, so it is given up
+        assert_eq!(bodies.len(), 5);
+        assert_eq!(bodies[3], encode_body(ch.format, &sent[2..3]));
+    }
+
+    #[tokio::test]
+    async fn sharded_rows_go_to_their_shards_endpoint() {
+        let (shard0, shard1) = (mock_clickhouse(200).await, mock_clickhouse(200).await);
+        let mut ch = clickhouse(&shard0.url, false);
+        ch.targets[0].insert_urls.push(shard1.url.clone());
+        let mut buf = rows();
+        assert!(flush_batch(&ch, &ch.targets, &mut buf, None, None).await.unwrap());
+        // pubkey "a" hashes to shard 1, "b" to shard 0
+        let all = rows();
+        assert_eq!(*shard0.bodies.lock().unwrap(), [encode_body(ch.format, &all[1..])]);
+        assert_eq!(*shard1.bodies.lock().unwrap(), [encode_body(ch.format, &all[..1])]);
+    }
+
+    #[test]
+    fn batch_size_shrinks_as_insert_latency_rises_and_recovers() {
+        let ms = Duration::from_millis;
+        let mut size = BatchSize::new(1000, 100, 2000, Some(200));
+        // latency creeping up past the 200ms target: each slow insert cuts the batch
+        let mut sizes = Vec::new();
+        for took in [150, 250, 400, 800, 1600, 3200] {
+            let rows = size.current;
+            size.on_insert(rows, ms(took), true);
+            sizes.push(size.current);
+        }
+        // 150ms is within the target; at 250ms only 800 rows fit in it; then at most halved
+        // per insert, and never below the minimum
+        assert_eq!(sizes, [1000, 800, 400, 200, 100, 100]);
+        // fast full batches grow it back by a quarter at a time, up to the maximum
+        for _ in 0..20 {
+            let rows = size.current;
+            size.on_insert(rows, ms(50), true);
+        }
+        assert_eq!(size.current, 2000);
+        // a failure or a partial batch is no reason to grow
+        let mut fixed = BatchSize::new(1000, 100, 2000, Some(200));
+        fixed.on_insert(1000, ms(50), false);
+        fixed.on_insert(10, ms(50), true);
+        assert_eq!(fixed.current, 1000);
+        // without a target the size never moves
+        let mut untuned = BatchSize::new(1000, 100, 2000, None);
+        untuned.on_insert(1000, ms(5000), true);
+        assert_eq!(untuned.current, 1000);
+    }
+
+    /// A JetStream API stand-in answering every CONSUMER.INFO request with `info`.
+    async fn mock_jetstream(info: String) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            let (conn, _) = listener.accept().await.unwrap();
+            let (read, mut write) = conn.into_split();
+            let hello = format!(
+                "INFO {{\"server_id\":\"mock\",\"version\":\"2.10.0\",\"host\":\"127.0.0.1\",\"port\":{},\"proto\":1,\"headers\":true,\"jetstream\":true,\"max_payload\":1048576}}\r\n",
+                addr.port()
+            );
+            write.write_all(hello.as_bytes()).await.unwrap();
+            let mut reader = BufReader::new(read);
+            // (subscribed pattern, sid); replies go to the client's inbox wildcard
+            let mut subs: Vec<(String, String)> = Vec::new();
+            loop {
+                let mut line = String::new();
+                if reader.read_line(&mut line).await.unwrap_or(0) == 0 {
+                    return;
+                }
+                let parts: Vec<&str> = line.split_whitespace().collect();
+                match parts.first().copied() {
+                    Some("PING") => write.write_all(b"PONG\r\n").await.unwrap(),
+                    Some("SUB") => subs.push((parts[1].to_string(), parts[parts.len() - 1].to_string())),
+                    Some(op @ ("PUB" | "HPUB")) => {
+                        let mut body = vec![0; parts[parts.len() - 1].parse::<usize>().unwrap() + 2];
+                        reader.read_exact(&mut body).await.unwrap();
+                        let reply = parts.get(2).filter(|_| parts.len() == if op == "HPUB" { 5 } else { 4 });
+                        let (Some(reply), true) = (reply, parts[1].starts_with("$JS.API.CONSUMER.INFO.")) else { continue };
+                        let inbox = |(pattern, _): &&(String, String)| {
+                            pattern.strip_suffix('*').is_some_and(|prefix| reply.starts_with(prefix)) || pattern == reply
+                        };
+                        if let Some((_, sid)) = subs.iter().find(inbox) {
+                            let frame = format!("MSG {reply} {sid} {}\r\n{info}\r\n", info.len());
+                            write.write_all(frame.as_bytes()).await.unwrap();
+                        }
+                    }
+                    _ => {}
+                }
+            }
+        });
+        format!("nats://{addr}")
+    }
+
+    #[tokio::test]
+    async fn polled_lag_is_the_consumers_unacked_backlog() {
+        // 5 messages not yet delivered, 3 delivered but not acked
+        let info = serde_json::json!({
+            "type": "io.nats.jetstream.api.v1.consumer_info_response",
+            "stream_name": "WALLET", "name": "clickhouse_ingestor", "created": "2025-11-13T22:15:33Z",
+            "config": {
+                "durable_name": "clickhouse_ingestor", "deliver_policy": "all", "ack_policy": "explicit",
+                "replay_policy": "instant",
+            },
+            "delivered": {"consumer_seq": 10, "stream_seq": 10},
+            "ack_floor": {"consumer_seq": 7, "stream_seq": 7},
+            "num_ack_pending": 3, "num_redelivered": 0, "num_waiting": 0, "num_pending": 5,
+        });
+        let url = mock_jetstream(info.to_string()).await;
+        let client = async_nats::connect(&url).await.unwrap();
+        let mut consumer: async_nats::jetstream::consumer::PullConsumer = async_nats::jetstream::new(client)
+            .get_consumer_from_stream("clickhouse_ingestor", "WALLET")
+            .await
+            .unwrap();
+        let health = Health::default();
+        poll_lag(&mut consumer, &health).await;
+        assert_eq!(health.lag().0, Some(8));
+    }
+
+    #[tokio::test]
+    async fn published_at_header_sets_the_lag_in_seconds() {
+        let now = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).unwrap().as_millis() as u64;
+        let mut headers = async_nats::HeaderMap::new();
+        headers.insert("Published-At", (now - 4_000).to_string().as_str());
+        let msg = async_nats::Message {
+            subject: "WALLET.updates".into(),
+            reply: None,
+            payload: rows()[0].clone().into(),
+            headers: Some(headers),
+            status: None,
+            description: None,
+            length: 0,
+        };
+        let health = Arc::new(Health::default());
+        let mut inbound = spawn_reader(Box::pin(futures_util::stream::iter([(msg, None)])), 2, health.clone());
+        assert!(inbound.recv().await.is_some());
+        let (messages, seconds) = health.lag();
+        assert_eq!(messages, None, "core NATS has no consumer backlog to poll");
+        assert!(seconds.is_some_and(|s| (4.0..5.0).contains(&s)), "{seconds:?}");
+    }
 }
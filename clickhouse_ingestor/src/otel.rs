@@ -0,0 +1,153 @@
+//! OpenTelemetry for the plugin's `tracing_enabled`: a message carrying a W3C
+//! `traceparent` header puts its batch in that trace, and every ClickHouse insert
+//! request for the batch (retries included) gets a client span under the plugin's
+//! publish span. The span's context goes on to ClickHouse as its own `traceparent`,
+//! so ClickHouse's spans (`system.opentelemetry_span_log`) nest under ours.
+//!
+//! Spans are exported over OTLP/HTTP only with `TRACING_ENABLED`; the collector comes
+//! from the standard `OTEL_EXPORTER_OTLP_TRACES_ENDPOINT` / `OTEL_EXPORTER_OTLP_ENDPOINT`
+//! variables (default `http://localhost:4318/v1/traces`). Without it nothing is
+//! recorded here and ClickHouse gets the plugin's context unchanged.
+
+use anyhow::{Context as _, Result};
+use opentelemetry::propagation::{Extractor, Injector, TextMapPropagator};
+use opentelemetry::trace::{SpanKind, Status, TraceContextExt, Tracer};
+use opentelemetry::{Context, KeyValue, global};
+use opentelemetry_sdk::propagation::TraceContextPropagator;
+use opentelemetry_sdk::trace::SdkTracerProvider;
+use opentelemetry_sdk::Resource;
+
+/// The installed tracer provider; dropping it exports the spans still queued.
+pub struct Tracing(SdkTracerProvider);
+
+impl Tracing {
+    /// Install the OTLP/HTTP exporter as the global tracer provider.
+    pub fn init() -> Result<Self> {
+        // the blocking HTTP client can't be built (or dropped) on a runtime thread; the
+        // exporter then lives on the batch processor's own thread
+        let exporter = std::thread::spawn(|| opentelemetry_otlp::SpanExporter::builder().with_http().build())
+            .join()
+            .map_err(|_| anyhow::anyhow!("OTLP exporter setup panicked"))?
+            .context("build the OTLP span exporter")?;
+        Ok(Self::install(SdkTracerProvider::builder().with_batch_exporter(exporter)))
+    }
+
+    fn install(builder: opentelemetry_sdk::trace::TracerProviderBuilder) -> Self {
+        let resource = Resource::builder().with_service_name(env!("CARGO_PKG_NAME")).build();
+        let provider = builder.with_resource(resource).build();
+        global::set_tracer_provider(provider.clone());
+        Tracing(provider)
+    }
+}
+
+impl Drop for Tracing {
+    fn drop(&mut self) {
+        if let Err(e) = self.0.shutdown() {
+            eprintln!("OpenTelemetry shutdown failed: {e}");
+        }
+    }
+}
+
+/// The trace context a message's `traceparent` header carries; `None` without one
+/// (or with a malformed one).
+pub fn parent(headers: &async_nats::HeaderMap) -> Option<Context> {
+    let cx = TraceContextPropagator::new().extract(&NatsHeaders(headers));
+    cx.span().span_context().is_valid().then_some(cx)
+}
+
+/// The span of one ClickHouse insert request; ends when dropped.
+pub struct InsertSpan(Context);
+
+impl InsertSpan {
+    pub fn start(parent: &Context, insert_url: &str, body_len: usize) -> Self {
+        let tracer = global::tracer(env!("CARGO_CRATE_NAME"));
+        let span = tracer
+            .span_builder("clickhouse insert")
+            .with_kind(SpanKind::Client)
+            .with_attributes([
+                KeyValue::new("db.system.name", "clickhouse"),
+                KeyValue::new("db.operation.name", "INSERT"),
+                // the query string holds the table and format, never the credentials
+                KeyValue::new("url.full", insert_url.to_string()),
+                KeyValue::new("http.request.body.size", body_len as i64),
+            ])
+            .start_with_context(&tracer, parent);
+        InsertSpan(parent.with_span(span))
+    }
+
+    /// This span's context as a `traceparent` header value, for ClickHouse.
+    pub fn traceparent(&self) -> Option<String> {
+        let mut headers = std::collections::HashMap::new();
+        TraceContextPropagator::new().inject_context(&self.0, &mut Headers(&mut headers));
+        headers.remove("traceparent")
+    }
+
+    pub fn fail(&self, reason: &str) {
+        self.0.span().set_status(Status::error(reason.to_string()));
+    }
+}
+
+impl Drop for InsertSpan {
+    fn drop(&mut self) {
+        self.0.span().end();
+    }
+}
+
+struct NatsHeaders<'a>(&'a async_nats::HeaderMap);
+
+impl Extractor for NatsHeaders<'_> {
+    fn get(&self, key: &str) -> Option<&str> {
+        self.0.get(key).map(|v| v.as_str())
+    }
+
+    fn keys(&self) -> Vec<&str> {
+        self.0.iter().map(|(name, _)| name.as_ref()).collect()
+    }
+}
+
+struct Headers<'a>(&'a mut std::collections::HashMap<String, String>);
+
+impl Injector for Headers<'_> {
+    fn set(&mut self, key: &str, value: String) {
+        self.0.insert(key.to_string(), value);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use opentelemetry_sdk::trace::InMemorySpanExporter;
+
+    const PARENT: &str = "00-0af7651916cd43dd8448eb211c80319c-b7ad6b7169203331-01";
+
+    fn headers(traceparent: &str) -> async_nats::HeaderMap {
+        let mut h = async_nats::HeaderMap::new();
+        h.insert("traceparent", traceparent);
+        h
+    }
+
+    #[test]
+    fn only_a_well_formed_traceparent_is_a_parent() {
+        let cx = parent(&headers(PARENT)).unwrap();
+        assert_eq!(cx.span().span_context().trace_id().to_string(), "0af7651916cd43dd8448eb211c80319c");
+        assert!(parent(&headers("00-zzz-b7ad6b7169203331-01")).is_none());
+        assert!(parent(&async_nats::HeaderMap::new()).is_none());
+    }
+
+    #[test]
+    fn insert_span_is_a_child_of_the_publish_span_and_is_sent_on() {
+        let exporter = InMemorySpanExporter::default();
+        let _tracing = Tracing::install(SdkTracerProvider::builder().with_simple_exporter(exporter.clone()));
+        let span = InsertSpan::start(&parent(&headers(PARENT)).unwrap(), "http://ch:8123/?query=INSERT", 10);
+        let sent = span.traceparent().unwrap();
+        span.fail("500 :: down");
+        drop(span);
+
+        let spans = exporter.get_finished_spans().unwrap();
+        let span = spans.iter().find(|s| s.span_context.trace_id().to_string() == "0af7651916cd43dd8448eb211c80319c").unwrap();
+        assert_eq!(span.parent_span_id.to_string(), "b7ad6b7169203331");
+        assert_eq!(span.span_kind, SpanKind::Client);
+        assert_eq!(span.status, Status::error("500 :: down"));
+        assert_eq!(sent, format!("00-{}-{}-01", span.span_context.trace_id(), span.span_context.span_id()));
+    }
+}
@@ -0,0 +1,213 @@
+//! Parquet output for cold-storage backfills (`SINK=parquet`).
+//!
+//! Rows are buffered into an in-memory Parquet file that is rotated after
+//! `PARQUET_ROTATE_ROWS` rows or `PARQUET_ROTATE_SECS` seconds, then written to
+//! `PARQUET_DIR` or, with the `s3` cargo feature, uploaded to `PARQUET_S3_BUCKET`.
+
+use anyhow::{Context, Result};
+use arrow_array::{ArrayRef, BooleanArray, Float64Array, RecordBatch, StringArray, UInt64Array, UInt8Array};
+use arrow_schema::{DataType, Field, Schema, SchemaRef};
+use parquet::arrow::ArrowWriter;
+use std::env;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use crate::row::RowRecord;
+
+fn row_schema() -> SchemaRef {
+    Arc::new(Schema::new(vec![
+        Field::new("ts", DataType::Utf8, false),
+        Field::new("slot", DataType::UInt64, false),
+        Field::new("write_ver", DataType::UInt64, false),
+        Field::new("pubkey", DataType::Utf8, false),
+        Field::new("lamports", DataType::UInt64, false),
+        Field::new("data", DataType::Utf8, true),
+        Field::new("data_encoding", DataType::Utf8, true),
+        Field::new("data_truncated", DataType::Boolean, true),
+        Field::new("data_len", DataType::UInt64, true),
+        Field::new("final", DataType::Boolean, true),
+        Field::new("txn_index", DataType::UInt64, true),
+        Field::new("leader", DataType::Utf8, true),
+        Field::new("source_host", DataType::Utf8, true),
+        Field::new("run_id", DataType::Utf8, true),
+        Field::new("voter", DataType::Utf8, true),
+        Field::new("stake", DataType::UInt64, true),
+        Field::new("activation_epoch", DataType::UInt64, true),
+        Field::new("deactivation_epoch", DataType::UInt64, true),
+        Field::new("token_mint", DataType::Utf8, true),
+        Field::new("token_owner", DataType::Utf8, true),
+        Field::new("token_amount", DataType::UInt64, true),
+        Field::new("token_ui_amount", DataType::Float64, true),
+        Field::new("slot_time", DataType::Utf8, true),
+        Field::new("node_pubkey", DataType::Utf8, true),
+        Field::new("commission", DataType::UInt8, true),
+        Field::new("last_vote_slot", DataType::UInt64, true),
+        Field::new("credits", DataType::UInt64, true),
+        Field::new("epoch", DataType::UInt64, true),
+    ]))
+}
+
+fn to_record_batch(schema: &SchemaRef, rows: &[RowRecord]) -> Result<RecordBatch> {
+    let columns: Vec<ArrayRef> = vec![
+        Arc::new(StringArray::from_iter_values(rows.iter().map(|r| r.ts.as_str()))),
+        Arc::new(UInt64Array::from_iter_values(rows.iter().map(|r| r.slot))),
+        Arc::new(UInt64Array::from_iter_values(rows.iter().map(|r| r.write_ver))),
+        Arc::new(StringArray::from_iter_values(rows.iter().map(|r| r.pubkey.as_str()))),
+        Arc::new(UInt64Array::from_iter_values(rows.iter().map(|r| r.lamports))),
+        Arc::new(StringArray::from_iter(rows.iter().map(|r| r.data.as_deref()))),
+        Arc::new(StringArray::from_iter(rows.iter().map(|r| r.data_encoding.as_deref()))),
+        Arc::new(BooleanArray::from_iter(rows.iter().map(|r| r.data_truncated))),
+        Arc::new(UInt64Array::from_iter(rows.iter().map(|r| r.data_len))),
+        Arc::new(BooleanArray::from_iter(rows.iter().map(|r| r.is_final))),
+        Arc::new(UInt64Array::from_iter(rows.iter().map(|r| r.txn_index))),
+        Arc::new(StringArray::from_iter(rows.iter().map(|r| r.leader.as_deref()))),
+        Arc::new(StringArray::from_iter(rows.iter().map(|r| r.source_host.as_deref()))),
+        Arc::new(StringArray::from_iter(rows.iter().map(|r| r.run_id.as_deref()))),
+        Arc::new(StringArray::from_iter(rows.iter().map(|r| r.voter.as_deref()))),
+        Arc::new(UInt64Array::from_iter(rows.iter().map(|r| r.stake))),
+        Arc::new(UInt64Array::from_iter(rows.iter().map(|r| r.activation_epoch))),
+        Arc::new(UInt64Array::from_iter(rows.iter().map(|r| r.deactivation_epoch))),
+        Arc::new(StringArray::from_iter(rows.iter().map(|r| r.token_mint.as_deref()))),
+        Arc::new(StringArray::from_iter(rows.iter().map(|r| r.token_owner.as_deref()))),
+        Arc::new(UInt64Array::from_iter(rows.iter().map(|r| r.token_amount))),
+        Arc::new(Float64Array::from_iter(rows.iter().map(|r| r.token_ui_amount))),
+        Arc::new(StringArray::from_iter(rows.iter().map(|r| r.slot_time.as_deref()))),
+        Arc::new(StringArray::from_iter(rows.iter().map(|r| r.node_pubkey.as_deref()))),
+        Arc::new(UInt8Array::from_iter(rows.iter().map(|r| r.commission))),
+        Arc::new(UInt64Array::from_iter(rows.iter().map(|r| r.last_vote_slot))),
+        Arc::new(UInt64Array::from_iter(rows.iter().map(|r| r.credits))),
+        Arc::new(UInt64Array::from_iter(rows.iter().map(|r| r.epoch))),
+    ];
+    RecordBatch::try_new(schema.clone(), columns).context("build Arrow record batch")
+}
+
+enum Target {
+    Dir(PathBuf),
+    #[cfg(feature = "s3")]
+    S3 {
+        client: aws_sdk_s3::Client,
+        bucket: String,
+        prefix: String,
+    },
+}
+
+pub struct ParquetOutput {
+    schema: SchemaRef,
+    target: Target,
+    rotate_rows: usize,
+    rotate_after: Duration,
+    writer: Option<ArrowWriter<Vec<u8>>>,
+    rows_in_file: usize,
+    opened_at: Instant,
+    files_written: u64,
+}
+
+impl ParquetOutput {
+    pub async fn from_env() -> Result<Self> {
+        let rotate_rows = env::var("PARQUET_ROTATE_ROWS").ok().and_then(|s| s.parse().ok()).unwrap_or(100_000usize);
+        let rotate_secs = env::var("PARQUET_ROTATE_SECS").ok().and_then(|s| s.parse().ok()).unwrap_or(300u64);
+        let bucket      = env::var("PARQUET_S3_BUCKET").ok().filter(|s| !s.is_empty());
+
+        let target = match bucket {
+            #[cfg(feature = "s3")]
+            Some(bucket) => {
+                let prefix = env::var("PARQUET_S3_PREFIX").unwrap_or_default();
+                let config = aws_config::load_defaults(aws_config::BehaviorVersion::latest()).await;
+                println!("Parquet → s3://{bucket}/{prefix} (rotate={rotate_rows} rows / {rotate_secs}s)");
+                Target::S3 { client: aws_sdk_s3::Client::new(&config), bucket, prefix }
+            }
+            #[cfg(not(feature = "s3"))]
+            Some(_) => anyhow::bail!("PARQUET_S3_BUCKET is set but the ingestor was built without the `s3` feature"),
+            None => {
+                let dir = PathBuf::from(env::var("PARQUET_DIR").unwrap_or_else(|_| "./parquet".into()));
+                std::fs::create_dir_all(&dir).with_context(|| format!("create PARQUET_DIR {}", dir.display()))?;
+                println!("Parquet → {} (rotate={rotate_rows} rows / {rotate_secs}s)", dir.display());
+                Target::Dir(dir)
+            }
+        };
+
+        Ok(ParquetOutput {
+            schema: row_schema(),
+            target,
+            rotate_rows,
+            rotate_after: Duration::from_secs(rotate_secs),
+            writer: None,
+            rows_in_file: 0,
+            opened_at: Instant::now(),
+            files_written: 0,
+        })
+    }
+
+    /// Append JSON rows to the current file. Returns the rows that don't match the
+    /// `Row` schema, with the reason, so the caller can dead-letter them.
+    pub async fn append(&mut self, buf: &[String]) -> Result<Vec<(String, String)>> {
+        let mut records = Vec::with_capacity(buf.len());
+        let mut rejected = Vec::new();
+        for line in buf {
+            match serde_json::from_str::<RowRecord>(line) {
+                Ok(r) => records.push(r),
+                Err(e) => rejected.push((line.clone(), format!("schema mismatch: {e}"))),
+            }
+        }
+        if !records.is_empty() {
+            let batch = to_record_batch(&self.schema, &records)?;
+            if self.writer.is_none() {
+                self.writer = Some(ArrowWriter::try_new(Vec::new(), self.schema.clone(), None)?);
+                self.opened_at = Instant::now();
+            }
+            if let Some(writer) = self.writer.as_mut() {
+                writer.write(&batch).context("write Parquet batch")?;
+            }
+            self.rows_in_file += records.len();
+        }
+        if self.rows_in_file >= self.rotate_rows {
+            self.rotate().await?;
+        }
+        Ok(rejected)
+    }
+
+    /// Rotate on the time limit; called from the flush ticker.
+    pub async fn tick(&mut self) -> Result<()> {
+        if self.writer.is_some() && self.opened_at.elapsed() >= self.rotate_after {
+            self.rotate().await?;
+        }
+        Ok(())
+    }
+
+    /// Close the current file (if any) and write it out.
+    pub async fn rotate(&mut self) -> Result<()> {
+        let Some(writer) = self.writer.take() else { return Ok(()) };
+        let bytes = writer.into_inner().context("finish Parquet file")?;
+        let rows = std::mem::take(&mut self.rows_in_file);
+        let millis = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_millis();
+        let name = format!("wallet_account_updates-{millis}-{}.parquet", self.files_written);
+        self.files_written += 1;
+
+        match &self.target {
+            Target::Dir(dir) => {
+                // write under a temp name so readers never see a partial file
+                let tmp = dir.join(format!("{name}.tmp"));
+                let path = dir.join(&name);
+                std::fs::write(&tmp, &bytes).with_context(|| format!("write {}", tmp.display()))?;
+                std::fs::rename(&tmp, &path).with_context(|| format!("rename to {}", path.display()))?;
+                println!("wrote {} ({rows} rows, {} bytes)", path.display(), bytes.len());
+            }
+            #[cfg(feature = "s3")]
+            Target::S3 { client, bucket, prefix } => {
+                let key = format!("{prefix}{name}");
+                let size = bytes.len();
+                client
+                    .put_object()
+                    .bucket(bucket)
+                    .key(&key)
+                    .body(bytes.into())
+                    .send()
+                    .await
+                    .with_context(|| format!("upload s3://{bucket}/{key}"))?;
+                println!("uploaded s3://{bucket}/{key} ({rows} rows, {size} bytes)");
+            }
+        }
+        Ok(())
+    }
+}
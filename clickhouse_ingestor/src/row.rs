@@ -0,0 +1,60 @@
+//! The typed view of a plugin row shared by the Parquet and RowBinary outputs.
+
+use serde::Deserialize;
+
+/// Mirror of the plugin's `Row` (keep in sync). `lamports` is a u128 in the JSON
+/// but comes from a u64 account field, so it is stored as a u64.
+#[derive(Deserialize)]
+pub struct RowRecord {
+    pub ts: String,
+    pub slot: u64,
+    pub write_ver: u64,
+    pub pubkey: String,
+    pub lamports: u64,
+    #[serde(default)]
+    pub data: Option<String>,
+    #[serde(default)]
+    pub data_encoding: Option<String>,
+    #[serde(default)]
+    pub data_truncated: Option<bool>,
+    #[serde(default)]
+    pub data_len: Option<u64>,
+    #[serde(default, rename = "final")]
+    pub is_final: Option<bool>,
+    #[serde(default)]
+    pub txn_index: Option<u64>,
+    #[serde(default)]
+    pub leader: Option<String>,
+    #[serde(default)]
+    pub source_host: Option<String>,
+    #[serde(default)]
+    pub run_id: Option<String>,
+    #[serde(default)]
+    pub voter: Option<String>,
+    #[serde(default)]
+    pub stake: Option<u64>,
+    #[serde(default)]
+    pub activation_epoch: Option<u64>,
+    #[serde(default)]
+    pub deactivation_epoch: Option<u64>,
+    #[serde(default)]
+    pub token_mint: Option<String>,
+    #[serde(default)]
+    pub token_owner: Option<String>,
+    #[serde(default)]
+    pub token_amount: Option<u64>,
+    #[serde(default)]
+    pub token_ui_amount: Option<f64>,
+    #[serde(default)]
+    pub slot_time: Option<String>,
+    #[serde(default)]
+    pub node_pubkey: Option<String>,
+    #[serde(default)]
+    pub commission: Option<u8>,
+    #[serde(default)]
+    pub last_vote_slot: Option<u64>,
+    #[serde(default)]
+    pub credits: Option<u64>,
+    #[serde(default)]
+    pub epoch: Option<u64>,
+}
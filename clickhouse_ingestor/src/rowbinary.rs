@@ -0,0 +1,131 @@
+//! ClickHouse RowBinary encoding (`CH_FORMAT=RowBinary`).
+//!
+//! RowBinary is positional and untyped on the wire, so the insert names its
+//! columns (`COLUMNS`) and the table must declare them with exactly these types:
+//!
+//! ```sql
+//! ts                 DateTime,          -- plugin ts parsed as UTC (don't combine with its `timezone`)
+//! slot               UInt64,
+//! write_ver          UInt64,
+//! pubkey             String,
+//! lamports           UInt64,
+//! data               Nullable(String),
+//! data_encoding      Nullable(String),
+//! data_truncated     Nullable(Bool),
+//! data_len           Nullable(UInt64),
+//! `final`            Nullable(Bool),
+//! txn_index          Nullable(UInt64),
+//! leader             Nullable(String),
+//! source_host        Nullable(String),
+//! run_id             Nullable(String),
+//! voter              Nullable(String),
+//! stake              Nullable(UInt64),
+//! activation_epoch   Nullable(UInt64),
+//! deactivation_epoch Nullable(UInt64),
+//! token_mint         Nullable(String),
+//! token_owner        Nullable(String),
+//! token_amount       Nullable(UInt64),
+//! token_ui_amount    Nullable(Float64),
+//! slot_time          Nullable(DateTime),
+//! node_pubkey        Nullable(String),
+//! commission         Nullable(UInt8),
+//! last_vote_slot     Nullable(UInt64),
+//! credits            Nullable(UInt64),
+//! epoch              Nullable(UInt64)
+//! ```
+//!
+//! A mismatch is not detected by ClickHouse row by row: it rejects the whole batch,
+//! or worse, reads garbage, so keep this list in sync with the table.
+
+use anyhow::{Context, Result};
+use chrono::NaiveDateTime;
+
+use crate::row::RowRecord;
+
+/// Column list for `INSERT INTO t (...) FORMAT RowBinary`, in encoding order.
+pub const COLUMNS: &str = "ts, slot, write_ver, pubkey, lamports, data, data_encoding, data_truncated, data_len, `final`, txn_index, leader, source_host, run_id, voter, stake, activation_epoch, deactivation_epoch, token_mint, token_owner, token_amount, token_ui_amount, slot_time, node_pubkey, commission, last_vote_slot, credits, epoch";
+
+/// Encode JSON rows as one RowBinary body. Rows that don't match the `Row`
+/// schema are returned with the reason instead of encoded.
+pub fn encode(buf: &[String]) -> (Vec<u8>, Vec<(String, String)>) {
+    let mut body = Vec::with_capacity(buf.len() * 128);
+    let mut rejected = Vec::new();
+    for line in buf {
+        let encoded = serde_json::from_str::<RowRecord>(line)
+            .context("schema mismatch")
+            .and_then(|row| encode_row(&mut body, &row));
+        if let Err(e) = encoded {
+            rejected.push((line.clone(), format!("{e:#}")));
+        }
+    }
+    (body, rejected)
+}
+
+fn encode_row(out: &mut Vec<u8>, row: &RowRecord) -> Result<()> {
+    // validate before writing anything so a bad row leaves no partial bytes
+    let ts = datetime("ts", &row.ts)?;
+    let slot_time = row.slot_time.as_deref().map(|s| datetime("slot_time", s)).transpose()?;
+
+    out.extend_from_slice(&ts.to_le_bytes());
+    out.extend_from_slice(&row.slot.to_le_bytes());
+    out.extend_from_slice(&row.write_ver.to_le_bytes());
+    put_string(out, &row.pubkey);
+    out.extend_from_slice(&row.lamports.to_le_bytes());
+    put_nullable(out, row.data.as_deref(), put_string);
+    put_nullable(out, row.data_encoding.as_deref(), put_string);
+    put_nullable(out, row.data_truncated, |out, v| out.push(u8::from(v)));
+    put_nullable(out, row.data_len, |out, v| out.extend_from_slice(&v.to_le_bytes()));
+    put_nullable(out, row.is_final, |out, v| out.push(u8::from(v)));
+    put_nullable(out, row.txn_index, |out, v| out.extend_from_slice(&v.to_le_bytes()));
+    put_nullable(out, row.leader.as_deref(), put_string);
+    put_nullable(out, row.source_host.as_deref(), put_string);
+    put_nullable(out, row.run_id.as_deref(), put_string);
+    put_nullable(out, row.voter.as_deref(), put_string);
+    put_nullable(out, row.stake, |out, v| out.extend_from_slice(&v.to_le_bytes()));
+    put_nullable(out, row.activation_epoch, |out, v| out.extend_from_slice(&v.to_le_bytes()));
+    put_nullable(out, row.deactivation_epoch, |out, v| out.extend_from_slice(&v.to_le_bytes()));
+    put_nullable(out, row.token_mint.as_deref(), put_string);
+    put_nullable(out, row.token_owner.as_deref(), put_string);
+    put_nullable(out, row.token_amount, |out, v| out.extend_from_slice(&v.to_le_bytes()));
+    put_nullable(out, row.token_ui_amount, |out, v| out.extend_from_slice(&v.to_le_bytes()));
+    put_nullable(out, slot_time, |out, v| out.extend_from_slice(&v.to_le_bytes()));
+    put_nullable(out, row.node_pubkey.as_deref(), put_string);
+    put_nullable(out, row.commission, |out, v| out.push(v));
+    put_nullable(out, row.last_vote_slot, |out, v| out.extend_from_slice(&v.to_le_bytes()));
+    put_nullable(out, row.credits, |out, v| out.extend_from_slice(&v.to_le_bytes()));
+    put_nullable(out, row.epoch, |out, v| out.extend_from_slice(&v.to_le_bytes()));
+    Ok(())
+}
+
+/// A plugin timestamp ("YYYY-MM-DD HH:MM:SS") as DateTime seconds.
+fn datetime(field: &str, s: &str) -> Result<u32> {
+    let t = NaiveDateTime::parse_from_str(s, "%Y-%m-%d %H:%M:%S")
+        .with_context(|| format!("{field} {s:?} is not \"YYYY-MM-DD HH:MM:SS\""))?;
+    u32::try_from(t.and_utc().timestamp()).with_context(|| format!("{field} {s:?} is outside the DateTime range"))
+}
+
+/// String: LEB128 length, then the bytes.
+fn put_string(out: &mut Vec<u8>, s: &str) {
+    let mut len = s.len() as u64;
+    loop {
+        let byte = (len & 0x7f) as u8;
+        len >>= 7;
+        if len == 0 {
+            out.push(byte);
+            break;
+        }
+        out.push(byte | 0x80);
+    }
+    out.extend_from_slice(s.as_bytes());
+}
+
+/// Nullable(T): one null-marker byte, then the value only if it is present.
+fn put_nullable<T>(out: &mut Vec<u8>, value: Option<T>, put: impl FnOnce(&mut Vec<u8>, T)) {
+    match value {
+        Some(v) => {
+            out.push(0);
+            put(out, v);
+        }
+        None => out.push(1),
+    }
+}
@@ -0,0 +1,120 @@
+//! `CH_SHARDS`: client-side sharding for a Distributed table sharded by pubkey.
+//!
+//! Instead of inserting into the Distributed table on `CH_HTTP` (which re-splits every
+//! batch and forwards the pieces), each row goes straight to its shard: the one at
+//! `sipHash64(pubkey) % N` in the comma-separated list of N shard endpoints. That is
+//! where ClickHouse puts it for `Distributed(cluster, db, local_table, sipHash64(pubkey))`
+//! with equal shard weights, as long as the list is in the cluster's shard order.
+//! `CH_TABLE` / `CH_TABLES` then name the shard-local table. Plugin events
+//! (`CH_CHECKPOINT_TABLE`, `CH_SLOT_STATS_TABLE`) still go to `CH_HTTP`.
+
+use serde::Deserialize;
+
+/// Parse the comma-separated endpoint list.
+pub fn parse(raw: &str) -> anyhow::Result<Vec<String>> {
+    let endpoints: Vec<String> =
+        raw.split(',').map(str::trim).filter(|s| !s.is_empty()).map(|s| s.trim_end_matches('/').to_string()).collect();
+    anyhow::ensure!(!endpoints.is_empty(), "CH_SHARDS lists no endpoints");
+    for e in &endpoints {
+        anyhow::ensure!(e.starts_with("http://") || e.starts_with("https://"), "CH_SHARDS entry {e:?} is not an http(s) URL");
+    }
+    Ok(endpoints)
+}
+
+#[derive(Deserialize)]
+struct PubkeyOnly<'a> {
+    #[serde(borrow)]
+    pubkey: &'a str,
+}
+
+/// Split `rows` into one batch per shard (of `shards`), keeping their order. Rows
+/// without a readable pubkey go to the first shard.
+pub fn split(rows: &[String], shards: usize) -> Vec<Vec<String>> {
+    let mut out = vec![Vec::new(); shards];
+    for row in rows {
+        let shard = match serde_json::from_str::<PubkeyOnly>(row) {
+            Ok(key) => (sip_hash64(key.pubkey.as_bytes()) % shards as u64) as usize,
+            Err(_) => 0,
+        };
+        out[shard].push(row.clone());
+    }
+    out
+}
+
+/// ClickHouse's `sipHash64` of a string: SipHash-2-4 with an all-zero key.
+pub fn sip_hash64(data: &[u8]) -> u64 {
+    let (k0, k1) = (0u64, 0u64);
+    let mut v = [
+        k0 ^ 0x736f_6d65_7073_6575,
+        k1 ^ 0x646f_7261_6e64_6f6d,
+        k0 ^ 0x6c79_6765_6e65_7261,
+        k1 ^ 0x7465_6462_7974_6573,
+    ];
+    fn round(v: &mut [u64; 4]) {
+        v[0] = v[0].wrapping_add(v[1]);
+        v[1] = v[1].rotate_left(13) ^ v[0];
+        v[0] = v[0].rotate_left(32);
+        v[2] = v[2].wrapping_add(v[3]);
+        v[3] = v[3].rotate_left(16) ^ v[2];
+        v[0] = v[0].wrapping_add(v[3]);
+        v[3] = v[3].rotate_left(21) ^ v[0];
+        v[2] = v[2].wrapping_add(v[1]);
+        v[1] = v[1].rotate_left(17) ^ v[2];
+        v[2] = v[2].rotate_left(32);
+    }
+    let compress = |m: u64, v: &mut [u64; 4]| {
+        v[3] ^= m;
+        round(v);
+        round(v);
+        v[0] ^= m;
+    };
+    let mut chunks = data.chunks_exact(8);
+    for chunk in &mut chunks {
+        compress(u64::from_le_bytes(chunk.try_into().expect("8-byte chunk")), &mut v);
+    }
+    let mut last = [0u8; 8];
+    let tail = chunks.remainder();
+    last[..tail.len()].copy_from_slice(tail);
+    last[7] = data.len() as u8;
+    compress(u64::from_le_bytes(last), &mut v);
+    v[2] ^= 0xff;
+    for _ in 0..4 {
+        round(&mut v);
+    }
+    v[0] ^ v[1] ^ v[2] ^ v[3]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sip_hash64_matches_clickhouse() {
+        // SELECT sipHash64(s) for each s, covering the empty, short, exactly-8 and multi-block cases
+        for (s, hash) in [
+            ("", 2_202_906_307_356_721_367),
+            ("a", 10_863_254_463_029_944_905),
+            ("b", 17_270_894_748_891_556_580),
+            ("abcdefgh", 16_599_038_366_039_810_451),
+            ("9xQeWvG816bUx9EPjHmaT23yvVM2ZWbrrpZb9PusVFin", 1_663_590_548_947_653_261),
+        ] {
+            assert_eq!(sip_hash64(s.as_bytes()), hash, "{s:?}");
+        }
+    }
+
+    #[test]
+    fn rows_split_by_pubkey_hash_in_order() {
+        let row = |pubkey: &str, n: u8| format!(r#"{{"pubkey":"{pubkey}","slot":{n}}}"#);
+        let rows = [row("a", 1), row("b", 2), "not json".to_string(), row("a", 3)];
+        // sipHash64("a") is odd, sipHash64("b") even
+        assert_eq!(split(&rows, 2), [vec![rows[1].clone(), rows[2].clone()], vec![rows[0].clone(), rows[3].clone()]]);
+        assert_eq!(split(&rows, 1), [rows.to_vec()]);
+    }
+
+    #[test]
+    fn endpoint_list_parses() {
+        assert_eq!(parse(" http://ch-0:8123/, https://ch-1:8443 ,").unwrap(), ["http://ch-0:8123", "https://ch-1:8443"]);
+        assert!(parse(" , ").is_err());
+        assert!(parse("ch-0:8123").is_err());
+    }
+}
@@ -0,0 +1,150 @@
+//! `CH_TABLES`: insert every batch into several tables, each with its own
+//! transform, e.g. `wallet_account_updates,wallet_balances_1m:latest_per_minute`.
+//!
+//! Transforms:
+//! - `raw` (default): the batch as received
+//! - `latest_per_minute`: only the latest row per (pubkey, minute of `ts`), by
+//!   `(slot, write_ver)`. This collapses within one batch only, so the table should
+//!   be a `ReplacingMergeTree` ordered by `(pubkey, toStartOfMinute(ts))` to finish
+//!   the job across batches.
+//! - `latest`: only the latest row per pubkey, by `(slot, write_ver)`, for a
+//!   current-state table next to the history, e.g.
+//!   `wallet_account_updates,wallet_balances_latest:latest`. ReplacingMergeTree takes a
+//!   single version column, so give the table
+//!   `version UInt128 MATERIALIZED bitShiftLeft(toUInt128(slot), 64) + write_ver` and
+//!   `ENGINE = ReplacingMergeTree(version) ORDER BY pubkey`; query it with `FINAL`.
+
+use anyhow::Result;
+use serde::Deserialize;
+use std::collections::HashMap;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Transform {
+    Raw,
+    LatestPerMinute,
+    Latest,
+}
+
+#[derive(Debug)]
+pub struct TableSpec {
+    pub name: String,
+    pub transform: Transform,
+}
+
+/// Parse `name[:transform],...`. Names are plain identifiers; the database is `CH_DB`.
+pub fn parse(raw: &str) -> Result<Vec<TableSpec>> {
+    let mut tables = Vec::new();
+    for entry in raw.split(',').map(str::trim).filter(|s| !s.is_empty()) {
+        let (name, transform) = entry.split_once(':').unwrap_or((entry, "raw"));
+        let name = name.trim();
+        anyhow::ensure!(
+            !name.is_empty() && name.bytes().all(|b| b.is_ascii_alphanumeric() || b == b'_'),
+            "CH_TABLES name {name:?} is not a plain table name"
+        );
+        let transform = match transform.trim() {
+            "raw" => Transform::Raw,
+            "latest_per_minute" => Transform::LatestPerMinute,
+            "latest" => Transform::Latest,
+            other => anyhow::bail!("unknown CH_TABLES transform {other:?} for {name} (expected raw, latest_per_minute or latest)"),
+        };
+        tables.push(TableSpec { name: name.to_string(), transform });
+    }
+    anyhow::ensure!(!tables.is_empty(), "CH_TABLES lists no tables");
+    Ok(tables)
+}
+
+/// The row fields the collapsing transforms group and order by.
+#[derive(Deserialize)]
+struct RowKey<'a> {
+    #[serde(borrow)]
+    pubkey: &'a str,
+    #[serde(borrow)]
+    ts: &'a str,
+    slot: u64,
+    write_ver: u64,
+}
+
+type Order = (u64, u64);
+
+/// Rows for a table with a non-`raw` transform; `None` means "use the batch as is".
+/// Rows that don't parse as account rows are passed through unchanged.
+pub fn apply(transform: Transform, rows: &[String]) -> Option<Vec<String>> {
+    match transform {
+        Transform::Raw => None,
+        // ts is "YYYY-MM-DD HH:MM:SS[...]": the first 16 bytes are the minute
+        Transform::LatestPerMinute => Some(keep_latest(rows, |key| key.ts.get(..16).unwrap_or(key.ts))),
+        Transform::Latest => Some(keep_latest(rows, |_| "")),
+    }
+}
+
+/// The latest row per (pubkey, `group(row)`) by `(slot, write_ver)`, in batch order.
+fn keep_latest<'a>(rows: &'a [String], group: impl Fn(&RowKey<'a>) -> &'a str) -> Vec<String> {
+    let mut out = Vec::new();
+    // (pubkey, group) -> ((slot, write_ver), index of the row in `rows`)
+    let mut latest: HashMap<(&str, &str), (Order, usize)> = HashMap::new();
+    for (i, row) in rows.iter().enumerate() {
+        let Ok(key) = serde_json::from_str::<RowKey>(row) else {
+            out.push(row.clone());
+            continue;
+        };
+        let order = (key.slot, key.write_ver);
+        latest
+            .entry((key.pubkey, group(&key)))
+            .and_modify(|seen| {
+                if order >= seen.0 {
+                    *seen = (order, i);
+                }
+            })
+            .or_insert((order, i));
+    }
+    let mut kept: Vec<usize> = latest.into_values().map(|(_, i)| i).collect();
+    kept.sort_unstable();
+    out.extend(kept.into_iter().map(|i| rows[i].clone()));
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn row(pubkey: &str, ts: &str, slot: u64, write_ver: u64) -> String {
+        format!(r#"{{"ts":"{ts}","slot":{slot},"write_ver":{write_ver},"pubkey":"{pubkey}","lamports":1}}"#)
+    }
+
+    #[test]
+    fn table_list_parses_with_transforms() {
+        let tables = parse("wallet_account_updates, wallet_balances_1m:latest_per_minute,cur:latest").unwrap();
+        let got: Vec<_> = tables.iter().map(|t| (t.name.as_str(), t.transform)).collect();
+        assert_eq!(
+            got,
+            [
+                ("wallet_account_updates", Transform::Raw),
+                ("wallet_balances_1m", Transform::LatestPerMinute),
+                ("cur", Transform::Latest),
+            ]
+        );
+        assert!(parse("t:sum").is_err());
+        assert!(parse("db.t").is_err());
+        assert!(parse(" , ").is_err());
+    }
+
+    #[test]
+    fn latest_per_minute_keeps_one_row_per_pubkey_and_minute() {
+        let rows = vec![
+            row("a", "2025-11-13 22:15:01", 10, 1),
+            row("b", "2025-11-13 22:15:02", 10, 2),
+            row("a", "2025-11-13 22:15:40", 11, 1),
+            // same slot, later write: the newer version of a
+            row("a", "2025-11-13 22:15:41", 11, 5),
+            // older than what was seen, though later in the batch
+            row("b", "2025-11-13 22:15:50", 9, 9),
+            row("a", "2025-11-13 22:16:00", 12, 1),
+            "not a row".to_string(),
+        ];
+        assert_eq!(apply(Transform::Raw, &rows), None);
+        let collapsed = apply(Transform::LatestPerMinute, &rows).unwrap();
+        assert_eq!(collapsed, [rows[6].clone(), rows[1].clone(), rows[3].clone(), rows[5].clone()]);
+        let latest = apply(Transform::Latest, &rows).unwrap();
+        assert_eq!(latest, [rows[6].clone(), rows[1].clone(), rows[5].clone()]);
+    }
+}
@@ -0,0 +1,82 @@
+//! Ed25519 payload verification for messages signed by the plugin's
+//! `signing_key_path` (`Ed25519-Signature` header, base64).
+//!
+//! `SIGNING_PUBKEYS` lists the trusted signers (base58, comma-separated). A message
+//! with a signature none of them produced is rejected; one without a signature is
+//! rejected only with `REQUIRE_SIGNATURE=1`.
+
+use anyhow::{Context, Result};
+use base64::{Engine as _, prelude::BASE64_STANDARD};
+use ed25519_dalek::{Signature, VerifyingKey};
+use std::env;
+
+pub struct Verifier {
+    keys: Vec<VerifyingKey>,
+    required: bool,
+}
+
+impl Verifier {
+    /// `None` when neither `SIGNING_PUBKEYS` nor `REQUIRE_SIGNATURE` is set.
+    pub fn from_env() -> Result<Option<Self>> {
+        let required = env::var("REQUIRE_SIGNATURE").map(|s| s == "1" || s.eq_ignore_ascii_case("true")).unwrap_or(false);
+        let mut keys = Vec::new();
+        for key in env::var("SIGNING_PUBKEYS").unwrap_or_default().split(',').map(str::trim).filter(|s| !s.is_empty()) {
+            let bytes = bs58::decode(key).into_vec().with_context(|| format!("SIGNING_PUBKEYS entry {key:?} is not base58"))?;
+            let bytes: [u8; 32] = bytes.try_into().map_err(|_| anyhow::anyhow!("SIGNING_PUBKEYS entry {key:?} is not 32 bytes"))?;
+            keys.push(VerifyingKey::from_bytes(&bytes).with_context(|| format!("SIGNING_PUBKEYS entry {key:?} is not an Ed25519 key"))?);
+        }
+        if keys.is_empty() {
+            anyhow::ensure!(!required, "REQUIRE_SIGNATURE is set but SIGNING_PUBKEYS is empty");
+            return Ok(None);
+        }
+        Ok(Some(Verifier { keys, required }))
+    }
+
+    pub fn describe(&self) -> String {
+        format!("{} trusted signer(s), signature {}", self.keys.len(), if self.required { "required" } else { "optional" })
+    }
+
+    /// `Err(reason)` if the message must be dropped.
+    pub fn check(&self, headers: Option<&async_nats::HeaderMap>, payload: &[u8]) -> Result<(), &'static str> {
+        let Some(sig) = headers.and_then(|h| h.get("Ed25519-Signature")) else {
+            return if self.required { Err("missing Ed25519-Signature") } else { Ok(()) };
+        };
+        let sig = BASE64_STANDARD.decode(sig.as_str().trim()).map_err(|_| "Ed25519-Signature is not base64")?;
+        let sig = Signature::from_slice(&sig).map_err(|_| "Ed25519-Signature is not 64 bytes")?;
+        if self.keys.iter().any(|key| key.verify_strict(payload, &sig).is_ok()) {
+            Ok(())
+        } else {
+            Err("Ed25519-Signature invalid for every trusted signer")
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ed25519_dalek::{Signer, SigningKey};
+
+    use super::*;
+
+    fn signed(key: &SigningKey, payload: &[u8]) -> async_nats::HeaderMap {
+        let mut headers = async_nats::HeaderMap::new();
+        headers.insert("Ed25519-Signature", BASE64_STANDARD.encode(key.sign(payload).to_bytes()).as_str());
+        headers
+    }
+
+    #[test]
+    fn plugin_signatures_verify_and_forgeries_do_not() {
+        let (key, other) = (SigningKey::from_bytes(&[3; 32]), SigningKey::from_bytes(&[4; 32]));
+        let payload = br#"{"pubkey":"a","lamports":5}"#;
+        let verifier = Verifier { keys: vec![key.verifying_key()], required: false };
+        assert_eq!(verifier.check(Some(&signed(&key, payload)), payload), Ok(()));
+        assert!(verifier.check(Some(&signed(&key, payload)), br#"{"pubkey":"a","lamports":6}"#).is_err());
+        assert!(verifier.check(Some(&signed(&other, payload)), payload).is_err());
+        let mut garbled = async_nats::HeaderMap::new();
+        garbled.insert("Ed25519-Signature", "not base64!");
+        assert!(verifier.check(Some(&garbled), payload).is_err());
+        // unsigned messages pass unless a signature is required
+        assert_eq!(verifier.check(None, payload), Ok(()));
+        let strict = Verifier { keys: vec![key.verifying_key()], required: true };
+        assert_eq!(strict.check(None, payload), Err("missing Ed25519-Signature"));
+    }
+}
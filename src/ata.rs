@@ -0,0 +1,60 @@
+//! Associated token account addresses, derived the way the ATA program does:
+//! the program-derived address of `[wallet, token_program, mint]` under
+//! `ATokenGPvbdGVxr1b2hvZbsiqW5xWH25efTNsLJA8knL`.
+
+use curve25519_dalek::edwards::CompressedEdwardsY;
+use sha2::{Digest, Sha256};
+
+/// ATokenGPvbdGVxr1b2hvZbsiqW5xWH25efTNsLJA8knL
+const ASSOCIATED_TOKEN_PROGRAM_ID: [u8; 32] = [
+    140, 151, 37, 143, 78, 36, 137, 241, 187, 61, 16, 41, 20, 142, 13, 131, 11, 90, 19, 153, 218, 255,
+    16, 132, 4, 142, 123, 216, 219, 233, 248, 89,
+];
+
+/// The ATA of `wallet` for `mint` under `token_program` (Token or Token-2022).
+pub(crate) fn associated_token_address(wallet: &[u8; 32], mint: &[u8; 32], token_program: &[u8; 32]) -> [u8; 32] {
+    find_program_address(&[wallet, token_program, mint], &ASSOCIATED_TOKEN_PROGRAM_ID)
+        .expect("a bump seed exists for all but a negligible fraction of seed sets")
+}
+
+/// `Pubkey::find_program_address`: the first bump from 255 down whose address is
+/// off the ed25519 curve (so no private key can exist for it).
+fn find_program_address(seeds: &[&[u8; 32]], program_id: &[u8; 32]) -> Option<[u8; 32]> {
+    (0..=u8::MAX).rev().find_map(|bump| {
+        let mut hasher = Sha256::new();
+        for seed in seeds {
+            hasher.update(seed);
+        }
+        hasher.update([bump]);
+        hasher.update(program_id);
+        hasher.update(b"ProgramDerivedAddress");
+        let address: [u8; 32] = hasher.finalize().into();
+        CompressedEdwardsY(address).decompress().is_none().then_some(address)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::decode::{TOKEN_2022_PROGRAM_ID, TOKEN_PROGRAM_ID};
+
+    fn key(base58: &str) -> [u8; 32] {
+        bs58::decode(base58).into_vec().unwrap().try_into().unwrap()
+    }
+
+    #[test]
+    fn derives_the_known_usdc_accounts_of_a_wallet() {
+        let wallet = key("9xQeWvG816bUx9EPjHmaT23yvVM2ZWbrrpZb9PusVFin");
+        let usdc = key("EPjFWdd5AufqSSqeM2qN1xzybapC8G4wEGGkZwyTDt1v");
+        assert_eq!(
+            associated_token_address(&wallet, &usdc, &TOKEN_PROGRAM_ID),
+            key("F4YA4H7HeXLCvjLRKdh56FgE4cyHpPqLP1VCM6fEqEmX")
+        );
+        // the token program is a seed: Token-2022 gives another address
+        assert_eq!(
+            associated_token_address(&wallet, &usdc, &TOKEN_2022_PROGRAM_ID),
+            key("D8jEyFGKCYMSSieSQykhVgbZRZunSditY2PQa7oJt1tu")
+        );
+        assert_eq!(ASSOCIATED_TOKEN_PROGRAM_ID, key("ATokenGPvbdGVxr1b2hvZbsiqW5xWH25efTNsLJA8knL"));
+    }
+}
@@ -0,0 +1,135 @@
+//! Publish-path benchmark: synthetic `update_account` calls against a
+//! counting [`MemorySink`], so matching + serialization is measured without NATS.
+//!
+//!     cargo run --release --bin bench
+//!
+//! Environment:
+//!   BENCH_ROWS     updates to send (default 1000000)
+//!   BENCH_RATE     target updates/sec, 0 = as fast as possible (default 0)
+//!   BENCH_ACCOUNTS distinct synthetic pubkeys (default 10000)
+//!   BENCH_DATA     account data bytes (default 165, an SPL token account)
+//!   BENCH_CONFIG   geyser config to load (default: match the synthetic owner, no logging)
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::env;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+use solana_geyser_wallet_indexer::geyser_plugin_interface::{
+    GeyserPlugin, ReplicaAccountInfoV3, ReplicaAccountInfoVersions,
+};
+use solana_geyser_wallet_indexer::{LoggerPlugin, MemorySink};
+
+/// Counts allocations so the report can show allocations per row.
+struct CountingAlloc;
+
+static ALLOCATIONS: AtomicU64 = AtomicU64::new(0);
+
+unsafe impl GlobalAlloc for CountingAlloc {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        // SAFETY: forwarded unchanged to the system allocator
+        unsafe { System.alloc(layout) }
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        // SAFETY: ptr was allocated by System with this layout
+        unsafe { System.dealloc(ptr, layout) }
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        // SAFETY: ptr was allocated by System with this layout
+        unsafe { System.realloc(ptr, layout, new_size) }
+    }
+}
+
+#[global_allocator]
+static GLOBAL: CountingAlloc = CountingAlloc;
+
+fn env_or<T: std::str::FromStr>(name: &str, default: T) -> T {
+    env::var(name).ok().and_then(|s| s.parse().ok()).unwrap_or(default)
+}
+
+fn main() {
+    let rows: usize = env_or("BENCH_ROWS", 1_000_000);
+    let rate: u64 = env_or("BENCH_RATE", 0);
+    let accounts: usize = env_or("BENCH_ACCOUNTS", 10_000).max(1);
+    let data_len: usize = env_or("BENCH_DATA", 165);
+
+    let owner = [7u8; 32];
+    let pubkeys: Vec<[u8; 32]> = (0..accounts as u64)
+        .map(|i| {
+            let mut key = [0u8; 32];
+            key[..8].copy_from_slice(&i.to_le_bytes());
+            key[8..16].copy_from_slice(&i.wrapping_mul(0x9e37_79b9_7f4a_7c15).to_le_bytes());
+            key
+        })
+        .collect();
+    let data = vec![0xabu8; data_len];
+
+    let config = match env::var("BENCH_CONFIG") {
+        Ok(path) => path,
+        Err(_) => {
+            let path = env::temp_dir().join("geyser-bench-config.json");
+            let json = serde_json::json!({
+                "libpath": "",
+                "params": {
+                    "target_owners": [bs58::encode(owner).into_string()],
+                    "account_log_sample_rate": 0,
+                }
+            });
+            std::fs::write(&path, json.to_string()).expect("write bench config");
+            path.to_string_lossy().into_owned()
+        }
+    };
+
+    let sink = Arc::new(MemorySink::counting());
+    let mut plugin = LoggerPlugin::new();
+    if let Err(e) = plugin.load_with_sink(&config, sink.clone()) {
+        eprintln!("bench: cannot load {config}: {e}");
+        std::process::exit(1);
+    }
+
+    let mut latencies: Vec<u64> = Vec::with_capacity(rows);
+    let interval = (rate > 0).then(|| Duration::from_secs_f64(1.0 / rate as f64));
+    let allocs_before = ALLOCATIONS.load(Ordering::Relaxed);
+    let start = Instant::now();
+    for i in 0..rows {
+        if let Some(interval) = interval {
+            let due = start + interval * i as u32;
+            while Instant::now() < due {
+                std::hint::spin_loop();
+            }
+        }
+        let info = ReplicaAccountInfoV3 {
+            pubkey: &pubkeys[i % accounts],
+            lamports: 2_039_280 + i as u64,
+            owner: &owner,
+            executable: false,
+            rent_epoch: u64::MAX,
+            data: &data,
+            write_version: i as u64,
+            txn: None,
+        };
+        let t = Instant::now();
+        let _ = plugin.update_account(ReplicaAccountInfoVersions::V0_0_3(&info), 1_000 + (i / 1_000) as u64, false);
+        latencies.push(t.elapsed().as_nanos() as u64);
+    }
+    let elapsed = start.elapsed();
+    let allocs = ALLOCATIONS.load(Ordering::Relaxed) - allocs_before;
+    let (messages, bytes) = sink.stats();
+    plugin.on_unload();
+
+    latencies.sort_unstable();
+    let pct = |p: usize| latencies.get((latencies.len() * p / 100).min(latencies.len().saturating_sub(1))).copied().unwrap_or(0);
+    println!(
+        "{rows} updates in {elapsed:.2?}: {:.0} rows/s, p50={}ns p99={}ns max={}ns, {:.1} allocs/row, {messages} messages / {bytes} bytes published",
+        rows as f64 / elapsed.as_secs_f64(),
+        pct(50),
+        pct(99),
+        latencies.last().copied().unwrap_or(0),
+        allocs as f64 / rows.max(1) as f64,
+    );
+}
@@ -0,0 +1,22 @@
+//! Print the ClickHouse `CREATE TABLE` matching the rows this plugin publishes.
+//!
+//!     cargo run --bin ddl -- [geyser-config.json] [table]
+//!
+//! With a config, only the columns it enables (and its `fields` allow-list)
+//! are included; without one, every `Row` column. The table defaults to
+//! `wallet_account_updates`, the ingestor's `CH_TABLE` default.
+
+use std::env;
+
+fn main() {
+    let mut args = env::args().skip(1);
+    let config = args.next().filter(|a| a != "-");
+    let table = args.next().unwrap_or_else(|| "wallet_account_updates".to_string());
+    match solana_geyser_wallet_indexer::clickhouse_ddl(config.as_deref(), &table) {
+        Ok(ddl) => print!("{ddl}"),
+        Err(e) => {
+            eprintln!("ddl: {e}");
+            std::process::exit(1);
+        }
+    }
+}
@@ -0,0 +1,98 @@
+//! Republish recorded rows to NATS, to reproduce a production stream against a
+//! test ingestor/ClickHouse. Input is newline-delimited JSON rows, e.g. a file
+//! written by the ingestor's `RECORD_PATH` or the plugin's file sink; one line is
+//! one message. Gzip files (`file_sink_compress`) are decompressed on the fly.
+//!
+//!     cargo run --release --bin replay -- wallet-updates.jsonl
+//!
+//! Environment:
+//!   NATS_URL     server to publish to (default nats://127.0.0.1:4222)
+//!   NATS_SUBJECT subject to publish on (default WALLET.updates)
+//!   REPLAY_RATE  target rows/sec, 0 = as fast as possible (default 0)
+//!   REPLAY_LOOP  replay the file this many times (default 1)
+
+use std::env;
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+use std::time::{Duration, Instant};
+
+fn env_or<T: std::str::FromStr>(name: &str, default: T) -> T {
+    env::var(name).ok().and_then(|s| s.parse().ok()).unwrap_or(default)
+}
+
+fn main() {
+    let Some(path) = env::args().nth(1) else {
+        eprintln!("usage: replay <recorded.jsonl>");
+        std::process::exit(2);
+    };
+    let url = env::var("NATS_URL").unwrap_or_else(|_| "nats://127.0.0.1:4222".into());
+    let subject = env::var("NATS_SUBJECT").unwrap_or_else(|_| "WALLET.updates".into());
+    let rate: u64 = env_or("REPLAY_RATE", 0);
+    let loops: u64 = env_or("REPLAY_LOOP", 1).max(1);
+
+    let conn = match nats::connect(&url) {
+        Ok(conn) => conn,
+        Err(e) => {
+            eprintln!("replay: cannot connect to {url}: {e}");
+            std::process::exit(1);
+        }
+    };
+
+    let interval = (rate > 0).then(|| Duration::from_secs_f64(1.0 / rate as f64));
+    let start = Instant::now();
+    let (mut sent, mut errors) = (0u64, 0u64);
+    for _ in 0..loops {
+        let file = match File::open(&path) {
+            Ok(file) => file,
+            Err(e) => {
+                eprintln!("replay: cannot open {path}: {e}");
+                std::process::exit(1);
+            }
+        };
+        let mut input = BufReader::new(file);
+        // gzip magic rather than the extension, so renamed files still work
+        let gzip = input.fill_buf().is_ok_and(|head| head.starts_with(&[0x1f, 0x8b]));
+        let input: Box<dyn BufRead> = if gzip {
+            Box::new(BufReader::new(flate2::read::MultiGzDecoder::new(input)))
+        } else {
+            Box::new(input)
+        };
+        for line in input.lines() {
+            let line = match line {
+                Ok(line) => line,
+                Err(e) => {
+                    // e.g. the unfinished gzip member of a file sink that didn't shut down cleanly
+                    eprintln!("replay: read error in {path}: {e}");
+                    break;
+                }
+            };
+            if line.trim().is_empty() {
+                continue;
+            }
+            if let Some(interval) = interval {
+                let due = start + interval.mul_f64(sent as f64);
+                let now = Instant::now();
+                if due > now {
+                    std::thread::sleep(due - now);
+                }
+            }
+            match conn.publish(&subject, line.as_bytes()) {
+                Ok(()) => sent += 1,
+                Err(e) => {
+                    errors += 1;
+                    if errors.is_power_of_two() {
+                        eprintln!("replay: publish failed: {e} ({errors} so far)");
+                    }
+                }
+            }
+        }
+    }
+    if let Err(e) = conn.flush() {
+        eprintln!("replay: final flush failed: {e}");
+    }
+    let elapsed = start.elapsed();
+    println!(
+        "replayed {sent} rows to {subject} in {elapsed:.2?} ({:.0} rows/s), {errors} publish errors",
+        sent as f64 / elapsed.as_secs_f64().max(f64::EPSILON),
+    );
+}
@@ -0,0 +1,57 @@
+//! Where row timestamps come from (`ts`, `slot_time`), and how they are formatted.
+//! The plugin reads [`RealClock`]; embedders and tests can swap in a [`FixedClock`]
+//! with [`LoggerPlugin::set_clock`](crate::LoggerPlugin::set_clock) so produced rows
+//! are reproducible.
+
+use std::fmt;
+
+use chrono::{DateTime, Utc};
+
+/// A source of wall-clock time.
+pub trait Clock: Send + Sync + fmt::Debug {
+    fn now(&self) -> DateTime<Utc>;
+}
+
+/// The system clock (the default).
+#[derive(Clone, Copy, Debug, Default)]
+pub struct RealClock;
+
+impl Clock for RealClock {
+    fn now(&self) -> DateTime<Utc> {
+        Utc::now()
+    }
+}
+
+/// Always the same instant.
+#[derive(Clone, Copy, Debug)]
+pub struct FixedClock(pub DateTime<Utc>);
+
+impl Clock for FixedClock {
+    fn now(&self) -> DateTime<Utc> {
+        self.0
+    }
+}
+
+/// A row timestamp, e.g. "2025-11-13 22:15:33", in `tz` (UTC if `None`).
+pub(crate) fn format_ts(at: DateTime<Utc>, tz: Option<chrono_tz::Tz>) -> String {
+    const FORMAT: &str = "%Y-%m-%d %H:%M:%S";
+    match tz {
+        Some(tz) => at.with_timezone(&tz).format(FORMAT).to_string(),
+        None => at.format(FORMAT).to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeZone;
+
+    use super::*;
+
+    #[test]
+    fn one_instant_formats_in_each_zone() {
+        let at = Utc.with_ymd_and_hms(2025, 11, 13, 22, 15, 33).unwrap();
+        assert_eq!(format_ts(at, None), "2025-11-13 22:15:33");
+        assert_eq!(format_ts(at, Some(chrono_tz::Europe::Berlin)), "2025-11-13 23:15:33");
+        assert_eq!(format_ts(at, Some(chrono_tz::Asia::Tokyo)), "2025-11-14 07:15:33");
+    }
+}
@@ -0,0 +1,111 @@
+//! `control_subject`: commands for a running plugin over NATS, on a connection of its
+//! own (so it works with any sink). Messages are JSON `{"cmd": "..."}`:
+//!
+//! - `reset_state`: forget the tracked-account state (close/deposit deltas, change
+//!   filter, write_version and identical-row caches). Applied on the next slot-status
+//!   callback, so it never races an update that is being processed.
+//!
+//! There is no authentication beyond the NATS server's: anyone allowed to publish to
+//! the subject can send commands, hence `control_allow_reset`. Restrict the subject
+//! with NATS permissions. Requests with a reply subject get `{"ok":...}` back.
+
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use serde::Deserialize;
+
+use crate::publisher::ConnectSpec;
+
+#[derive(Deserialize)]
+struct Command {
+    cmd: String,
+}
+
+/// Background listener thread; stopped and joined by `on_unload`.
+#[derive(Debug)]
+pub(crate) struct ControlListener {
+    stop: Arc<AtomicBool>,
+    handle: JoinHandle<()>,
+}
+
+impl ControlListener {
+    /// Connect (retrying in the background until it works) and subscribe to
+    /// `subject`; a `reset_state` command sets `reset`.
+    pub(crate) fn start(spec: ConnectSpec, subject: String, reset: Arc<AtomicBool>) -> std::io::Result<Self> {
+        let stop = Arc::new(AtomicBool::new(false));
+        let flag = stop.clone();
+        let handle = thread::Builder::new().name("control".into()).spawn(move || listen(&spec, &subject, &reset, &flag))?;
+        Ok(ControlListener { stop, handle })
+    }
+
+    pub(crate) fn stop(self) {
+        self.stop.store(true, Ordering::SeqCst);
+        if self.handle.join().is_err() {
+            eprintln!("[PLUGIN] WARNING: control thread panicked");
+        }
+    }
+}
+
+// how long a blocked wait may delay stop()
+pub(crate) const POLL: Duration = Duration::from_millis(500);
+
+/// Connect and subscribe to `subject`, retrying with backoff until it works; `None`
+/// if `stop` is set first. `what` names the listener in log lines.
+pub(crate) fn subscribe(
+    spec: &ConnectSpec,
+    subject: &str,
+    stop: &AtomicBool,
+    what: &str,
+) -> Option<(nats::Connection, nats::Subscription)> {
+    let mut backoff = Duration::from_secs(1);
+    loop {
+        let attempt = spec.connect_with_retry(1, backoff).and_then(|conn| conn.subscribe(subject).map(|sub| (conn, sub)));
+        match attempt {
+            Ok(sub) => return Some(sub),
+            Err(e) => {
+                eprintln!("[PLUGIN] {what} connect to {} failed: {e}; retrying in {backoff:?}", spec.url);
+                let deadline = Instant::now() + backoff;
+                while Instant::now() < deadline {
+                    if stop.load(Ordering::SeqCst) {
+                        return None;
+                    }
+                    thread::sleep(POLL);
+                }
+                backoff = (backoff * 2).min(Duration::from_secs(30));
+            }
+        }
+    }
+}
+
+fn listen(spec: &ConnectSpec, subject: &str, reset: &AtomicBool, stop: &AtomicBool) {
+    let Some((conn, sub)) = subscribe(spec, subject, stop, "control") else { return };
+    eprintln!("[PLUGIN] listening for control commands on {subject}");
+    while !stop.load(Ordering::SeqCst) {
+        // timeouts (and errors while the client reconnects) just mean: poll again
+        let Ok(msg) = sub.next_timeout(POLL) else { continue };
+        let reply = match serde_json::from_slice::<Command>(&msg.data) {
+            Ok(c) if c.cmd == "reset_state" => {
+                eprintln!("[PLUGIN] control: reset_state requested");
+                reset.store(true, Ordering::SeqCst);
+                r#"{"ok":true}"#.to_string()
+            }
+            Ok(c) => {
+                eprintln!("[PLUGIN] WARNING: control: unknown command {:?}", c.cmd);
+                serde_json::json!({ "ok": false, "error": format!("unknown command {:?}", c.cmd) }).to_string()
+            }
+            Err(e) => {
+                eprintln!("[PLUGIN] WARNING: control: unreadable message on {subject}: {e}");
+                serde_json::json!({ "ok": false, "error": format!("expected {{\"cmd\": ...}}: {e}") }).to_string()
+            }
+        };
+        if msg.reply.is_some()
+            && let Err(e) = msg.respond(reply)
+        {
+            eprintln!("[PLUGIN] WARNING: control reply failed: {e}");
+        }
+    }
+    drop(sub);
+    conn.close();
+}
@@ -0,0 +1,274 @@
+//! Fixed-offset decoders for well-known account layouts, so a row can carry the
+//! fields a dashboard wants without pulling in the program crates. Each decoder
+//! checks the account size and state tag first and returns `None` for anything else.
+
+/// 11111111111111111111111111111111, the owner of plain (native SOL) wallets
+pub(crate) const SYSTEM_PROGRAM_ID: [u8; 32] = [0; 32];
+
+/// Stake11111111111111111111111111111111111111
+pub(crate) const STAKE_PROGRAM_ID: [u8; 32] = [
+    6, 161, 216, 23, 145, 55, 84, 42, 152, 52, 55, 189, 254, 42, 122, 178, 85, 127, 83, 92, 138, 120,
+    114, 43, 104, 164, 157, 192, 0, 0, 0, 0,
+];
+
+/// TokenkegQfeZyiNwAJbNbGKPFXCWuBvf9Ss623VQ5DA
+pub(crate) const TOKEN_PROGRAM_ID: [u8; 32] = [
+    6, 221, 246, 225, 215, 101, 161, 147, 217, 203, 225, 70, 206, 235, 121, 172, 28, 180, 133, 237, 95,
+    91, 55, 145, 58, 140, 245, 133, 126, 255, 0, 169,
+];
+
+/// TokenzQdBNbLqP5VEhdkAS6EPFLC1PHnBqCXEpPxuEb (Token-2022)
+pub(crate) const TOKEN_2022_PROGRAM_ID: [u8; 32] = [
+    6, 221, 246, 225, 238, 117, 143, 222, 24, 66, 93, 188, 228, 108, 205, 218, 182, 26, 252, 77, 131,
+    185, 13, 39, 254, 189, 249, 40, 216, 161, 139, 252,
+];
+
+// StakeStateV2 (bincode): u32 tag, Meta (120 bytes), then for tag 2 the Stake's
+// Delegation: voter (32), stake (u64), activation_epoch (u64), deactivation_epoch (u64).
+const STAKE_ACCOUNT_LEN: usize = 200;
+const STAKE_TAG_DELEGATED: u32 = 2;
+const DELEGATION_OFFSET: usize = 4 + 120;
+
+/// The delegation of an active (or deactivating) stake account.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct StakeDelegation {
+    pub voter: [u8; 32],
+    pub stake: u64,
+    pub activation_epoch: u64,
+    /// u64::MAX while the stake has not been deactivated
+    pub deactivation_epoch: u64,
+}
+
+/// Decode a Stake-program account; `None` unless it is in the `Stake` state.
+pub(crate) fn stake(data: &[u8]) -> Option<StakeDelegation> {
+    if data.len() != STAKE_ACCOUNT_LEN || u32_at(data, 0)? != STAKE_TAG_DELEGATED {
+        return None;
+    }
+    let d = DELEGATION_OFFSET;
+    Some(StakeDelegation {
+        voter: data.get(d..d + 32)?.try_into().ok()?,
+        stake: u64_at(data, d + 32)?,
+        activation_epoch: u64_at(data, d + 40)?,
+        deactivation_epoch: u64_at(data, d + 48)?,
+    })
+}
+
+/// Vote111111111111111111111111111111111111111
+pub(crate) const VOTE_PROGRAM_ID: [u8; 32] = [
+    7, 97, 72, 29, 53, 116, 116, 187, 124, 77, 118, 36, 235, 211, 189, 179, 216, 53, 94, 115, 209, 16,
+    67, 252, 13, 163, 83, 128, 0, 0, 0, 0,
+];
+
+// VoteStateVersions (bincode): u32 tag, then the variable-length state:
+//   0 V0_23_5:  node (32), authorized_voter (32), its epoch (u64), prior_voters (32 x 56 + u64),
+//               withdrawer (32), commission (u8), votes (u64 len, 12 bytes each), root, epoch_credits
+//   1 V1_14_11: node (32), withdrawer (32), commission (u8), votes (u64 len, 12 bytes each),
+//               root (Option<u64>), authorized_voters (u64 len, 40 bytes each),
+//               prior_voters (32 x 48 + u64 + bool), epoch_credits (u64 len, 24 bytes each)
+//   2 V3:       as V1_14_11, but each vote is a LandedVote: latency (u8) first, 13 bytes
+//   3 V4:       node, withdrawer, two reward collectors (32 each), inflation and block revenue
+//               commissions (u16 bps each), pending rewards (u64), Option<BLS key (48)>, then
+//               as V3 without prior_voters
+const VOTE_PRIOR_VOTERS_0_23_5: usize = 32 * 56 + 8;
+const VOTE_PRIOR_VOTERS: usize = 32 * 48 + 8 + 1;
+const VOTE_V4_BLS_OFFSET: usize = 4 + 4 * 32 + 2 + 2 + 8;
+
+/// The performance-relevant part of a vote account.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct VoteAccount {
+    pub node_pubkey: [u8; 32],
+    /// percent; V4 accounts keep basis points, rounded down here
+    pub commission: u8,
+    /// None before the first vote
+    pub last_vote_slot: Option<u64>,
+    /// lifetime credits, from the latest epoch_credits entry (0 before any)
+    pub credits: u64,
+}
+
+/// Decode a Vote-program account of any known version; `None` for uninitialized
+/// accounts, newer versions and anything that doesn't parse.
+pub(crate) fn vote(data: &[u8]) -> Option<VoteAccount> {
+    let tag = u32_at(data, 0)?;
+    let node_pubkey: [u8; 32] = data.get(4..36)?.try_into().ok()?;
+    // an uninitialized account is all zeroes
+    if node_pubkey == [0; 32] {
+        return None;
+    }
+    // (commission, offset of the votes, bytes per vote)
+    let (commission, mut at, vote_len) = match tag {
+        0 => {
+            let c = 4 + 32 + 32 + 8 + VOTE_PRIOR_VOTERS_0_23_5 + 32;
+            (*data.get(c)?, c + 1, 12)
+        }
+        1 => (*data.get(68)?, 69, 12),
+        2 => (*data.get(68)?, 69, 13),
+        3 => {
+            let bps = u16::from_le_bytes(data.get(4 + 4 * 32..4 + 4 * 32 + 2)?.try_into().ok()?);
+            let bls = match *data.get(VOTE_V4_BLS_OFFSET)? {
+                0 => 1,
+                1 => 1 + 48,
+                _ => return None,
+            };
+            (u8::try_from(bps / 100).ok()?, VOTE_V4_BLS_OFFSET + bls, 13)
+        }
+        _ => return None,
+    };
+    let votes = usize::try_from(u64_at(data, at)?).ok()?;
+    at += 8;
+    let votes_end = votes.checked_mul(vote_len)?.checked_add(at)?;
+    // a LandedVote's slot follows its latency byte
+    let slot_in_vote = if vote_len == 13 { 1 } else { 0 };
+    let last_vote_slot = match votes {
+        0 => None,
+        n => Some(u64_at(data, at + (n - 1) * vote_len + slot_in_vote)?),
+    };
+    at = votes_end;
+    // root_slot: Option<u64>
+    at += match *data.get(at)? {
+        0 => 1,
+        1 => 9,
+        _ => return None,
+    };
+    if tag != 0 {
+        let voters = usize::try_from(u64_at(data, at)?).ok()?;
+        at = voters.checked_mul(40)?.checked_add(at + 8)?;
+    }
+    if tag == 1 || tag == 2 {
+        at += VOTE_PRIOR_VOTERS;
+    }
+    let entries = usize::try_from(u64_at(data, at)?).ok()?;
+    at += 8;
+    let credits = match entries {
+        0 => 0,
+        n => u64_at(data, at.checked_add((n - 1).checked_mul(24)?)?.checked_add(8)?)?,
+    };
+    Some(VoteAccount { node_pubkey, commission, last_vote_slot, credits })
+}
+
+// SPL token Account: mint (32), owner (32), amount (u64), delegate (36), state (u8)
+// at 108, ... 165 bytes. Token-2022 accounts with extensions are longer and carry
+// AccountType::Account (2) at byte 165; mints are 82 bytes (or type 1), so never match.
+const TOKEN_ACCOUNT_LEN: usize = 165;
+const TOKEN_STATE_OFFSET: usize = 108;
+const TOKEN_ACCOUNT_TYPE_ACCOUNT: u8 = 2;
+
+/// The balance part of an initialized token account.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct TokenAccount {
+    pub mint: [u8; 32],
+    pub owner: [u8; 32],
+    /// raw amount in base units of the mint
+    pub amount: u64,
+}
+
+/// Whether `owner` is the Token or Token-2022 program.
+pub(crate) fn is_token_program(owner: &[u8]) -> bool {
+    owner == TOKEN_PROGRAM_ID || owner == TOKEN_2022_PROGRAM_ID
+}
+
+/// Decode a token account owned by either token program; `None` for mints,
+/// uninitialized accounts and anything of the wrong size.
+pub(crate) fn token_account(data: &[u8]) -> Option<TokenAccount> {
+    let is_account = data.len() == TOKEN_ACCOUNT_LEN
+        || (data.len() > TOKEN_ACCOUNT_LEN && data[TOKEN_ACCOUNT_LEN] == TOKEN_ACCOUNT_TYPE_ACCOUNT);
+    // state 0 = uninitialized; 1 initialized and 2 frozen both hold a balance
+    if !is_account || *data.get(TOKEN_STATE_OFFSET)? == 0 {
+        return None;
+    }
+    Some(TokenAccount {
+        mint: data.get(0..32)?.try_into().ok()?,
+        owner: data.get(32..64)?.try_into().ok()?,
+        amount: u64_at(data, 64)?,
+    })
+}
+
+/// `amount` scaled by the mint's decimals, as the wallets display it.
+pub(crate) fn ui_amount(amount: u64, decimals: u8) -> f64 {
+    amount as f64 / 10f64.powi(i32::from(decimals))
+}
+
+fn u32_at(data: &[u8], at: usize) -> Option<u32> {
+    Some(u32::from_le_bytes(data.get(at..at + 4)?.try_into().ok()?))
+}
+
+fn u64_at(data: &[u8], at: usize) -> Option<u64> {
+    Some(u64::from_le_bytes(data.get(at..at + 8)?.try_into().ok()?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // a delegated stake account as the Stake program lays it out: Meta (rent reserve,
+    // staker, withdrawer, lockup), then the Stake's delegation and credits_observed
+    fn stake_account(tag: u32) -> Vec<u8> {
+        let mut data = Vec::with_capacity(STAKE_ACCOUNT_LEN);
+        data.extend_from_slice(&tag.to_le_bytes());
+        data.extend_from_slice(&2_282_880u64.to_le_bytes());
+        data.extend_from_slice(&[1; 32]);
+        data.extend_from_slice(&[2; 32]);
+        data.extend_from_slice(&[0; 8 + 8]);
+        data.extend_from_slice(&[3; 32]);
+        data.extend_from_slice(&[4; 32]);
+        data.extend_from_slice(&1_000_000_000u64.to_le_bytes());
+        data.extend_from_slice(&590u64.to_le_bytes());
+        data.extend_from_slice(&u64::MAX.to_le_bytes());
+        // legacy warmup_cooldown_rate (f64), credits_observed, stake flags
+        data.extend_from_slice(&0.25f64.to_le_bytes());
+        data.extend_from_slice(&123_456u64.to_le_bytes());
+        data.push(0);
+        data.resize(STAKE_ACCOUNT_LEN, 0);
+        data
+    }
+
+    #[test]
+    fn stake_fixture_decodes_its_delegation() {
+        assert_eq!(
+            stake(&stake_account(STAKE_TAG_DELEGATED)),
+            Some(StakeDelegation { voter: [4; 32], stake: 1_000_000_000, activation_epoch: 590, deactivation_epoch: u64::MAX })
+        );
+        // Initialized (undelegated) accounts and other sizes carry no delegation
+        assert_eq!(stake(&stake_account(1)), None);
+        assert_eq!(stake(&stake_account(STAKE_TAG_DELEGATED)[..199]), None);
+        assert_eq!(stake(&[]), None);
+    }
+
+    // a voting validator's account as the Vote program serializes it
+    fn vote_state() -> solana_vote_interface::state::VoteStateV3 {
+        use solana_vote_interface::state::{LandedVote, Lockout, VoteStateV3};
+        VoteStateV3 {
+            node_pubkey: [5; 32].into(),
+            authorized_withdrawer: [6; 32].into(),
+            commission: 7,
+            votes: [312_000_090, 312_000_091]
+                .into_iter()
+                .map(|slot| LandedVote { latency: 1, lockout: Lockout::new(slot) })
+                .collect(),
+            root_slot: Some(312_000_059),
+            epoch_credits: vec![(721, 1_000, 0), (722, 5_432, 1_000)],
+            ..VoteStateV3::default()
+        }
+    }
+
+    #[test]
+    fn vote_fixtures_decode_in_every_version() {
+        use solana_vote_interface::state::{VoteState1_14_11, VoteStateVersions};
+        let expected = VoteAccount { node_pubkey: [5; 32], commission: 7, last_vote_slot: Some(312_000_091), credits: 5_432 };
+        let v3 = bincode::serialize(&VoteStateVersions::new_v3(vote_state())).unwrap();
+        assert_eq!(vote(&v3), Some(expected));
+        let v1_14_11 = VoteStateVersions::V1_14_11(Box::new(VoteState1_14_11::from(vote_state())));
+        assert_eq!(vote(&bincode::serialize(&v1_14_11).unwrap()), Some(expected));
+        // accounts are allocated at full size, so trailing zeroes are normal
+        let mut padded = v3.clone();
+        padded.resize(3762, 0);
+        assert_eq!(vote(&padded), Some(expected));
+
+        let fresh = solana_vote_interface::state::VoteStateV3 { votes: Default::default(), epoch_credits: vec![], ..vote_state() };
+        let fresh = bincode::serialize(&VoteStateVersions::new_v3(fresh)).unwrap();
+        assert_eq!(vote(&fresh), Some(VoteAccount { last_vote_slot: None, credits: 0, ..expected }));
+        // uninitialized, unknown versions and cut-off accounts don't parse
+        assert_eq!(vote(&[0; 3762]), None);
+        assert_eq!(vote(&[[9, 0, 0, 0].as_slice(), &v3[4..]].concat()), None);
+        assert_eq!(vote(&v3[..v3.len() - 30]), None);
+    }
+}
@@ -0,0 +1,114 @@
+//! `diagnostics_subject`: notable errors as JSON events, next to their stderr line,
+//! so monitoring can alert on them:
+//!
+//! ```json
+//! {"ts": "2025-11-13 22:15:33", "level": "error", "kind": "publish_error", "detail": "...", "suppressed": 12}
+//! ```
+//!
+//! Events go through the installed sink like any other side subject. At most one
+//! per kind per second is sent (`suppressed` counts the ones swallowed since), and a
+//! failure while sending one is never reported again, so a NATS outage produces a
+//! trickle of events instead of feeding on itself.
+
+use std::cell::Cell;
+use std::sync::RwLock;
+use std::time::Duration;
+
+use serde::Serialize;
+
+use crate::clock::format_ts;
+use crate::publisher;
+use crate::state::RateLimit;
+
+static SUBJECT: RwLock<Option<String>> = RwLock::new(None);
+
+const EVERY: Duration = Duration::from_secs(1);
+static LIMITS: [RateLimit; Kind::COUNT] = [const { RateLimit::new(EVERY) }; Kind::COUNT];
+
+thread_local! {
+    // set while this thread publishes an event, whose own failures must not report
+    static REPORTING: Cell<bool> = const { Cell::new(false) };
+}
+
+#[derive(Clone, Copy)]
+pub(crate) enum Kind {
+    /// a NATS publish failed
+    PublishError,
+    /// a NATS flush did not complete in time (the server is slow or gone)
+    FlushTimeout,
+    /// a sink dropped messages (queue full, retries exhausted, no worker)
+    Dropped,
+    /// a row could not be serialized
+    EncodeError,
+    /// an account that should decode (a tracked token account) did not
+    DecodeError,
+    /// a callback, worker or sink panicked
+    Panic,
+    /// the filter script failed or ran out of time
+    ScriptError,
+}
+
+impl Kind {
+    const COUNT: usize = 7;
+
+    fn as_str(self) -> &'static str {
+        match self {
+            Kind::PublishError => "publish_error",
+            Kind::FlushTimeout => "flush_timeout",
+            Kind::Dropped => "dropped_messages",
+            Kind::EncodeError => "encode_error",
+            Kind::DecodeError => "decode_error",
+            Kind::Panic => "panic",
+            Kind::ScriptError => "script_error",
+        }
+    }
+
+    fn level(self) -> &'static str {
+        match self {
+            Kind::FlushTimeout | Kind::Dropped | Kind::DecodeError | Kind::ScriptError => "warning",
+            Kind::PublishError | Kind::EncodeError | Kind::Panic => "error",
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct DiagnosticEvent<'a> {
+    ts: String,
+    level: &'static str,
+    kind: &'static str,
+    detail: &'a str,
+    #[serde(skip_serializing_if = "is_zero")]
+    suppressed: u64,
+}
+
+fn is_zero(n: &u64) -> bool {
+    *n == 0
+}
+
+/// Start (`Some`) or stop (`None`) sending events; set by `on_load`, cleared by `on_unload`.
+pub(crate) fn set_subject(subject: Option<String>) {
+    *SUBJECT.write().unwrap_or_else(|e| e.into_inner()) = subject;
+}
+
+/// Send a `kind` event unless events are off or rate-limited; `detail` is only built
+/// when one is sent.
+pub(crate) fn report(kind: Kind, detail: impl FnOnce() -> String) {
+    if REPORTING.get() {
+        return;
+    }
+    let subject = SUBJECT.read().unwrap_or_else(|e| e.into_inner());
+    let Some(subject) = subject.as_deref() else { return };
+    let Some(suppressed) = LIMITS[kind as usize].allow() else { return };
+    let detail = detail();
+    let event = DiagnosticEvent {
+        ts: format_ts(chrono::Utc::now(), None),
+        level: kind.level(),
+        kind: kind.as_str(),
+        detail: &detail,
+        suppressed,
+    };
+    let Ok(json) = serde_json::to_vec(&event) else { return };
+    REPORTING.set(true);
+    publisher::publish_to(subject, &json);
+    REPORTING.set(false);
+}
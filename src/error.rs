@@ -0,0 +1,45 @@
+//! Errors surfaced at the config boundary (`on_load`, [`crate::validate_config`]).
+
+use std::{error::Error, fmt, io};
+
+/// Why a plugin config could not be loaded.
+#[derive(Debug)]
+pub enum ConfigError {
+    /// The config file could not be read.
+    ReadFailed { path: String, source: io::Error },
+    /// The file is not valid JSON or doesn't match the expected shape.
+    InvalidJson(serde_json::Error),
+    /// A pubkey option is not a 32-byte base58 key.
+    InvalidPubkey { field: &'static str, value: String, reason: String },
+    /// Any other option has an invalid value.
+    InvalidOption { field: &'static str, reason: String },
+    /// The initial NATS connection (including the TLS handshake) failed.
+    NatsConnectFailed { url: String, source: io::Error },
+    /// A non-NATS sink (e.g. the file sink) could not be opened.
+    SinkOpenFailed { sink: &'static str, target: String, source: io::Error },
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigError::ReadFailed { path, source } => write!(f, "Cannot read config file {path}: {source}"),
+            ConfigError::InvalidJson(e) => write!(f, "Invalid geyser config JSON: {e}"),
+            ConfigError::InvalidPubkey { field, value, reason } => write!(f, "Invalid pubkey in {field} ({value}): {reason}"),
+            ConfigError::InvalidOption { field, reason } => write!(f, "Invalid {field}: {reason}"),
+            ConfigError::NatsConnectFailed { url, source } => write!(f, "Failed to connect to NATS at {url}: {source}"),
+            ConfigError::SinkOpenFailed { sink, target, source } => write!(f, "Failed to open {sink} sink {target}: {source}"),
+        }
+    }
+}
+
+impl Error for ConfigError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            ConfigError::ReadFailed { source, .. }
+            | ConfigError::NatsConnectFailed { source, .. }
+            | ConfigError::SinkOpenFailed { source, .. } => Some(source),
+            ConfigError::InvalidJson(e) => Some(e),
+            ConfigError::InvalidPubkey { .. } | ConfigError::InvalidOption { .. } => None,
+        }
+    }
+}
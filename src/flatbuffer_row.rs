@@ -0,0 +1,160 @@
+//! `payload_format = "flatbuffers"`: a `Row` as the `wallet.Row` table of
+//! `proto/wallet_row.fbs`, so consumers can read single fields in place instead of
+//! parsing the whole row. Written by hand field by field (no flatc at build time);
+//! the id of each field is its position in `Row`.
+
+use flatbuffers::{FlatBufferBuilder, VOffsetT, WIPOffset};
+
+use crate::Row;
+
+/// `file_identifier` of the schema, at bytes 4..8 of every message.
+const FILE_IDENTIFIER: &str = "WROW";
+
+// vtable slot of the field with this id (flatbuffers::field_index_to_field_offset, in const)
+const fn slot(id: VOffsetT) -> VOffsetT {
+    4 + 2 * id
+}
+
+const TS: VOffsetT = slot(0);
+const SLOT: VOffsetT = slot(1);
+const WRITE_VER: VOffsetT = slot(2);
+const PUBKEY: VOffsetT = slot(3);
+const LAMPORTS: VOffsetT = slot(4);
+const DATA: VOffsetT = slot(5);
+const DATA_ENCODING: VOffsetT = slot(6);
+const DATA_TRUNCATED: VOffsetT = slot(7);
+const DATA_LEN: VOffsetT = slot(8);
+const FINAL: VOffsetT = slot(9);
+const TXN_INDEX: VOffsetT = slot(10);
+const LEADER: VOffsetT = slot(11);
+const SOURCE_HOST: VOffsetT = slot(12);
+const RUN_ID: VOffsetT = slot(13);
+const VOTER: VOffsetT = slot(14);
+const STAKE: VOffsetT = slot(15);
+const ACTIVATION_EPOCH: VOffsetT = slot(16);
+const DEACTIVATION_EPOCH: VOffsetT = slot(17);
+const TOKEN_MINT: VOffsetT = slot(18);
+const TOKEN_OWNER: VOffsetT = slot(19);
+const TOKEN_AMOUNT: VOffsetT = slot(20);
+const TOKEN_UI_AMOUNT: VOffsetT = slot(21);
+const SLOT_TIME: VOffsetT = slot(22);
+const NODE_PUBKEY: VOffsetT = slot(23);
+const COMMISSION: VOffsetT = slot(24);
+const LAST_VOTE_SLOT: VOffsetT = slot(25);
+const CREDITS: VOffsetT = slot(26);
+const EPOCH: VOffsetT = slot(27);
+
+fn push_str(fbb: &mut FlatBufferBuilder<'_>, slot: VOffsetT, s: Option<WIPOffset<&str>>) {
+    if let Some(s) = s {
+        fbb.push_slot_always(slot, s);
+    }
+}
+
+/// Serialize `row` as a finished `wallet.Row` buffer.
+pub(crate) fn encode(row: &Row) -> Vec<u8> {
+    let mut fbb = FlatBufferBuilder::with_capacity(256);
+    // strings go into the buffer before the table that points at them
+    let mut string = |s: Option<&str>| s.map(|s| fbb.create_string(s));
+    let ts = string(Some(&row.ts));
+    let pubkey = string(Some(&row.pubkey));
+    let data = string(row.data.as_deref());
+    let data_encoding = string(row.data_encoding);
+    let leader = string(row.leader.as_deref());
+    let source_host = string(row.source_host.as_deref());
+    let run_id = string(row.run_id.as_deref());
+    let voter = string(row.voter.as_deref());
+    let token_mint = string(row.token_mint.as_deref());
+    let token_owner = string(row.token_owner.as_deref());
+    let slot_time = string(row.slot_time.as_deref());
+    let node_pubkey = string(row.node_pubkey.as_deref());
+
+    let table = fbb.start_table();
+    push_str(&mut fbb, TS, ts);
+    fbb.push_slot_always(SLOT, row.slot);
+    fbb.push_slot_always(WRITE_VER, row.write_ver);
+    push_str(&mut fbb, PUBKEY, pubkey);
+    fbb.push_slot_always(LAMPORTS, u64::try_from(row.lamports).unwrap_or(u64::MAX));
+    push_str(&mut fbb, DATA, data);
+    push_str(&mut fbb, DATA_ENCODING, data_encoding);
+    push_str(&mut fbb, LEADER, leader);
+    push_str(&mut fbb, SOURCE_HOST, source_host);
+    push_str(&mut fbb, RUN_ID, run_id);
+    push_str(&mut fbb, VOTER, voter);
+    push_str(&mut fbb, TOKEN_MINT, token_mint);
+    push_str(&mut fbb, TOKEN_OWNER, token_owner);
+    push_str(&mut fbb, SLOT_TIME, slot_time);
+    push_str(&mut fbb, NODE_PUBKEY, node_pubkey);
+    // optional scalars: absent unless set, so a reader can tell None from 0 / false
+    for (slot, value) in [
+        (DATA_LEN, row.data_len),
+        (TXN_INDEX, row.txn_index),
+        (STAKE, row.stake),
+        (ACTIVATION_EPOCH, row.activation_epoch),
+        (DEACTIVATION_EPOCH, row.deactivation_epoch),
+        (TOKEN_AMOUNT, row.token_amount),
+        (LAST_VOTE_SLOT, row.last_vote_slot),
+        (CREDITS, row.credits),
+        (EPOCH, row.epoch),
+    ] {
+        if let Some(value) = value {
+            fbb.push_slot_always(slot, value);
+        }
+    }
+    if let Some(value) = row.token_ui_amount {
+        fbb.push_slot_always(TOKEN_UI_AMOUNT, value);
+    }
+    if let Some(value) = row.data_truncated {
+        fbb.push_slot_always(DATA_TRUNCATED, value);
+    }
+    if let Some(value) = row.is_final {
+        fbb.push_slot_always(FINAL, value);
+    }
+    if let Some(value) = row.commission {
+        fbb.push_slot_always(COMMISSION, value);
+    }
+    let table = fbb.end_table(table);
+    fbb.finish(table, Some(FILE_IDENTIFIER));
+    fbb.finished_data().to_vec()
+}
+
+#[cfg(test)]
+mod tests {
+    use flatbuffers::{ForwardsUOffset, Table};
+
+    use super::*;
+
+    #[test]
+    fn encoded_row_reads_back_field_by_field() {
+        let row = Row {
+            ts: "2025-11-13 22:15:33".into(),
+            slot: 312_000_123,
+            write_ver: 7,
+            pubkey: "9xQeWvG816bUx9EPjHmaT23yvVM2ZWbrrpZb9PusVFin".into(),
+            lamports: 2_039_280,
+            data_len: Some(0),
+            token_ui_amount: Some(1.5),
+            is_final: Some(true),
+            commission: Some(5),
+            ..Row::default()
+        };
+        let buf = encode(&row);
+        assert!(flatbuffers::buffer_has_identifier(&buf, FILE_IDENTIFIER, false));
+        // SAFETY: a buffer encode just finished
+        let table = unsafe { flatbuffers::root_unchecked::<Table>(&buf) };
+        // SAFETY: each slot is read as the type encode wrote it with
+        unsafe {
+            assert_eq!(table.get::<ForwardsUOffset<&str>>(TS, None), Some("2025-11-13 22:15:33"));
+            assert_eq!(table.get::<u64>(SLOT, None), Some(312_000_123));
+            assert_eq!(table.get::<u64>(WRITE_VER, None), Some(7));
+            assert_eq!(table.get::<ForwardsUOffset<&str>>(PUBKEY, None), Some(row.pubkey.as_str()));
+            assert_eq!(table.get::<u64>(LAMPORTS, None), Some(2_039_280));
+            // a set zero is present, an unset field is absent
+            assert_eq!(table.get::<u64>(DATA_LEN, None), Some(0));
+            assert_eq!(table.get::<u64>(STAKE, None), None);
+            assert_eq!(table.get::<ForwardsUOffset<&str>>(DATA, None), None);
+            assert_eq!(table.get::<f64>(TOKEN_UI_AMOUNT, None), Some(1.5));
+            assert_eq!(table.get::<bool>(FINAL, None), Some(true));
+            assert_eq!(table.get::<u8>(COMMISSION, None), Some(5));
+        }
+    }
+}
@@ -0,0 +1,111 @@
+//! Process-wide counters and the `update_account` latency histogram.
+
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+pub(crate) static COUNTERS: Counters = Counters::new();
+
+/// Process-wide plugin counters (true totals, independent of log sampling).
+pub(crate) struct Counters {
+    pub matched: AtomicU64,
+    pub published: AtomicU64,
+    pub publish_errors: AtomicU64,
+    pub flush_timeouts: AtomicU64,
+    // gauge: NATS publishes holding a max_concurrent_publishes permit right now
+    pub publishes_in_flight: AtomicU64,
+}
+
+impl Counters {
+    const fn new() -> Self {
+        Counters {
+            matched: AtomicU64::new(0),
+            published: AtomicU64::new(0),
+            publish_errors: AtomicU64::new(0),
+            flush_timeouts: AtomicU64::new(0),
+            publishes_in_flight: AtomicU64::new(0),
+        }
+    }
+}
+
+pub(crate) static UPDATE_LATENCY: LatencyHistogram = LatencyHistogram::new();
+
+// bucket i counts durations in [2^(i-1), 2^i) µs; the last bucket is open-ended (≥ ~0.5s)
+const BUCKETS: usize = 21;
+const REPORT_EVERY: Duration = Duration::from_secs(60);
+
+/// Log-scale histogram of time spent in `update_account`. Recording is a couple
+/// of relaxed atomic adds; percentiles are read at bucket resolution (upper bound),
+/// which is enough to tell "microseconds" from "milliseconds".
+pub(crate) struct LatencyHistogram {
+    buckets: [AtomicU64; BUCKETS],
+    last_report_secs: AtomicU64,
+}
+
+impl LatencyHistogram {
+    const fn new() -> Self {
+        LatencyHistogram {
+            buckets: [const { AtomicU64::new(0) }; BUCKETS],
+            last_report_secs: AtomicU64::new(0),
+        }
+    }
+
+    #[inline]
+    pub(crate) fn record(&self, elapsed: Duration) {
+        let micros = u64::try_from(elapsed.as_micros()).unwrap_or(u64::MAX);
+        let idx = ((u64::BITS - micros.leading_zeros()) as usize).min(BUCKETS - 1);
+        self.buckets[idx].fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Log p50/p99 for the window since the last report, at most once per minute.
+    pub(crate) fn maybe_report(&self) {
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs();
+        let last = self.last_report_secs.load(Ordering::Relaxed);
+        if now < last + REPORT_EVERY.as_secs()
+            || self.last_report_secs.compare_exchange(last, now, Ordering::Relaxed, Ordering::Relaxed).is_err()
+        {
+            return;
+        }
+        if last != 0 {
+            self.report();
+        }
+    }
+
+    /// Log and reset the current window.
+    pub(crate) fn report(&self) {
+        let counts: Vec<u64> = self.buckets.iter().map(|b| b.swap(0, Ordering::Relaxed)).collect();
+        let total: u64 = counts.iter().sum();
+        if total == 0 {
+            return;
+        }
+        eprintln!(
+            "[PLUGIN] update_account latency: n={total} p50<={} p99<={} max<={}",
+            bucket_label(percentile_bucket(&counts, total, 50)),
+            bucket_label(percentile_bucket(&counts, total, 99)),
+            bucket_label(counts.iter().rposition(|&c| c > 0).unwrap_or(0)),
+        );
+    }
+}
+
+fn percentile_bucket(counts: &[u64], total: u64, pct: u64) -> usize {
+    let rank = (total * pct).div_ceil(100);
+    let mut seen = 0;
+    for (i, c) in counts.iter().enumerate() {
+        seen += c;
+        if seen >= rank {
+            return i;
+        }
+    }
+    counts.len() - 1
+}
+
+fn bucket_label(idx: usize) -> String {
+    if idx == BUCKETS - 1 {
+        return "inf".to_string();
+    }
+    let upper_us = 1u64 << idx;
+    if upper_us >= 1000 {
+        format!("{}ms", upper_us / 1000)
+    } else {
+        format!("{upper_us}µs")
+    }
+}
@@ -0,0 +1,100 @@
+//! `tracing_enabled`: one OpenTelemetry span per NATS publish (one per batch when
+//! batching), exported over OTLP/HTTP, with its context in the message's W3C
+//! `traceparent` header. The ingestor extracts it and parents its ClickHouse insert
+//! span on it, so a trace runs from the validator callback to the row landing.
+//!
+//! `otlp_endpoint` is the collector's full traces URL (e.g.
+//! `http://collector:4318/v1/traces`); without it the exporter follows the standard
+//! `OTEL_EXPORTER_OTLP_TRACES_ENDPOINT` / `OTEL_EXPORTER_OTLP_ENDPOINT` variables, and
+//! falls back to `http://localhost:4318/v1/traces`. Spans are exported in batches
+//! from a background thread, so an unreachable collector never slows a publish.
+
+use opentelemetry::propagation::{Injector, TextMapPropagator};
+use opentelemetry::trace::{SpanKind, TraceContextExt, Tracer, TracerProvider as _};
+use opentelemetry::{Context, KeyValue};
+use opentelemetry_otlp::WithExportConfig;
+use opentelemetry_sdk::propagation::TraceContextPropagator;
+use opentelemetry_sdk::trace::{SdkTracer, SdkTracerProvider, Span};
+use opentelemetry_sdk::Resource;
+
+pub(crate) struct Tracing {
+    provider: SdkTracerProvider,
+    tracer: SdkTracer,
+}
+
+impl Tracing {
+    /// Export to the OTLP/HTTP collector at `endpoint` (see the module docs for the default).
+    pub(crate) fn otlp(endpoint: Option<&str>) -> Result<Self, String> {
+        let mut exporter = opentelemetry_otlp::SpanExporter::builder().with_http();
+        if let Some(endpoint) = endpoint {
+            exporter = exporter.with_endpoint(endpoint);
+        }
+        let exporter = exporter.build().map_err(|e| e.to_string())?;
+        Ok(Self::with_provider(SdkTracerProvider::builder().with_batch_exporter(exporter)))
+    }
+
+    fn with_provider(builder: opentelemetry_sdk::trace::TracerProviderBuilder) -> Self {
+        let resource = Resource::builder().with_service_name(env!("CARGO_PKG_NAME")).build();
+        let provider = builder.with_resource(resource).build();
+        let tracer = provider.tracer(env!("CARGO_CRATE_NAME"));
+        Tracing { provider, tracer }
+    }
+
+    /// Start the span for publishing `len` bytes on `subject` and put its `traceparent`
+    /// into `headers`. The span ends when dropped.
+    pub(crate) fn start_publish(&self, subject: &str, len: usize, headers: &mut nats::HeaderMap) -> Span {
+        let span = self
+            .tracer
+            .span_builder(format!("publish {subject}"))
+            .with_kind(SpanKind::Producer)
+            .with_attributes([
+                KeyValue::new("messaging.system", "nats"),
+                KeyValue::new("messaging.destination.name", subject.to_string()),
+                KeyValue::new("messaging.message.body.size", len as i64),
+            ])
+            .start(&self.tracer);
+        let cx = Context::new().with_remote_span_context(opentelemetry::trace::Span::span_context(&span).clone());
+        TraceContextPropagator::new().inject_context(&cx, &mut NatsHeaders(headers));
+        span
+    }
+}
+
+impl Drop for Tracing {
+    // exports whatever spans are still queued
+    fn drop(&mut self) {
+        if let Err(e) = self.provider.shutdown() {
+            eprintln!("[PLUGIN] OpenTelemetry shutdown failed: {e}");
+        }
+    }
+}
+
+struct NatsHeaders<'a>(&'a mut nats::HeaderMap);
+
+impl Injector for NatsHeaders<'_> {
+    fn set(&mut self, key: &str, value: String) {
+        self.0.insert(key, value);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use opentelemetry_sdk::trace::InMemorySpanExporter;
+
+    #[test]
+    fn publish_span_is_exported_with_the_traceparent_it_sent() {
+        let exporter = InMemorySpanExporter::default();
+        let tracing = Tracing::with_provider(SdkTracerProvider::builder().with_simple_exporter(exporter.clone()));
+        let mut headers = nats::HeaderMap::new();
+        drop(tracing.start_publish("WALLET.updates", 42, &mut headers));
+
+        let spans = exporter.get_finished_spans().unwrap();
+        assert_eq!(spans.len(), 1);
+        let span = &spans[0];
+        assert_eq!(span.name, "publish WALLET.updates");
+        assert_eq!(span.span_kind, SpanKind::Producer);
+        assert!(span.attributes.contains(&KeyValue::new("messaging.message.body.size", 42i64)));
+        let want = format!("00-{}-{}-01", span.span_context.trace_id(), span.span_context.span_id());
+        assert_eq!(headers.get("traceparent").map(String::as_str), Some(want.as_str()));
+    }
+}
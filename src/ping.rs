@@ -0,0 +1,109 @@
+//! `ping_subject`: request-reply liveness probes over NATS, on a connection of its
+//! own (so it works with any sink). Any request with a reply subject, whatever its
+//! body, gets a pong with the plugin's current progress and counters:
+//!
+//! ```json
+//! {"type": "pong", "ts": "2025-11-13 22:15:33", "uptime_secs": 3600, "last_slot": 312000123,
+//!  "last_rooted_slot": 312000091, "matched": 81234, "published": 81230, "publish_errors": 4,
+//!  "flush_timeouts": 0, "publishes_in_flight": 1}
+//! ```
+//!
+//! e.g. `nats req WALLET.ping ''`. A stuck `last_slot` means the validator stopped
+//! notifying; no reply at all means the plugin (or its NATS connection) is gone.
+
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::thread::{self, JoinHandle};
+use std::time::Instant;
+
+use serde::Serialize;
+
+use crate::clock::{Clock, format_ts};
+use crate::control::{self, POLL};
+use crate::metrics::COUNTERS;
+use crate::publisher::ConnectSpec;
+
+/// What a pong reports, shared with the plugin.
+#[derive(Debug, Clone)]
+pub(crate) struct PingState {
+    pub last_slot: Arc<AtomicU64>,
+    pub last_rooted_slot: Arc<AtomicU64>,
+    pub clock: Arc<dyn Clock>,
+    pub started: Instant,
+}
+
+#[derive(Serialize)]
+struct Pong {
+    #[serde(rename = "type")]
+    kind: &'static str,
+    ts: String,
+    uptime_secs: u64,
+    last_slot: u64,
+    last_rooted_slot: u64,
+    matched: u64,
+    published: u64,
+    publish_errors: u64,
+    flush_timeouts: u64,
+    publishes_in_flight: u64,
+}
+
+impl PingState {
+    fn pong(&self) -> Pong {
+        Pong {
+            kind: "pong",
+            ts: format_ts(self.clock.now(), None),
+            uptime_secs: self.started.elapsed().as_secs(),
+            last_slot: self.last_slot.load(Ordering::Relaxed),
+            last_rooted_slot: self.last_rooted_slot.load(Ordering::Relaxed),
+            matched: COUNTERS.matched.load(Ordering::Relaxed),
+            published: COUNTERS.published.load(Ordering::Relaxed),
+            publish_errors: COUNTERS.publish_errors.load(Ordering::Relaxed),
+            flush_timeouts: COUNTERS.flush_timeouts.load(Ordering::Relaxed),
+            publishes_in_flight: COUNTERS.publishes_in_flight.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// Background responder thread; stopped and joined by `on_unload`.
+#[derive(Debug)]
+pub(crate) struct PingListener {
+    stop: Arc<AtomicBool>,
+    handle: JoinHandle<()>,
+}
+
+impl PingListener {
+    /// Connect (retrying in the background until it works) and answer requests on
+    /// `subject` from `state`.
+    pub(crate) fn start(spec: ConnectSpec, subject: String, state: PingState) -> std::io::Result<Self> {
+        let stop = Arc::new(AtomicBool::new(false));
+        let flag = stop.clone();
+        let handle = thread::Builder::new().name("ping".into()).spawn(move || listen(&spec, &subject, &state, &flag))?;
+        Ok(PingListener { stop, handle })
+    }
+
+    pub(crate) fn stop(self) {
+        self.stop.store(true, Ordering::SeqCst);
+        if self.handle.join().is_err() {
+            eprintln!("[PLUGIN] WARNING: ping thread panicked");
+        }
+    }
+}
+
+fn listen(spec: &ConnectSpec, subject: &str, state: &PingState, stop: &AtomicBool) {
+    let Some((conn, sub)) = control::subscribe(spec, subject, stop, "ping") else { return };
+    eprintln!("[PLUGIN] answering pings on {subject}");
+    while !stop.load(Ordering::SeqCst) {
+        // timeouts (and errors while the client reconnects) just mean: poll again
+        let Ok(msg) = sub.next_timeout(POLL) else { continue };
+        // a plain publish has nobody to answer
+        if msg.reply.is_none() {
+            continue;
+        }
+        let Ok(pong) = serde_json::to_vec(&state.pong()) else { continue };
+        if let Err(e) = msg.respond(pong) {
+            eprintln!("[PLUGIN] WARNING: ping reply failed: {e}");
+        }
+    }
+    drop(sub);
+    conn.close();
+}
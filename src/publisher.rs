@@ -0,0 +1,537 @@
+//! Publishing: the installed [`Sink`] and the NATS sink with its shared
+//! connection, optional row batching and flush policy.
+
+use std::collections::BTreeMap;
+use std::io;
+use std::sync::{Arc, Condvar, Mutex, RwLock, atomic::{AtomicU64, Ordering}};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use base64::{Engine as _, prelude::BASE64_STANDARD};
+use flate2::{Compression, write::GzEncoder};
+use opentelemetry::trace::{Span as _, Status};
+
+use crate::diagnostics::{self, Kind};
+use crate::metrics::COUNTERS;
+use crate::otel::Tracing;
+use crate::sink::{RowKey, Sink};
+use crate::state::RateLimit;
+
+// global sink (normally the NATS publisher); installed by on_load, taken down by on_unload
+// so a reload reconnects.
+//
+// Ordering: the connection and its subject live in one sink behind one lock, so
+// a concurrent `update_account` sees either no publisher (and skips) or a complete one,
+// never a connection without its subject. Agave calls `on_load` before any
+// notification, but a background reconnect installs later, concurrently with them.
+static PUBLISHER: RwLock<Option<Arc<dyn Sink>>> = RwLock::new(None);
+// bumped by shutdown so a background reconnect from a previous load gives up
+static GENERATION: AtomicU64 = AtomicU64::new(0);
+
+/// Everything needed to (re)build a NATS connection.
+pub(crate) struct ConnectSpec {
+    pub url: String,
+    pub tls: bool,
+    pub tls_ca: Option<String>,
+    // client name for the server's monitoring
+    pub name: String,
+}
+
+impl ConnectSpec {
+    fn connect(&self) -> io::Result<nats::Connection> {
+        let mut opts = nats::Options::new().with_name(&self.name);
+        if self.tls {
+            opts = opts.tls_required(true);
+        }
+        if let Some(ca) = &self.tls_ca {
+            opts = opts.add_root_certificate(ca);
+        }
+        opts.connect(self.url.as_str())
+    }
+
+    /// Try up to `attempts` times, `delay` apart. NATS is often briefly down
+    /// during coordinated restarts, so one failed attempt shouldn't be final.
+    pub(crate) fn connect_with_retry(&self, attempts: u32, delay: Duration) -> io::Result<nats::Connection> {
+        let attempts = attempts.max(1);
+        let mut attempt = 1;
+        loop {
+            match self.connect() {
+                Ok(conn) => return Ok(conn),
+                Err(e) if attempt < attempts => {
+                    eprintln!("[PLUGIN] NATS connect to {} failed (attempt {attempt}/{attempts}): {e}; retrying in {delay:?}", self.url);
+                    thread::sleep(delay);
+                    attempt += 1;
+                }
+                Err(e) => return Err(e),
+            }
+        }
+    }
+}
+
+/// Keep trying to connect in the background and install the publisher once it
+/// works. Publishes are skipped (and logged) until then.
+pub(crate) fn spawn_reconnect(
+    spec: ConnectSpec,
+    delay: Duration,
+    make: impl FnOnce(nats::Connection) -> Publisher + Send + 'static,
+) {
+    let generation = GENERATION.load(Ordering::SeqCst);
+    let spawned = thread::Builder::new().name("nats-reconnect".into()).spawn(move || {
+        let mut backoff = delay;
+        loop {
+            thread::sleep(backoff);
+            if GENERATION.load(Ordering::SeqCst) != generation || is_installed() {
+                return;
+            }
+            match spec.connect() {
+                Ok(conn) => {
+                    // re-check under the lock: on_unload (or a newer on_load) may have run
+                    // while we were connecting
+                    let mut slot = PUBLISHER.write().unwrap_or_else(|e| e.into_inner());
+                    if GENERATION.load(Ordering::SeqCst) == generation && slot.is_none() {
+                        eprintln!("[PLUGIN] connected to NATS at {} (background reconnect)", spec.url);
+                        *slot = Some(Arc::new(make(conn)) as Arc<dyn Sink>);
+                    }
+                    return;
+                }
+                Err(e) => {
+                    eprintln!("[PLUGIN] NATS background connect to {} failed: {e}", spec.url);
+                    backoff = (backoff * 2).min(Duration::from_secs(30));
+                }
+            }
+        }
+    });
+    if let Err(e) = spawned {
+        eprintln!("[PLUGIN] ERROR: cannot spawn NATS reconnect thread: {e}");
+    }
+}
+
+/// `nats::Connection::publish` only buffers; flushing every `every` publishes with a
+/// deadline makes a stuck server visible (and blocks the caller, i.e. backpressure)
+/// instead of growing the client buffer without bound.
+pub(crate) struct FlushPolicy {
+    pub every: u64,
+    pub timeout: Duration,
+}
+
+/// `max_concurrent_publishes`: at most `max` threads (the callback's and the
+/// workers') inside a publish and its periodic flush at once. The rest wait for a
+/// permit, so a slow server backs up into the worker queues and then the callback,
+/// as with a flush timeout, instead of every thread piling onto the connection.
+pub(crate) struct PublishLimit {
+    max: usize,
+    in_flight: Mutex<usize>,
+    freed: Condvar,
+    saturated_warn: RateLimit,
+}
+
+impl PublishLimit {
+    pub(crate) fn new(max: usize) -> Self {
+        PublishLimit { max, in_flight: Mutex::new(0), freed: Condvar::new(), saturated_warn: RateLimit::new(Duration::from_secs(10)) }
+    }
+
+    fn acquire(&self) -> PublishPermit<'_> {
+        let in_flight = self.in_flight.lock().unwrap_or_else(|e| e.into_inner());
+        if *in_flight >= self.max
+            && let Some(suppressed) = self.saturated_warn.allow()
+        {
+            eprintln!(
+                "[PLUGIN] WARNING: {} NATS publishes in flight (max_concurrent_publishes), waiting ({suppressed} more waits since last warning)",
+                self.max
+            );
+        }
+        let mut in_flight = self.freed.wait_while(in_flight, |n| *n >= self.max).unwrap_or_else(|e| e.into_inner());
+        *in_flight += 1;
+        COUNTERS.publishes_in_flight.fetch_add(1, Ordering::Relaxed);
+        PublishPermit(self)
+    }
+}
+
+struct PublishPermit<'a>(&'a PublishLimit);
+
+impl Drop for PublishPermit<'_> {
+    fn drop(&mut self) {
+        *self.0.in_flight.lock().unwrap_or_else(|e| e.into_inner()) -= 1;
+        COUNTERS.publishes_in_flight.fetch_sub(1, Ordering::Relaxed);
+        self.0.freed.notify_one();
+    }
+}
+
+/// Newline-delimited rows waiting to go out as one NATS message.
+pub(crate) struct BatchPolicy {
+    pub max_rows: usize,
+    pub max_age: Duration,
+    // batch_mode = "per_slot": one message per slot instead (max_rows is ignored)
+    pub per_slot: bool,
+}
+
+// per_slot: slots whose batches are still open at once before the oldest is sent anyway
+const MAX_OPEN_SLOTS: usize = 64;
+
+struct Batch {
+    buf: Vec<u8>,
+    rows: usize,
+    started: Instant,
+}
+
+/// Per-message NATS headers.
+pub(crate) struct HeaderPolicy {
+    // tracing_enabled: a span per message, its context in a traceparent header
+    pub tracing: Option<Tracing>,
+    // Ed25519-Signature: base64 signature of the payload
+    pub signing_key: Option<ed25519_dalek::SigningKey>,
+    // Slot and Pubkey of the row on every row message (batching is off)
+    pub row_key: bool,
+    // Published-At: unix milliseconds at send time
+    pub published_at: bool,
+}
+
+impl HeaderPolicy {
+    fn headers(&self, payload: &[u8], key: Option<RowKey<'_>>) -> Option<nats::HeaderMap> {
+        let key = key.filter(|_| self.row_key);
+        if !self.published_at && self.signing_key.is_none() && key.is_none() {
+            return None;
+        }
+        let mut h = nats::HeaderMap::new();
+        if let Some(key) = key {
+            h.insert("Slot", key.slot.to_string());
+            h.insert("Pubkey", key.pubkey);
+        }
+        if self.published_at {
+            let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+            h.insert("Published-At", now.as_millis().to_string());
+        }
+        if let Some(key) = &self.signing_key {
+            let signature = ed25519_dalek::Signer::sign(key, payload);
+            h.insert("Ed25519-Signature", BASE64_STANDARD.encode(signature.to_bytes()));
+        }
+        Some(h)
+    }
+}
+
+pub(crate) struct Publisher {
+    conn: nats::Connection,
+    subject: String,
+    flush: FlushPolicy,
+    // None when batching is off (max_rows <= 1) or per slot
+    batch: Option<(BatchPolicy, Mutex<Batch>)>,
+    // Some((max_age, slot -> batch)) with batch_mode = "per_slot"
+    slots: Option<(Duration, Mutex<BTreeMap<u64, Batch>>)>,
+    headers: HeaderPolicy,
+    limit: Option<PublishLimit>,
+    // compress_min_bytes: gzip messages larger than this
+    compress_min_bytes: Option<usize>,
+}
+
+impl Publisher {
+    pub(crate) fn new(
+        conn: nats::Connection,
+        subject: String,
+        flush: FlushPolicy,
+        batch: BatchPolicy,
+        headers: HeaderPolicy,
+        limit: Option<PublishLimit>,
+        compress_min_bytes: Option<usize>,
+    ) -> Self {
+        let slots = batch.per_slot.then(|| (batch.max_age, Mutex::new(BTreeMap::new())));
+        let batch = (batch.max_rows > 1 && !batch.per_slot).then(|| {
+            let buf = Batch { buf: Vec::new(), rows: 0, started: Instant::now() };
+            (batch, Mutex::new(buf))
+        });
+        Publisher { conn, subject, flush, batch, slots, headers, limit, compress_min_bytes }
+    }
+
+    fn publish(&self, bytes: &[u8]) {
+        let Some((policy, batch)) = &self.batch else {
+            self.send(bytes);
+            return;
+        };
+        let mut b = batch.lock().unwrap_or_else(|e| e.into_inner());
+        if b.rows == 0 {
+            b.started = Instant::now();
+        }
+        b.buf.extend_from_slice(bytes);
+        b.buf.push(b'\n');
+        b.rows += 1;
+        if b.rows >= policy.max_rows || b.started.elapsed() >= policy.max_age {
+            self.send_batch(&mut b);
+        }
+    }
+
+    /// Add a main-subject row to its slot's batch (batch_mode = "per_slot").
+    fn publish_in_slot(&self, slots: &Mutex<BTreeMap<u64, Batch>>, bytes: &[u8], slot: u64) {
+        let mut slots = slots.lock().unwrap_or_else(|e| e.into_inner());
+        let b = slots.entry(slot).or_insert_with(|| Batch { buf: Vec::new(), rows: 0, started: Instant::now() });
+        b.buf.extend_from_slice(bytes);
+        b.buf.push(b'\n');
+        b.rows += 1;
+        if slots.len() > MAX_OPEN_SLOTS
+            && let Some((slot, mut b)) = slots.pop_first()
+        {
+            self.send_slot_batch(slot, &mut b);
+        }
+    }
+
+    /// Publish the pending batch if it is older than `max_age`, or unconditionally with `force`.
+    fn flush_batch(&self, force: bool) {
+        if let Some((policy, batch)) = &self.batch {
+            let mut b = batch.lock().unwrap_or_else(|e| e.into_inner());
+            if b.rows > 0 && (force || b.started.elapsed() >= policy.max_age) {
+                self.send_batch(&mut b);
+            }
+        }
+        if let Some((max_age, slots)) = &self.slots {
+            let mut slots = slots.lock().unwrap_or_else(|e| e.into_inner());
+            let due: Vec<u64> = slots.iter().filter(|(_, b)| force || b.started.elapsed() >= *max_age).map(|(&s, _)| s).collect();
+            for slot in due {
+                if let Some(mut b) = slots.remove(&slot) {
+                    self.send_slot_batch(slot, &mut b);
+                }
+            }
+        }
+    }
+
+    /// per_slot: send the batch of `slot` and everything older, which can no longer grow
+    /// except through late rows (those start a new batch for their slot).
+    fn flush_slot(&self, slot: u64) {
+        let Some((_, slots)) = &self.slots else { return };
+        let mut slots = slots.lock().unwrap_or_else(|e| e.into_inner());
+        let newer = slots.split_off(&(slot + 1));
+        for (slot, mut b) in std::mem::replace(&mut *slots, newer) {
+            self.send_slot_batch(slot, &mut b);
+        }
+    }
+
+    fn send_slot_batch(&self, slot: u64, b: &mut Batch) {
+        b.buf.pop();
+        let mut headers = self.headers.headers(&b.buf, None).unwrap_or_default();
+        headers.insert("Slot", slot.to_string());
+        headers.insert("Rows", b.rows.to_string());
+        self.send_with_headers(&self.subject, &b.buf, Some(&headers));
+    }
+
+    fn send_batch(&self, b: &mut Batch) {
+        // drop the trailing newline; the ingestor splits on the inner ones
+        b.buf.pop();
+        self.send(&b.buf);
+        b.buf.clear();
+        b.rows = 0;
+    }
+
+    fn send(&self, bytes: &[u8]) {
+        self.send_to(&self.subject, bytes, None);
+    }
+
+    fn send_to(&self, subj: &str, bytes: &[u8], key: Option<RowKey<'_>>) {
+        let headers = self.headers.headers(bytes, key);
+        self.send_with_headers(subj, bytes, headers.as_ref());
+    }
+
+    fn send_with_headers(&self, subj: &str, bytes: &[u8], headers: Option<&nats::HeaderMap>) {
+        // signed (and Published-At stamped) before compression, so the ingestor verifies
+        // what it decompressed
+        let gzipped = self.compress_min_bytes.filter(|&min| bytes.len() > min).and_then(|_| gzip(bytes));
+        let with_encoding = gzipped.as_ref().map(|_| {
+            let mut h = headers.cloned().unwrap_or_default();
+            h.insert("Content-Encoding", "gzip");
+            h
+        });
+        let headers = with_encoding.as_ref().or(headers);
+        let bytes = gzipped.as_deref().unwrap_or(bytes);
+        let mut traced = None;
+        let mut span = self.headers.tracing.as_ref().map(|t| {
+            let h = traced.insert(headers.cloned().unwrap_or_default());
+            t.start_publish(subj, bytes.len(), h)
+        });
+        let headers = traced.as_ref().or(headers);
+        let permit = self.limit.as_ref().map(PublishLimit::acquire);
+        let result = self.conn.publish_with_reply_or_headers(subj, None, headers, bytes);
+        if let (Some(span), Err(e)) = (&mut span, &result) {
+            span.set_status(Status::error(e.to_string()));
+        }
+        drop(span);
+        if let Err(e) = result {
+            drop(permit);
+            COUNTERS.publish_errors.fetch_add(1, Ordering::Relaxed);
+            eprintln!("[PLUGIN] NATS publish error on {subj}: {e}");
+            diagnostics::report(Kind::PublishError, || format!("publish on {subj} failed: {e}"));
+            return;
+        }
+        let n = COUNTERS.published.fetch_add(1, Ordering::Relaxed) + 1;
+        let flushed = if self.flush.every > 0 && n.is_multiple_of(self.flush.every) {
+            self.conn.flush_timeout(self.flush.timeout)
+        } else {
+            Ok(())
+        };
+        // a diagnostic event publishes too, so only once the permit is back
+        drop(permit);
+        if let Err(e) = flushed {
+            let timeouts = COUNTERS.flush_timeouts.fetch_add(1, Ordering::Relaxed) + 1;
+            eprintln!(
+                "[PLUGIN] WARNING: NATS flush did not complete within {:?} ({e}); {timeouts} flush timeouts so far",
+                self.flush.timeout
+            );
+            diagnostics::report(Kind::FlushTimeout, || format!("flush did not complete within {:?}: {e}", self.flush.timeout));
+        }
+    }
+}
+
+/// `bytes` as one gzip member; `None` (send uncompressed) if encoding fails.
+fn gzip(bytes: &[u8]) -> Option<Vec<u8>> {
+    let mut gz = GzEncoder::new(Vec::with_capacity(bytes.len() / 2), Compression::fast());
+    io::Write::write_all(&mut gz, bytes).ok()?;
+    gz.finish().ok()
+}
+
+impl Sink for Publisher {
+    fn publish(&self, bytes: &[u8]) {
+        Publisher::publish(self, bytes);
+    }
+
+    fn publish_to(&self, subject: &str, bytes: &[u8]) {
+        self.send_to(subject, bytes, None);
+    }
+
+    fn publish_row(&self, subject: Option<&str>, bytes: &[u8], key: RowKey<'_>) {
+        match subject {
+            // with row_key headers batching is off, so this is the one-row-per-message path
+            None if self.headers.row_key => self.send_to(&self.subject, bytes, Some(key)),
+            None => match &self.slots {
+                Some((_, slots)) => self.publish_in_slot(slots, bytes, key.slot),
+                None => Publisher::publish(self, bytes),
+            },
+            Some(subject) => self.send_to(subject, bytes, Some(key)),
+        }
+    }
+
+    fn flush_stale(&self) {
+        self.flush_batch(false);
+    }
+
+    fn slot_done(&self, slot: u64) {
+        self.flush_slot(slot);
+    }
+
+    // no server round trip: publishes on one connection stay in order, so anything
+    // published after this lands behind the batch
+    fn flush(&self) {
+        self.flush_batch(true);
+    }
+
+    fn shutdown(&self) {
+        self.flush_batch(true);
+        if let Err(e) = self.conn.flush_timeout(self.flush.timeout) {
+            eprintln!("[PLUGIN] WARNING: NATS flush on unload failed: {e}");
+        }
+        // drain flushes once more and closes the connection
+        if let Err(e) = self.conn.drain() {
+            eprintln!("[PLUGIN] WARNING: NATS drain on unload failed: {e}");
+        }
+        eprintln!("[PLUGIN] NATS connection drained and closed");
+    }
+}
+
+fn current() -> Option<Arc<dyn Sink>> {
+    PUBLISHER.read().unwrap_or_else(|e| e.into_inner()).clone()
+}
+
+pub(crate) fn is_installed() -> bool {
+    PUBLISHER.read().unwrap_or_else(|e| e.into_inner()).is_some()
+}
+
+pub(crate) fn install(sink: Arc<dyn Sink>) {
+    *PUBLISHER.write().unwrap_or_else(|e| e.into_inner()) = Some(sink);
+}
+
+/// Publish one message to `subject`, bypassing the row batch (side channels
+/// such as `capture_subject`). Shares the connection, counters and flush policy.
+pub(crate) fn publish_to(subject: &str, bytes: &[u8]) {
+    match current() {
+        Some(p) => p.publish_to(subject, bytes),
+        None => eprintln!("[PLUGIN] NATS connection not initialized, skipping publish"),
+    }
+}
+
+/// Publish one account row on `subject` (the main subject if `None`); see
+/// [`Sink::publish_row`].
+#[inline]
+pub(crate) fn publish_row(subject: Option<&str>, bytes: &[u8], key: RowKey<'_>) {
+    match current() {
+        Some(p) => p.publish_row(subject, bytes, key),
+        None => eprintln!("[PLUGIN] NATS connection not initialized, skipping publish"),
+    }
+}
+
+/// Time-based batch flush; the sync client has no timer thread, so this runs
+/// from the slot-status callback, which fires several times per slot.
+pub(crate) fn flush_stale_batch() {
+    if let Some(p) = current() {
+        p.flush_stale();
+    }
+}
+
+/// `slot` has been processed: see [`Sink::slot_done`].
+pub(crate) fn slot_done(slot: u64) {
+    if let Some(p) = current() {
+        p.slot_done(slot);
+    }
+}
+
+/// Send the pending batch now, ahead of anything published afterwards.
+pub(crate) fn flush() {
+    if let Some(p) = current() {
+        p.flush();
+    }
+}
+
+/// Shut the sink down (for NATS: publish any pending batch, flush and drain the
+/// connection) and clear it so the next `on_load` connects afresh. The generation
+/// bump comes first, so a reconnect that is mid-connect can no longer install.
+pub(crate) fn shutdown() {
+    GENERATION.fetch_add(1, Ordering::SeqCst);
+    let Some(p) = PUBLISHER.write().unwrap_or_else(|e| e.into_inner()).take() else { return };
+    p.shutdown();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn policy(signing_key: Option<ed25519_dalek::SigningKey>) -> HeaderPolicy {
+        HeaderPolicy { tracing: None, signing_key, row_key: false, published_at: false }
+    }
+
+    #[test]
+    fn signature_header_verifies_against_the_payload() {
+        let key = ed25519_dalek::SigningKey::from_bytes(&[3; 32]);
+        let payload = br#"{"pubkey":"a","lamports":5}"#;
+        let headers = policy(Some(key.clone())).headers(payload, None).unwrap();
+        let sig = BASE64_STANDARD.decode(headers.get("Ed25519-Signature").unwrap()).unwrap();
+        let sig = ed25519_dalek::Signature::from_slice(&sig).unwrap();
+        assert!(key.verifying_key().verify_strict(payload, &sig).is_ok());
+        assert!(key.verifying_key().verify_strict(br#"{"pubkey":"a","lamports":6}"#, &sig).is_err());
+        // nothing to attach, no header map at all
+        assert!(policy(None).headers(payload, None).is_none());
+    }
+
+    #[test]
+    fn publish_limit_caps_concurrent_publishes() {
+        use std::sync::atomic::AtomicUsize;
+        let limit = PublishLimit::new(2);
+        let (inside, most) = (AtomicUsize::new(0), AtomicUsize::new(0));
+        thread::scope(|s| {
+            for _ in 0..6 {
+                s.spawn(|| {
+                    let _permit = limit.acquire();
+                    let now = inside.fetch_add(1, Ordering::SeqCst) + 1;
+                    most.fetch_max(now, Ordering::SeqCst);
+                    // a slow server: the publish holds its permit for a while
+                    thread::sleep(Duration::from_millis(50));
+                    inside.fetch_sub(1, Ordering::SeqCst);
+                });
+            }
+        });
+        assert_eq!(most.load(Ordering::SeqCst), 2);
+        assert_eq!(*limit.in_flight.lock().unwrap(), 0, "every permit was given back");
+    }
+}
@@ -0,0 +1,106 @@
+//! The ClickHouse schema of `Row`, kept next to the struct's field list so the
+//! DDL printer (`cargo run --bin ddl`) and the `fields` allow-list can't drift
+//! from what is actually published. Update [`ROW_COLUMNS`] whenever `Row` changes.
+
+use crate::{ConfigError, Params, read_params};
+
+/// A `Row` field: JSON name, ClickHouse type, and whether a config publishes it.
+pub(crate) struct Column {
+    pub name: &'static str,
+    pub ch_type: &'static str,
+    enabled: fn(&Params) -> bool,
+}
+
+const fn col(name: &'static str, ch_type: &'static str, enabled: fn(&Params) -> bool) -> Column {
+    Column { name, ch_type, enabled }
+}
+
+fn always(_: &Params) -> bool {
+    true
+}
+
+fn with_data(p: &Params) -> bool {
+    p.include_data.unwrap_or(false)
+}
+
+fn with_stake(p: &Params) -> bool {
+    p.decode_stake.unwrap_or(false)
+}
+
+fn with_vote(p: &Params) -> bool {
+    p.decode_vote.unwrap_or(false)
+}
+
+fn with_token(p: &Params) -> bool {
+    p.decode_token.unwrap_or(false)
+}
+
+/// Every `Row` field, in declaration order.
+pub(crate) const ROW_COLUMNS: &[Column] = &[
+    // the plugin formats ts in UTC unless `timezone` is set (see clickhouse_ddl)
+    col("ts", "DateTime('UTC')", always),
+    col("slot", "UInt64", always),
+    col("write_ver", "UInt64", always),
+    col("pubkey", "String", always),
+    col("lamports", "UInt64", always),
+    col("data", "Nullable(String)", with_data),
+    col("data_encoding", "Nullable(String)", with_data),
+    col("data_truncated", "Nullable(Bool)", |p| with_data(p) && p.max_data_bytes.is_some()),
+    col("data_len", "Nullable(UInt64)", |p| p.include_data_len.unwrap_or(false)),
+    col("final", "Nullable(Bool)", |p| p.republish_on_rooted.unwrap_or(false)),
+    // no interface version provides it yet, but the column keeps inserts stable once one does
+    col("txn_index", "Nullable(UInt64)", always),
+    col("leader", "Nullable(String)", |p| p.leader_schedule_path.is_some()),
+    col("source_host", "Nullable(String)", |p| p.source_host.is_some()),
+    col("run_id", "Nullable(String)", |p| p.include_run_id.unwrap_or(false)),
+    col("voter", "Nullable(String)", with_stake),
+    col("stake", "Nullable(UInt64)", with_stake),
+    col("activation_epoch", "Nullable(UInt64)", with_stake),
+    col("deactivation_epoch", "Nullable(UInt64)", with_stake),
+    col("token_mint", "Nullable(String)", with_token),
+    col("token_owner", "Nullable(String)", with_token),
+    col("token_amount", "Nullable(UInt64)", with_token),
+    col("token_ui_amount", "Nullable(Float64)", |p| with_token(p) && p.mint_decimals.is_some()),
+    col("slot_time", "Nullable(DateTime('UTC'))", |p| p.include_slot_time.unwrap_or(false)),
+    col("node_pubkey", "Nullable(String)", with_vote),
+    col("commission", "Nullable(UInt8)", with_vote),
+    col("last_vote_slot", "Nullable(UInt64)", with_vote),
+    col("credits", "Nullable(UInt64)", with_vote),
+    col("epoch", "Nullable(UInt64)", |p| p.include_epoch.unwrap_or(false)),
+];
+
+/// `CREATE TABLE` for rows published under `config_file` (every column without one),
+/// honouring its `fields` allow-list.
+///
+/// The ingestor's `CH_FORMAT=RowBinary` inserts every column, so generate its table
+/// without a config; JSONEachRow inserts only need the published ones.
+pub fn clickhouse_ddl(config_file: Option<&str>, table: &str) -> Result<String, ConfigError> {
+    let params = config_file.map(read_params).transpose()?;
+    let columns: Vec<&Column> = ROW_COLUMNS
+        .iter()
+        .filter(|c| {
+            params.as_ref().is_none_or(|p| {
+                (c.enabled)(p) && p.fields.as_ref().is_none_or(|f| f.iter().any(|name| name == c.name))
+            })
+        })
+        .collect();
+    let width = columns.iter().map(|c| c.name.len() + 2).max().unwrap_or(0);
+    let body: Vec<String> = columns
+        .iter()
+        .map(|c| {
+            let ch_type = match (c.name, params.as_ref().and_then(|p| p.timezone.as_deref())) {
+                ("ts", Some(tz)) => format!("DateTime('{tz}')"),
+                ("slot_time", Some(tz)) => format!("Nullable(DateTime('{tz}'))"),
+                _ => c.ch_type.to_string(),
+            };
+            format!("    {:width$} {ch_type}", format!("`{}`", c.name))
+        })
+        .collect();
+    Ok(format!(
+        "CREATE TABLE IF NOT EXISTS {table}\n(\n{}\n)\n\
+         -- rows re-sent after a restart or retry keep (pubkey, slot, write_ver)\n\
+         ENGINE = ReplacingMergeTree\n\
+         ORDER BY (pubkey, slot, write_ver);\n",
+        body.join(",\n")
+    ))
+}
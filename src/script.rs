@@ -0,0 +1,103 @@
+//! `filter_script_path`: a [rhai](https://rhai.rs) script deciding which matched
+//! updates are published, for filters the config options can't express. The script
+//! sees `pubkey` and `owner` (base58 strings), `lamports` and `data_len` (integers)
+//! and evaluates to `true` to publish the update, e.g.
+//!
+//! ```text
+//! lamports >= 1_000_000_000 && data_len == 0
+//! ```
+//!
+//! It is compiled once at load and run for every matched update, on the callback's
+//! thread, so it should stay cheap. A run longer than `filter_script_budget_us` is
+//! aborted; that, a runtime error or a non-boolean result publishes the update
+//! anyway (losing rows silently would be worse) and is reported.
+
+use std::cell::Cell;
+use std::fmt;
+use std::time::{Duration, Instant};
+
+use rhai::{AST, Dynamic, Engine, EvalAltResult, Scope};
+
+use crate::diagnostics::{self, Kind};
+use crate::state::RateLimit;
+
+// the time budget is checked every this many script operations
+const CHECK_EVERY_OPS: u64 = 64;
+
+thread_local! {
+    // when the script run on this thread must end
+    static DEADLINE: Cell<Option<Instant>> = const { Cell::new(None) };
+}
+
+pub(crate) struct FilterScript {
+    engine: Engine,
+    ast: AST,
+    budget: Duration,
+    error_warn: RateLimit,
+}
+
+impl fmt::Debug for FilterScript {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("FilterScript").field("budget", &self.budget).finish_non_exhaustive()
+    }
+}
+
+impl FilterScript {
+    /// Compile `source`; the error is rhai's, with line and column.
+    pub(crate) fn compile(source: &str, budget: Duration) -> Result<Self, String> {
+        let mut engine = Engine::new();
+        engine.on_progress(|ops| {
+            if !ops.is_multiple_of(CHECK_EVERY_OPS) {
+                return None;
+            }
+            let late = DEADLINE.get().is_some_and(|deadline| Instant::now() > deadline);
+            late.then(|| Dynamic::from("time budget exceeded"))
+        });
+        let ast = engine.compile(source).map_err(|e| e.to_string())?;
+        Ok(FilterScript { engine, ast, budget, error_warn: RateLimit::new(Duration::from_secs(10)) })
+    }
+
+    /// Whether the update should be published.
+    pub(crate) fn allows(&self, pubkey: &[u8], owner: &[u8], lamports: u64, data_len: usize) -> bool {
+        let mut scope = Scope::new();
+        scope.push_constant("pubkey", bs58::encode(pubkey).into_string());
+        scope.push_constant("owner", bs58::encode(owner).into_string());
+        scope.push_constant("lamports", i64::try_from(lamports).unwrap_or(i64::MAX));
+        scope.push_constant("data_len", i64::try_from(data_len).unwrap_or(i64::MAX));
+        DEADLINE.set(Some(Instant::now() + self.budget));
+        let result = self.engine.eval_ast_with_scope::<bool>(&mut scope, &self.ast);
+        DEADLINE.set(None);
+        match result {
+            Ok(allowed) => allowed,
+            Err(e) => {
+                let reason = match *e {
+                    EvalAltResult::ErrorTerminated(..) => format!("ran over its {:?} budget", self.budget),
+                    e => e.to_string(),
+                };
+                let detail = format!("filter script failed for {}: {reason}; publishing the update", bs58::encode(pubkey).into_string());
+                if let Some(suppressed) = self.error_warn.allow() {
+                    eprintln!("[PLUGIN] WARNING: {detail} ({suppressed} similar warnings suppressed)");
+                }
+                diagnostics::report(Kind::ScriptError, || detail);
+                true
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn script_filters_by_lamports() {
+        let script = FilterScript::compile("lamports >= 1_000_000_000 && data_len == 0", Duration::from_millis(10)).unwrap();
+        assert!(script.allows(&[1; 32], &[0; 32], 1_000_000_000, 0));
+        assert!(!script.allows(&[1; 32], &[0; 32], 999_999_999, 0));
+        assert!(!script.allows(&[1; 32], &[0; 32], 5_000_000_000, 165));
+        // u64 lamports beyond i64 saturate rather than wrap negative
+        assert!(script.allows(&[1; 32], &[0; 32], u64::MAX, 0));
+        let err = FilterScript::compile("lamports >=", Duration::from_millis(10)).unwrap_err();
+        assert!(err.contains("line 1"), "{err}");
+    }
+}
@@ -0,0 +1,123 @@
+//! Where published rows go, chosen by the `sink` option: the NATS [`crate::publisher`]
+//! (default), a local [`FileSink`], [`StdoutSink`], an HTTP [`WebhookSink`], a local
+//! [`UnixSocketSink`] or, with the `grpc` feature, a `GrpcSink` stream; `sinks` fans out to several through a
+//! [`MultiSink`]. Others plug in through the same global slot (see
+//! `LoggerPlugin::load_with_sink`).
+
+use std::sync::{Mutex, atomic::Ordering};
+
+use crate::metrics::COUNTERS;
+
+mod file;
+#[cfg(feature = "grpc")]
+mod grpc;
+mod multi;
+mod stdout;
+mod unix_socket;
+mod webhook;
+
+pub use file::FileSink;
+#[cfg(feature = "grpc")]
+pub use grpc::GrpcSink;
+pub use multi::MultiSink;
+pub use stdout::StdoutSink;
+pub use unix_socket::UnixSocketSink;
+pub use webhook::{WebhookConfig, WebhookSink};
+
+/// A destination for serialized rows and side-channel messages.
+///
+/// Methods are called from Geyser callbacks, so they must not panic and should
+/// not block for long; failures are logged and counted, never returned.
+pub trait Sink: Send + Sync {
+    /// Publish one row on the main subject (may be batched).
+    fn publish(&self, bytes: &[u8]);
+
+    /// Publish one message on `subject`, bypassing any batching.
+    fn publish_to(&self, subject: &str, bytes: &[u8]);
+
+    /// Publish one account row on `subject` (the main subject if `None`). Sinks that
+    /// can carry the row's key out of band (NATS headers) override this.
+    fn publish_row(&self, subject: Option<&str>, bytes: &[u8], _key: RowKey<'_>) {
+        match subject {
+            Some(subject) => self.publish_to(subject, bytes),
+            None => self.publish(bytes),
+        }
+    }
+
+    /// Time-based housekeeping (e.g. sending a stale batch); called from the
+    /// slot-status callback.
+    fn flush_stale(&self) {}
+
+    /// `slot` reached a status after execution (processed, confirmed, rooted or dead), so
+    /// rows batched per slot can go out.
+    fn slot_done(&self, _slot: u64) {}
+
+    /// Send anything batched or buffered now, so it precedes later messages (e.g. the
+    /// end-of-startup and rooted-slot events).
+    fn flush(&self) {}
+
+    /// Send anything pending and release resources; called once from `on_unload`.
+    fn shutdown(&self) {}
+}
+
+/// Identifies the account row being published.
+#[derive(Clone, Copy, Debug)]
+pub struct RowKey<'a> {
+    pub slot: u64,
+    /// base58
+    pub pubkey: &'a str,
+}
+
+/// Keeps messages in memory instead of sending them: for benchmarks and dry runs.
+#[derive(Default)]
+pub struct MemorySink {
+    // (None = main subject, payload); only when keeping messages
+    messages: Mutex<Vec<(Option<String>, Vec<u8>)>>,
+    keep: bool,
+    stats: Mutex<(u64, u64)>,
+}
+
+impl MemorySink {
+    /// A sink that keeps every message; see [`MemorySink::take`].
+    pub fn new() -> Self {
+        MemorySink { keep: true, ..Default::default() }
+    }
+
+    /// A sink that only counts messages and bytes (constant memory).
+    pub fn counting() -> Self {
+        MemorySink::default()
+    }
+
+    /// Messages and payload bytes received so far.
+    pub fn stats(&self) -> (u64, u64) {
+        *self.stats.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Remove and return the kept messages, oldest first.
+    pub fn take(&self) -> Vec<(Option<String>, Vec<u8>)> {
+        std::mem::take(&mut *self.messages.lock().unwrap_or_else(|e| e.into_inner()))
+    }
+
+    fn record(&self, subject: Option<&str>, bytes: &[u8]) {
+        {
+            let mut stats = self.stats.lock().unwrap_or_else(|e| e.into_inner());
+            stats.0 += 1;
+            stats.1 += bytes.len() as u64;
+        }
+        COUNTERS.published.fetch_add(1, Ordering::Relaxed);
+        if self.keep {
+            let mut messages = self.messages.lock().unwrap_or_else(|e| e.into_inner());
+            messages.push((subject.map(str::to_owned), bytes.to_vec()));
+        }
+    }
+}
+
+impl Sink for MemorySink {
+    fn publish(&self, bytes: &[u8]) {
+        self.record(None, bytes);
+    }
+
+    fn publish_to(&self, subject: &str, bytes: &[u8]) {
+        self.record(Some(subject), bytes);
+    }
+}
@@ -0,0 +1,269 @@
+//! `sink = "file"`: newline-delimited JSON rows appended to a local file that is
+//! rotated by size, for hosts that can't reach NATS. Rotated files are renamed
+//! to `<path>.<unix millis>`; a separate process ships them.
+//!
+//! With `file_sink_compress` every file is gzip (`<path>.gz`, rotated to
+//! `<path>.<unix millis>.gz`). Each open appends a new gzip member, which
+//! `gzip -d`, `zcat` and the `replay` bin read as one stream; the member is only
+//! complete once the file is rotated or the sink shut down, so after a crash the
+//! last few rows of the live file may be unreadable.
+//!
+//! This is the only file either binary writes rows to: dead letters go to a NATS
+//! subject (the ingestor's `DLQ_SUBJECT`) and nothing keeps a write-ahead log, so
+//! there are no DLQ or WAL files to compress.
+
+use std::collections::HashMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, atomic::Ordering};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use flate2::{Compression, write::GzEncoder};
+
+use super::Sink;
+use crate::metrics::COUNTERS;
+
+// buffered bytes reach the file at least this often (from the slot-status callback)
+const FLUSH_EVERY: Duration = Duration::from_secs(1);
+
+enum Output {
+    Plain(File),
+    Gzip(GzEncoder<File>),
+}
+
+impl Write for Output {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Output::Plain(f) => f.write(buf),
+            Output::Gzip(gz) => gz.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Output::Plain(f) => f.flush(),
+            Output::Gzip(gz) => gz.flush(),
+        }
+    }
+}
+
+struct RotatingFile {
+    path: PathBuf,
+    out: BufWriter<Output>,
+    gzip: bool,
+    // uncompressed bytes, so rotate_bytes means the same with and without gzip
+    written: u64,
+    rotate_bytes: u64,
+    last_flush: Instant,
+}
+
+impl RotatingFile {
+    fn open(path: PathBuf, rotate_bytes: u64, gzip: bool) -> io::Result<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(&path)?;
+        // for an existing gzip file this is the compressed size; close enough to rotate on
+        let written = file.metadata()?.len();
+        let out = if gzip { Output::Gzip(GzEncoder::new(file, Compression::fast())) } else { Output::Plain(file) };
+        Ok(RotatingFile { path, out: BufWriter::new(out), gzip, written, rotate_bytes, last_flush: Instant::now() })
+    }
+
+    fn append(&mut self, bytes: &[u8]) -> io::Result<()> {
+        if self.rotate_bytes > 0 && self.written > 0 && self.written + bytes.len() as u64 + 1 > self.rotate_bytes {
+            self.rotate()?;
+        }
+        self.out.write_all(bytes)?;
+        self.out.write_all(b"\n")?;
+        self.written += bytes.len() as u64 + 1;
+        Ok(())
+    }
+
+    fn rotate(&mut self) -> io::Result<()> {
+        self.finish()?;
+        let millis = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_millis();
+        let rotated = match self.path.to_str().and_then(|p| p.strip_suffix(".gz")) {
+            Some(base) if self.gzip => PathBuf::from(format!("{base}.{millis}.gz")),
+            _ => {
+                let mut rotated = self.path.clone().into_os_string();
+                rotated.push(format!(".{millis}"));
+                PathBuf::from(rotated)
+            }
+        };
+        fs::rename(&self.path, &rotated)?;
+        *self = RotatingFile::open(self.path.clone(), self.rotate_bytes, self.gzip)?;
+        Ok(())
+    }
+
+    /// Flush and, for gzip, write the member trailer. Nothing may be appended afterwards.
+    fn finish(&mut self) -> io::Result<()> {
+        self.out.flush()?;
+        match self.out.get_mut() {
+            Output::Plain(_) => Ok(()),
+            Output::Gzip(gz) => gz.try_finish(),
+        }
+    }
+
+    fn flush(&mut self, force: bool) {
+        if !force && self.last_flush.elapsed() < FLUSH_EVERY {
+            return;
+        }
+        self.last_flush = Instant::now();
+        if let Err(e) = self.out.flush() {
+            eprintln!("[PLUGIN] file sink flush of {} failed: {e}", self.path.display());
+        }
+    }
+}
+
+/// Rows go to `path`; side-channel messages (close events, ...) to `<path>.<subject>`.
+/// Every message is one line, so binary side channels (`capture_full` CBOR) don't
+/// belong in a file sink.
+pub struct FileSink {
+    path: PathBuf,
+    rotate_bytes: u64,
+    gzip: bool,
+    rows: Mutex<RotatingFile>,
+    side: Mutex<HashMap<String, RotatingFile>>,
+}
+
+impl FileSink {
+    /// Open (or append to) `path`, rotating after `rotate_bytes` (0 = never). With
+    /// `gzip`, `.gz` is appended to every file name.
+    pub fn open(path: &Path, rotate_bytes: u64, gzip: bool) -> io::Result<Self> {
+        let rows = RotatingFile::open(Self::file_name(path.to_path_buf().into_os_string(), gzip), rotate_bytes, gzip)?;
+        Ok(FileSink {
+            path: path.to_path_buf(),
+            rotate_bytes,
+            gzip,
+            rows: Mutex::new(rows),
+            side: Mutex::new(HashMap::new()),
+        })
+    }
+
+    fn file_name(mut path: std::ffi::OsString, gzip: bool) -> PathBuf {
+        if gzip {
+            path.push(".gz");
+        }
+        PathBuf::from(path)
+    }
+
+    fn write(file: &mut RotatingFile, bytes: &[u8]) {
+        match file.append(bytes) {
+            Ok(()) => {
+                COUNTERS.published.fetch_add(1, Ordering::Relaxed);
+            }
+            Err(e) => {
+                COUNTERS.publish_errors.fetch_add(1, Ordering::Relaxed);
+                eprintln!("[PLUGIN] file sink write to {} failed: {e}", file.path.display());
+            }
+        }
+    }
+
+    fn flush_all(&self, force: bool) {
+        self.rows.lock().unwrap_or_else(|e| e.into_inner()).flush(force);
+        for file in self.side.lock().unwrap_or_else(|e| e.into_inner()).values_mut() {
+            file.flush(force);
+        }
+    }
+}
+
+impl Sink for FileSink {
+    fn publish(&self, bytes: &[u8]) {
+        let mut rows = self.rows.lock().unwrap_or_else(|e| e.into_inner());
+        Self::write(&mut rows, bytes);
+    }
+
+    fn publish_to(&self, subject: &str, bytes: &[u8]) {
+        let mut side = self.side.lock().unwrap_or_else(|e| e.into_inner());
+        if !side.contains_key(subject) {
+            let mut path = self.path.clone().into_os_string();
+            path.push(format!(".{subject}"));
+            match RotatingFile::open(Self::file_name(path, self.gzip), self.rotate_bytes, self.gzip) {
+                Ok(file) => {
+                    side.insert(subject.to_string(), file);
+                }
+                Err(e) => {
+                    COUNTERS.publish_errors.fetch_add(1, Ordering::Relaxed);
+                    eprintln!("[PLUGIN] file sink cannot open file for {subject}: {e}");
+                    return;
+                }
+            }
+        }
+        if let Some(file) = side.get_mut(subject) {
+            Self::write(file, bytes);
+        }
+    }
+
+    fn flush_stale(&self) {
+        self.flush_all(false);
+    }
+
+    fn flush(&self) {
+        self.flush_all(true);
+    }
+
+    fn shutdown(&self) {
+        let mut rows = self.rows.lock().unwrap_or_else(|e| e.into_inner());
+        let mut side = self.side.lock().unwrap_or_else(|e| e.into_inner());
+        for file in std::iter::once(&mut *rows).chain(side.values_mut()) {
+            if let Err(e) = file.finish() {
+                eprintln!("[PLUGIN] file sink close of {} failed: {e}", file.path.display());
+            }
+        }
+        eprintln!("[PLUGIN] file sink flushed");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Read;
+
+    use flate2::read::MultiGzDecoder;
+
+    use super::*;
+
+    fn read_gz(path: &Path) -> String {
+        let mut out = String::new();
+        MultiGzDecoder::new(File::open(path).unwrap()).read_to_string(&mut out).unwrap();
+        out
+    }
+
+    #[test]
+    fn gzip_files_read_back_across_reopens_and_rotation() {
+        let dir = std::env::temp_dir().join(format!("wallet-indexer-file-sink-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("rows.jsonl");
+
+        let sink = FileSink::open(&path, 0, true).unwrap();
+        sink.publish(br#"{"slot":1}"#);
+        sink.publish(br#"{"slot":2}"#);
+        sink.publish_to("WALLET.closed", br#"{"type":"closed"}"#);
+        sink.shutdown();
+        // a restart appends a second gzip member to the same file
+        let sink = FileSink::open(&path, 0, true).unwrap();
+        sink.publish(br#"{"slot":3}"#);
+        sink.shutdown();
+        let live = dir.join("rows.jsonl.gz");
+        assert_eq!(read_gz(&live), "{\"slot\":1}\n{\"slot\":2}\n{\"slot\":3}\n");
+        assert_eq!(read_gz(&dir.join("rows.jsonl.WALLET.closed.gz")), "{\"type\":\"closed\"}\n");
+
+        // rotation finishes the member and keeps the .gz suffix last
+        fs::remove_file(&live).unwrap();
+        let sink = FileSink::open(&path, 16, true).unwrap();
+        sink.publish(br#"{"slot":4}"#);
+        sink.publish(br#"{"slot":5}"#);
+        sink.shutdown();
+        let rotated: Vec<PathBuf> = fs::read_dir(&dir)
+            .unwrap()
+            .map(|e| e.unwrap().path())
+            .filter(|p| {
+                let name = p.file_name().and_then(|n| n.to_str()).unwrap_or_default();
+                let millis = name.strip_prefix("rows.jsonl.").and_then(|n| n.strip_suffix(".gz"));
+                millis.is_some_and(|m| m.parse::<u128>().is_ok())
+            })
+            .collect();
+        assert_eq!(rotated.len(), 1, "{rotated:?}");
+        assert_eq!(read_gz(&rotated[0]), "{\"slot\":4}\n");
+        assert_eq!(read_gz(&live), "{\"slot\":5}\n");
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,315 @@
+//! `sink = "grpc"` (feature `grpc`): messages streamed to a gRPC endpoint over the
+//! client-streaming `wallet.WalletUpdates/Stream` call in `proto/wallet_updates.proto`.
+//!
+//! Callbacks only queue; one background thread with its own single-threaded tokio
+//! runtime keeps the stream open and reconnects with backoff (200ms doubling to 10s)
+//! whenever it fails or the server ends it. Delivery is at most once: updates
+//! already handed to a stream that breaks are lost, and anything arriving while
+//! the queue is full is dropped and counted.
+
+use std::io;
+use std::sync::Mutex;
+use std::sync::atomic::Ordering;
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use tokio::sync::mpsc::{self, Receiver, Sender, error::TrySendError};
+use tonic::codec::ProstCodec;
+use tonic::codegen::http::uri::PathAndQuery;
+use tonic::transport::Endpoint;
+
+use super::{RowKey, Sink};
+use crate::diagnostics::{self, Kind};
+use crate::metrics::COUNTERS;
+use crate::state::RateLimit;
+
+/// `wallet.Update`.
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct Update {
+    #[prost(string, tag = "1")]
+    pub subject: String,
+    #[prost(uint64, tag = "2")]
+    pub slot: u64,
+    #[prost(string, tag = "3")]
+    pub pubkey: String,
+    #[prost(bytes = "vec", tag = "4")]
+    pub payload: Vec<u8>,
+}
+
+/// `wallet.StreamSummary`.
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct StreamSummary {
+    #[prost(uint64, tag = "1")]
+    pub received: u64,
+}
+
+const STREAM_PATH: &str = "/wallet.WalletUpdates/Stream";
+const MAX_BACKOFF: Duration = Duration::from_secs(10);
+
+pub struct GrpcSink {
+    tx: Mutex<Option<Sender<Update>>>,
+    handle: Mutex<Option<JoinHandle<()>>>,
+    full_warn: RateLimit,
+}
+
+impl GrpcSink {
+    /// Start streaming to `endpoint` (e.g. "http://127.0.0.1:50051"), queueing up to
+    /// `queue` updates while disconnected.
+    pub fn start(endpoint: &str, queue: usize) -> io::Result<Self> {
+        let endpoint = Endpoint::from_shared(endpoint.to_string())
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?
+            .connect_timeout(Duration::from_secs(5));
+        let runtime = tokio::runtime::Builder::new_current_thread().enable_all().build()?;
+        let (tx, rx) = mpsc::channel(queue.max(1));
+        let handle = thread::Builder::new()
+            .name("grpc-sink".into())
+            .spawn(move || runtime.block_on(stream_updates(endpoint, rx)))?;
+        Ok(GrpcSink {
+            tx: Mutex::new(Some(tx)),
+            handle: Mutex::new(Some(handle)),
+            full_warn: RateLimit::new(Duration::from_secs(10)),
+        })
+    }
+
+    fn enqueue(&self, update: Update) {
+        // unlocked before reporting: a diagnostic event comes back through this sink
+        let sent = match self.tx.lock().unwrap_or_else(|e| e.into_inner()).as_ref() {
+            Some(tx) => tx.try_send(update),
+            None => return,
+        };
+        match sent {
+            Ok(()) => {}
+            Err(TrySendError::Full(_)) => {
+                COUNTERS.publish_errors.fetch_add(1, Ordering::Relaxed);
+                if let Some(suppressed) = self.full_warn.allow() {
+                    eprintln!("[PLUGIN] WARNING: gRPC queue full, dropping updates ({suppressed} more dropped since last warning)");
+                }
+                diagnostics::report(Kind::Dropped, || "gRPC queue full".to_string());
+            }
+            Err(TrySendError::Closed(_)) => {
+                COUNTERS.publish_errors.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+    }
+}
+
+impl Sink for GrpcSink {
+    fn publish(&self, bytes: &[u8]) {
+        self.enqueue(Update { payload: bytes.to_vec(), ..Default::default() });
+    }
+
+    fn publish_to(&self, subject: &str, bytes: &[u8]) {
+        self.enqueue(Update { subject: subject.to_string(), payload: bytes.to_vec(), ..Default::default() });
+    }
+
+    fn publish_row(&self, subject: Option<&str>, bytes: &[u8], key: RowKey<'_>) {
+        self.enqueue(Update {
+            subject: subject.unwrap_or_default().to_string(),
+            slot: key.slot,
+            pubkey: key.pubkey.to_string(),
+            payload: bytes.to_vec(),
+        });
+    }
+
+    fn shutdown(&self) {
+        // dropping the sender ends the stream once everything queued has been sent
+        self.tx.lock().unwrap_or_else(|e| e.into_inner()).take();
+        if let Some(handle) = self.handle.lock().unwrap_or_else(|e| e.into_inner()).take()
+            && handle.join().is_err()
+        {
+            eprintln!("[PLUGIN] WARNING: gRPC sink thread panicked");
+        }
+        eprintln!("[PLUGIN] gRPC sink flushed");
+    }
+}
+
+/// The background side: (re)connect, then feed queued updates into the stream
+/// until the queue is closed.
+async fn stream_updates(endpoint: Endpoint, mut rx: Receiver<Update>) {
+    let target = endpoint.uri().to_string();
+    let mut backoff = Duration::from_millis(200);
+    // the update that found the last stream closed, sent first on the next one
+    let mut carry: Option<Update> = None;
+    loop {
+        if carry.is_none() {
+            // don't hold a connection open while there is nothing to send
+            match rx.recv().await {
+                Some(update) => carry = Some(update),
+                None => return,
+            }
+        }
+        let mut grpc = match endpoint.connect().await {
+            Ok(channel) => tonic::client::Grpc::new(channel),
+            Err(e) => {
+                eprintln!("[PLUGIN] gRPC connect to {target} failed: {e}; retrying in {backoff:?}");
+                tokio::time::sleep(backoff).await;
+                backoff = (backoff * 2).min(MAX_BACKOFF);
+                continue;
+            }
+        };
+        if let Err(e) = grpc.ready().await {
+            eprintln!("[PLUGIN] gRPC endpoint {target} not ready: {e}");
+            tokio::time::sleep(backoff).await;
+            continue;
+        }
+        eprintln!("[PLUGIN] gRPC stream to {target} open");
+        backoff = Duration::from_millis(200);
+
+        let (stream_tx, stream_rx) = mpsc::channel::<Update>(256);
+        let outbound = futures_util::stream::unfold(stream_rx, |mut rx| async move { rx.recv().await.map(|u| (u, rx)) });
+        // a task of its own, so the call keeps draining the stream while this one waits
+        let call = tokio::spawn(async move {
+            let codec = ProstCodec::<Update, StreamSummary>::default();
+            grpc.client_streaming(tonic::Request::new(outbound), PathAndQuery::from_static(STREAM_PATH), codec).await
+        });
+        let mut sent = 0u64;
+        let mut closing = false;
+        loop {
+            if let Some(update) = carry.take() {
+                match stream_tx.send(update).await {
+                    Ok(()) => sent += 1,
+                    // the call is over; its result says why
+                    Err(e) => {
+                        carry = Some(e.0);
+                        break;
+                    }
+                }
+            }
+            match rx.recv().await {
+                Some(update) => carry = Some(update),
+                None => {
+                    // queue closed (shutdown): end the stream and wait for the summary
+                    closing = true;
+                    break;
+                }
+            }
+        }
+        drop(stream_tx);
+        let result = match tokio::time::timeout(Duration::from_secs(5), call).await {
+            Ok(Ok(result)) => result,
+            Ok(Err(e)) => Err(tonic::Status::internal(format!("stream task failed: {e}"))),
+            Err(_) => Err(tonic::Status::deadline_exceeded("no reply within 5s of closing the stream")),
+        };
+        COUNTERS.published.fetch_add(sent, Ordering::Relaxed);
+        match result {
+            Ok(summary) if closing => {
+                eprintln!("[PLUGIN] gRPC stream to {target} closed; server received {}", summary.get_ref().received);
+                return;
+            }
+            Err(status) if closing => {
+                eprintln!("[PLUGIN] gRPC stream to {target} failed while closing: {status}");
+                return;
+            }
+            Ok(_) => eprintln!("[PLUGIN] gRPC stream to {target} ended by the server; reconnecting"),
+            Err(status) => eprintln!("[PLUGIN] gRPC stream to {target} failed: {status}; reconnecting"),
+        }
+        tokio::time::sleep(backoff).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use tonic::codegen::{BoxFuture, Context, Poll, Service, StdError, http};
+    use tonic::transport::server::{Server, TcpIncoming};
+
+    use super::*;
+
+    /// An in-process `wallet.WalletUpdates` server that keeps every update it streams in.
+    #[derive(Clone, Default)]
+    struct Collector(Arc<Mutex<Vec<Update>>>);
+
+    impl tonic::server::ClientStreamingService<Update> for Collector {
+        type Response = StreamSummary;
+        type Future = BoxFuture<tonic::Response<StreamSummary>, tonic::Status>;
+
+        fn call(&mut self, request: tonic::Request<tonic::Streaming<Update>>) -> Self::Future {
+            let seen = self.0.clone();
+            Box::pin(async move {
+                let mut stream = request.into_inner();
+                let mut received = 0;
+                while let Some(update) = stream.message().await? {
+                    seen.lock().unwrap().push(update);
+                    received += 1;
+                }
+                Ok(tonic::Response::new(StreamSummary { received }))
+            })
+        }
+    }
+
+    impl<B> Service<http::Request<B>> for Collector
+    where
+        B: tonic::codegen::Body + Send + 'static,
+        B::Error: Into<StdError> + Send + 'static,
+    {
+        type Response = http::Response<tonic::body::BoxBody>;
+        type Error = std::convert::Infallible;
+        type Future = BoxFuture<Self::Response, Self::Error>;
+
+        fn poll_ready(&mut self, _: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+            Poll::Ready(Ok(()))
+        }
+
+        fn call(&mut self, request: http::Request<B>) -> Self::Future {
+            let collector = self.clone();
+            Box::pin(async move {
+                if request.uri().path() != STREAM_PATH {
+                    return Ok(tonic::Status::unimplemented(request.uri().path().to_string()).into_http());
+                }
+                let mut grpc = tonic::server::Grpc::new(ProstCodec::<StreamSummary, Update>::default());
+                Ok(grpc.client_streaming(collector, request).await)
+            })
+        }
+    }
+
+    impl tonic::server::NamedService for Collector {
+        const NAME: &'static str = "wallet.WalletUpdates";
+    }
+
+    #[test]
+    fn updates_stream_to_a_server_that_comes_up_late() {
+        // a free port, with nothing listening on it yet
+        let addr = std::net::TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap();
+        let sink = GrpcSink::start(&format!("http://{addr}"), 16).unwrap();
+        sink.publish_row(Some("WALLET.pools"), b"{\"slot\":7}", RowKey { slot: 7, pubkey: "9xQe" });
+        sink.publish_to("WALLET.closed", b"{\"type\":\"closed\"}");
+        sink.publish(b"{\"slot\":8}");
+
+        // the sink reconnects with backoff until the server is there
+        thread::sleep(Duration::from_millis(300));
+        let collector = Collector::default();
+        let server = collector.clone();
+        let (stop, stopped) = tokio::sync::oneshot::channel::<()>();
+        let serving = thread::spawn(move || {
+            let runtime = tokio::runtime::Builder::new_current_thread().enable_all().build().unwrap();
+            runtime.block_on(async move {
+                let listener = tokio::net::TcpListener::bind(addr).await.unwrap();
+                let incoming = TcpIncoming::from_listener(listener, true, None).unwrap();
+                Server::builder()
+                    .add_service(server)
+                    .serve_with_incoming_shutdown(incoming, async {
+                        let _ = stopped.await;
+                    })
+                    .await
+                    .unwrap();
+            });
+        });
+        // shutdown ends the stream once the queue is sent, and waits for the summary
+        sink.shutdown();
+        let _ = stop.send(());
+        serving.join().unwrap();
+
+        let seen = collector.0.lock().unwrap();
+        let got: Vec<(&str, u64, &str, &[u8])> =
+            seen.iter().map(|u| (u.subject.as_str(), u.slot, u.pubkey.as_str(), u.payload.as_slice())).collect();
+        assert_eq!(
+            got,
+            [
+                ("WALLET.pools", 7, "9xQe", &b"{\"slot\":7}"[..]),
+                ("WALLET.closed", 0, "", &b"{\"type\":\"closed\"}"[..]),
+                ("", 0, "", &b"{\"slot\":8}"[..]),
+            ]
+        );
+    }
+}
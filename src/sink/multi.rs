@@ -0,0 +1,95 @@
+//! `sinks = [...]`: every message fanned out to several sinks, e.g. NATS for live
+//! consumers plus a file for archival.
+//!
+//! Sinks already log and count their own failures, so one sink failing never keeps
+//! a message from the others; a sink that panics is caught and reported instead of
+//! taking down the callback. They are called one after another on the callback's
+//! thread, so a sink that blocks (NATS backpressure) delays the rest. Each sink
+//! counts what it sent, so `published` counts once per sink.
+
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::Arc;
+
+use super::{RowKey, Sink};
+use crate::diagnostics::{self, Kind};
+
+pub struct MultiSink {
+    // (name for logs, sink)
+    sinks: Vec<(String, Arc<dyn Sink>)>,
+}
+
+impl MultiSink {
+    pub fn new(sinks: Vec<(String, Arc<dyn Sink>)>) -> Self {
+        MultiSink { sinks }
+    }
+
+    fn each(&self, op: &str, f: impl Fn(&dyn Sink)) {
+        for (name, sink) in &self.sinks {
+            if panic::catch_unwind(AssertUnwindSafe(|| f(sink.as_ref()))).is_err() {
+                eprintln!("[PLUGIN] ERROR: {name} sink panicked in {op}; the other sinks are unaffected");
+                diagnostics::report(Kind::Panic, || format!("{name} sink panicked in {op}"));
+            }
+        }
+    }
+}
+
+impl Sink for MultiSink {
+    fn publish(&self, bytes: &[u8]) {
+        self.each("publish", |s| s.publish(bytes));
+    }
+
+    fn publish_to(&self, subject: &str, bytes: &[u8]) {
+        self.each("publish_to", |s| s.publish_to(subject, bytes));
+    }
+
+    fn publish_row(&self, subject: Option<&str>, bytes: &[u8], key: RowKey<'_>) {
+        self.each("publish_row", |s| s.publish_row(subject, bytes, key));
+    }
+
+    fn flush_stale(&self) {
+        self.each("flush_stale", |s| s.flush_stale());
+    }
+
+    fn slot_done(&self, slot: u64) {
+        self.each("slot_done", |s| s.slot_done(slot));
+    }
+
+    fn flush(&self) {
+        self.each("flush", |s| s.flush());
+    }
+
+    fn shutdown(&self) {
+        self.each("shutdown", |s| s.shutdown());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+    use crate::sink::{FileSink, MemorySink};
+
+    #[test]
+    fn every_sink_gets_every_message() {
+        let dir = std::env::temp_dir().join(format!("wallet-indexer-multi-sink-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("rows.jsonl");
+        let memory = Arc::new(MemorySink::new());
+        let file = Arc::new(FileSink::open(&path, 0, false).unwrap());
+        let multi = MultiSink::new(vec![("memory".into(), memory.clone()), ("file".into(), file)]);
+
+        multi.publish_row(None, br#"{"slot":1}"#, RowKey { slot: 1, pubkey: "a" });
+        multi.publish_to("WALLET.closed", br#"{"type":"closed"}"#);
+        multi.shutdown();
+
+        assert_eq!(
+            memory.take(),
+            [(None, br#"{"slot":1}"#.to_vec()), (Some("WALLET.closed".into()), br#"{"type":"closed"}"#.to_vec())]
+        );
+        assert_eq!(fs::read_to_string(&path).unwrap(), "{\"slot\":1}\n");
+        assert_eq!(fs::read_to_string(dir.join("rows.jsonl.WALLET.closed")).unwrap(), "{\"type\":\"closed\"}\n");
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,94 @@
+//! `sink = "stdout"`: every row as one line of JSON on stdout, for piping a test
+//! harness into `jq` or a file. The plugin's own logs go to stderr, so stdout
+//! carries nothing else. Inside a validator stdout is usually the validator's log,
+//! so this is for development only.
+
+use std::io::{self, Write};
+use std::sync::atomic::Ordering;
+
+use super::Sink;
+use crate::metrics::COUNTERS;
+
+/// Rows and side-channel messages alike go out as lines; like the file sink, binary
+/// side channels (`capture_full` CBOR) don't belong here.
+pub struct StdoutSink;
+
+impl StdoutSink {
+    fn write_line(bytes: &[u8]) {
+        // one write under the stdout lock, so concurrent callers never split a line
+        let mut line = Vec::with_capacity(bytes.len() + 1);
+        line.extend_from_slice(bytes);
+        line.push(b'\n');
+        match io::stdout().lock().write_all(&line) {
+            Ok(()) => {
+                COUNTERS.published.fetch_add(1, Ordering::Relaxed);
+            }
+            Err(e) => {
+                COUNTERS.publish_errors.fetch_add(1, Ordering::Relaxed);
+                eprintln!("[PLUGIN] stdout sink write failed: {e}");
+            }
+        }
+    }
+}
+
+impl Sink for StdoutSink {
+    fn publish(&self, bytes: &[u8]) {
+        Self::write_line(bytes);
+    }
+
+    fn publish_to(&self, _subject: &str, bytes: &[u8]) {
+        Self::write_line(bytes);
+    }
+
+    fn flush(&self) {
+        let _ = io::stdout().lock().flush();
+    }
+
+    fn shutdown(&self) {
+        let _ = io::stdout().lock().flush();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::process::Command;
+
+    use super::*;
+
+    const CHILD: &str = "WALLET_INDEXER_STDOUT_SINK_CHILD";
+
+    // Test output capture doesn't see writes to the real stdout, so the test runs
+    // itself again in a child process and reads the child's stdout.
+    #[test]
+    fn rows_from_many_threads_come_out_as_whole_lines() {
+        if std::env::var_os(CHILD).is_some() {
+            std::thread::scope(|s| {
+                for t in 0..4 {
+                    s.spawn(move || {
+                        for i in 0..200 {
+                            let row = format!(r#"{{"thread":{t},"i":{i},"pad":"{}"}}"#, "x".repeat(500));
+                            StdoutSink.publish(row.as_bytes());
+                        }
+                    });
+                }
+            });
+            StdoutSink.publish_to("WALLET.closed", br#"{"type":"closed"}"#);
+            StdoutSink.flush();
+            return;
+        }
+        let out = Command::new(std::env::current_exe().unwrap())
+            .args(["--exact", "sink::stdout::tests::rows_from_many_threads_come_out_as_whole_lines", "--nocapture"])
+            .env(CHILD, "1")
+            .output()
+            .unwrap();
+        assert!(out.status.success(), "{}", String::from_utf8_lossy(&out.stderr));
+        let stdout = String::from_utf8(out.stdout).unwrap();
+        // libtest's own progress lines are not JSON; its "test ... " prefix has no newline
+        let rows: Vec<serde_json::Value> = stdout
+            .lines()
+            .filter_map(|l| l.find('{').map(|at| serde_json::from_str(&l[at..]).unwrap()))
+            .collect();
+        assert_eq!(rows.len(), 4 * 200 + 1);
+        assert_eq!(rows.last().unwrap()["type"], "closed");
+    }
+}
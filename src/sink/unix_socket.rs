@@ -0,0 +1,238 @@
+//! `sink = "unix_socket"`: length-prefixed messages to consumers on the same host,
+//! over a unix domain socket the plugin listens on (`unix_socket_path`).
+//!
+//! Framing, repeated for every message, integers big-endian:
+//!
+//! ```text
+//! u32 frame length (the bytes after it) | u16 subject length | subject | payload
+//! ```
+//!
+//! The subject is empty for rows on the main subject, otherwise the side subject the
+//! message would go to on NATS; the payload is the message as it would be published.
+//!
+//! Any number of consumers may connect; each gets every message from the moment it
+//! connects. Callbacks only queue; one background thread accepts consumers and writes
+//! to them. While nobody is connected messages are dropped and counted, and a consumer
+//! that stops reading for a second is disconnected (it would otherwise hold up the
+//! rest), as is everything arriving while the queue is full.
+
+use std::fs;
+use std::io::{self, Write};
+use std::os::unix::fs::FileTypeExt;
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::sync::atomic::Ordering;
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, SyncSender, TrySendError};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use super::Sink;
+use crate::diagnostics::{self, Kind};
+use crate::metrics::COUNTERS;
+use crate::state::RateLimit;
+
+// a consumer that can't take a frame within this is dropped
+const WRITE_TIMEOUT: Duration = Duration::from_secs(1);
+// how often new consumers are accepted while no messages arrive
+const ACCEPT_EVERY: Duration = Duration::from_millis(100);
+
+pub struct UnixSocketSink {
+    tx: Mutex<Option<SyncSender<Vec<u8>>>>,
+    handle: Mutex<Option<JoinHandle<()>>>,
+    full_warn: RateLimit,
+}
+
+impl UnixSocketSink {
+    /// Listen on `path` (replacing a stale socket left by an earlier run), queueing up
+    /// to `queue` messages for the writer thread.
+    pub fn start(path: &Path, queue: usize) -> io::Result<Self> {
+        if fs::symlink_metadata(path).is_ok_and(|m| m.file_type().is_socket()) {
+            fs::remove_file(path)?;
+        }
+        let listener = UnixListener::bind(path)?;
+        listener.set_nonblocking(true)?;
+        let (tx, rx) = mpsc::sync_channel(queue.max(1));
+        let path = path.to_path_buf();
+        let handle = thread::Builder::new()
+            .name("unix-socket-sink".into())
+            .spawn(move || Writer { listener, path, consumers: Vec::new(), idle_warn: RateLimit::new(Duration::from_secs(10)) }.run(rx))?;
+        Ok(UnixSocketSink {
+            tx: Mutex::new(Some(tx)),
+            handle: Mutex::new(Some(handle)),
+            full_warn: RateLimit::new(Duration::from_secs(10)),
+        })
+    }
+
+    fn enqueue(&self, subject: &str, bytes: &[u8]) {
+        let Ok(subject_len) = u16::try_from(subject.len()) else {
+            COUNTERS.publish_errors.fetch_add(1, Ordering::Relaxed);
+            return;
+        };
+        let Ok(frame_len) = u32::try_from(2 + subject.len() + bytes.len()) else {
+            COUNTERS.publish_errors.fetch_add(1, Ordering::Relaxed);
+            return;
+        };
+        let mut frame = Vec::with_capacity(4 + frame_len as usize);
+        frame.extend_from_slice(&frame_len.to_be_bytes());
+        frame.extend_from_slice(&subject_len.to_be_bytes());
+        frame.extend_from_slice(subject.as_bytes());
+        frame.extend_from_slice(bytes);
+
+        // unlocked before reporting: a diagnostic event comes back through this sink
+        let sent = match self.tx.lock().unwrap_or_else(|e| e.into_inner()).as_ref() {
+            Some(tx) => tx.try_send(frame),
+            None => return,
+        };
+        match sent {
+            Ok(()) => {}
+            Err(TrySendError::Full(_)) => {
+                COUNTERS.publish_errors.fetch_add(1, Ordering::Relaxed);
+                if let Some(suppressed) = self.full_warn.allow() {
+                    eprintln!("[PLUGIN] WARNING: unix socket queue full, dropping messages ({suppressed} more dropped since last warning)");
+                }
+                diagnostics::report(Kind::Dropped, || "unix socket queue full".to_string());
+            }
+            Err(TrySendError::Disconnected(_)) => {
+                COUNTERS.publish_errors.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+    }
+}
+
+impl Sink for UnixSocketSink {
+    fn publish(&self, bytes: &[u8]) {
+        self.enqueue("", bytes);
+    }
+
+    fn publish_to(&self, subject: &str, bytes: &[u8]) {
+        self.enqueue(subject, bytes);
+    }
+
+    fn shutdown(&self) {
+        // dropping the sender ends the writer once it has sent everything queued
+        self.tx.lock().unwrap_or_else(|e| e.into_inner()).take();
+        if let Some(handle) = self.handle.lock().unwrap_or_else(|e| e.into_inner()).take()
+            && handle.join().is_err()
+        {
+            eprintln!("[PLUGIN] WARNING: unix socket sink thread panicked");
+        }
+        eprintln!("[PLUGIN] unix socket sink closed");
+    }
+}
+
+/// The background side: accepting consumers and writing frames to them.
+struct Writer {
+    listener: UnixListener,
+    path: PathBuf,
+    consumers: Vec<UnixStream>,
+    idle_warn: RateLimit,
+}
+
+impl Writer {
+    fn run(mut self, rx: Receiver<Vec<u8>>) {
+        loop {
+            let frame = rx.recv_timeout(ACCEPT_EVERY);
+            self.accept();
+            match frame {
+                Ok(frame) => self.send(&frame),
+                Err(RecvTimeoutError::Timeout) => {}
+                Err(RecvTimeoutError::Disconnected) => break,
+            }
+        }
+        if let Err(e) = fs::remove_file(&self.path) {
+            eprintln!("[PLUGIN] WARNING: cannot remove {}: {e}", self.path.display());
+        }
+    }
+
+    fn accept(&mut self) {
+        loop {
+            match self.listener.accept() {
+                Ok((stream, _)) => {
+                    // accepted sockets inherit non-blocking mode on some platforms
+                    let ready = stream.set_nonblocking(false).and_then(|()| stream.set_write_timeout(Some(WRITE_TIMEOUT)));
+                    match ready {
+                        Ok(()) => {
+                            eprintln!("[PLUGIN] unix socket consumer connected ({} now)", self.consumers.len() + 1);
+                            self.consumers.push(stream);
+                        }
+                        Err(e) => eprintln!("[PLUGIN] WARNING: unix socket consumer setup failed: {e}"),
+                    }
+                }
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => return,
+                Err(e) => {
+                    eprintln!("[PLUGIN] WARNING: unix socket accept failed: {e}");
+                    return;
+                }
+            }
+        }
+    }
+
+    fn send(&mut self, frame: &[u8]) {
+        if self.consumers.is_empty() {
+            COUNTERS.publish_errors.fetch_add(1, Ordering::Relaxed);
+            if let Some(suppressed) = self.idle_warn.allow() {
+                eprintln!(
+                    "[PLUGIN] WARNING: no consumer on {}, dropping messages ({suppressed} more dropped since last warning)",
+                    self.path.display()
+                );
+            }
+            return;
+        }
+        let before = self.consumers.len();
+        // a consumer that fails mid-frame would be out of sync, so it is disconnected
+        self.consumers.retain_mut(|stream| match stream.write_all(frame) {
+            Ok(()) => true,
+            Err(e) => {
+                eprintln!("[PLUGIN] unix socket consumer dropped: {e}");
+                false
+            }
+        });
+        if self.consumers.len() < before {
+            eprintln!("[PLUGIN] unix socket consumers: {} left", self.consumers.len());
+        }
+        if self.consumers.is_empty() {
+            COUNTERS.publish_errors.fetch_add(1, Ordering::Relaxed);
+        } else {
+            COUNTERS.published.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Read;
+
+    use super::*;
+
+    // one frame as a consumer reads it: (subject, payload); None at end of stream
+    fn read_frame(stream: &mut UnixStream) -> Option<(String, Vec<u8>)> {
+        let mut len = [0; 4];
+        stream.read_exact(&mut len).ok()?;
+        let mut frame = vec![0; u32::from_be_bytes(len) as usize];
+        stream.read_exact(&mut frame).unwrap();
+        let subject_len = u16::from_be_bytes([frame[0], frame[1]]) as usize;
+        let subject = String::from_utf8(frame[2..2 + subject_len].to_vec()).unwrap();
+        Some((subject, frame[2 + subject_len..].to_vec()))
+    }
+
+    #[test]
+    fn a_connected_consumer_reads_every_frame() {
+        let path = std::env::temp_dir().join(format!("wallet-indexer-sink-{}.sock", std::process::id()));
+        let sink = UnixSocketSink::start(&path, 16).unwrap();
+        // nobody is connected yet: dropped once the writer takes it off the queue
+        sink.publish(br#"{"slot":1}"#);
+        thread::sleep(ACCEPT_EVERY * 3);
+        let mut consumer = UnixStream::connect(&path).unwrap();
+        consumer.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+        // accepted before the next frame is written
+        sink.publish(br#"{"slot":2}"#);
+        sink.publish_to("WALLET.closed", br#"{"type":"closed"}"#);
+        sink.shutdown();
+
+        assert_eq!(read_frame(&mut consumer), Some((String::new(), br#"{"slot":2}"#.to_vec())));
+        assert_eq!(read_frame(&mut consumer), Some(("WALLET.closed".into(), br#"{"type":"closed"}"#.to_vec())));
+        assert_eq!(read_frame(&mut consumer), None);
+        assert!(!path.exists(), "shutdown removes the socket");
+    }
+}
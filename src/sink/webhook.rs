@@ -0,0 +1,287 @@
+//! `sink = "webhook"`: rows POSTed to an HTTP endpoint as JSON arrays.
+//!
+//! Callbacks only queue; one background thread batches rows (up to `max_rows`, or
+//! whatever is queued after `max_age`) and POSTs them, so a slow endpoint never
+//! blocks the validator. Side-channel messages go out on their own, after the
+//! rows queued before them, with an `X-Wallet-Subject` header. Failed POSTs are
+//! retried with exponential backoff (4xx responses are not); a batch that still
+//! fails, and anything arriving while the queue is full, is dropped and counted.
+
+use std::io;
+use std::sync::Mutex;
+use std::sync::atomic::Ordering;
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, SyncSender, TrySendError};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use super::Sink;
+use crate::diagnostics::{self, Kind};
+use crate::metrics::COUNTERS;
+use crate::state::RateLimit;
+
+/// Where and how to POST.
+pub struct WebhookConfig {
+    pub url: String,
+    /// Sent as the `Authorization` header, e.g. "Bearer <token>".
+    pub auth: Option<String>,
+    pub max_rows: usize,
+    pub max_age: Duration,
+    pub retries: u32,
+    pub queue: usize,
+}
+
+enum Msg {
+    Row(Vec<u8>),
+    Side(String, Vec<u8>),
+    Flush,
+}
+
+pub struct WebhookSink {
+    tx: Mutex<Option<SyncSender<Msg>>>,
+    handle: Mutex<Option<JoinHandle<()>>>,
+    full_warn: RateLimit,
+}
+
+impl WebhookSink {
+    pub fn start(config: WebhookConfig) -> io::Result<Self> {
+        let (tx, rx) = mpsc::sync_channel(config.queue.max(1));
+        let handle = thread::Builder::new().name("webhook-sink".into()).spawn(move || Poster::new(config).run(rx))?;
+        Ok(WebhookSink {
+            tx: Mutex::new(Some(tx)),
+            handle: Mutex::new(Some(handle)),
+            full_warn: RateLimit::new(Duration::from_secs(10)),
+        })
+    }
+
+    fn enqueue(&self, msg: Msg) {
+        // unlocked before reporting: a diagnostic event comes back through this sink
+        let sent = match self.tx.lock().unwrap_or_else(|e| e.into_inner()).as_ref() {
+            Some(tx) => tx.try_send(msg),
+            None => return,
+        };
+        match sent {
+            Ok(()) => {}
+            Err(TrySendError::Full(_)) => {
+                COUNTERS.publish_errors.fetch_add(1, Ordering::Relaxed);
+                if let Some(suppressed) = self.full_warn.allow() {
+                    eprintln!("[PLUGIN] WARNING: webhook queue full, dropping messages ({suppressed} more dropped since last warning)");
+                }
+                diagnostics::report(Kind::Dropped, || "webhook queue full".to_string());
+            }
+            Err(TrySendError::Disconnected(_)) => {
+                COUNTERS.publish_errors.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+    }
+}
+
+impl Sink for WebhookSink {
+    fn publish(&self, bytes: &[u8]) {
+        self.enqueue(Msg::Row(bytes.to_vec()));
+    }
+
+    fn publish_to(&self, subject: &str, bytes: &[u8]) {
+        self.enqueue(Msg::Side(subject.to_string(), bytes.to_vec()));
+    }
+
+    fn flush(&self) {
+        self.enqueue(Msg::Flush);
+    }
+
+    fn shutdown(&self) {
+        // dropping the sender ends the poster once it has sent everything queued
+        self.tx.lock().unwrap_or_else(|e| e.into_inner()).take();
+        if let Some(handle) = self.handle.lock().unwrap_or_else(|e| e.into_inner()).take()
+            && handle.join().is_err()
+        {
+            eprintln!("[PLUGIN] WARNING: webhook sink thread panicked");
+        }
+        eprintln!("[PLUGIN] webhook sink flushed");
+    }
+}
+
+/// The background side: batching and POSTing.
+struct Poster {
+    config: WebhookConfig,
+    agent: ureq::Agent,
+    rows: Vec<Vec<u8>>,
+    started: Instant,
+}
+
+impl Poster {
+    fn new(config: WebhookConfig) -> Self {
+        let agent = ureq::AgentBuilder::new().timeout(Duration::from_secs(10)).build();
+        Poster { config, agent, rows: Vec::new(), started: Instant::now() }
+    }
+
+    fn run(mut self, rx: Receiver<Msg>) {
+        loop {
+            let wait = self.config.max_age.saturating_sub(self.started.elapsed());
+            match rx.recv_timeout(wait) {
+                Ok(Msg::Row(row)) => {
+                    if self.rows.is_empty() {
+                        self.started = Instant::now();
+                    }
+                    self.rows.push(row);
+                    if self.rows.len() >= self.config.max_rows {
+                        self.send_rows();
+                    }
+                }
+                Ok(Msg::Side(subject, bytes)) => {
+                    self.send_rows();
+                    self.post(Some(&subject), &[bytes]);
+                }
+                Ok(Msg::Flush) => self.send_rows(),
+                Err(RecvTimeoutError::Timeout) => {
+                    self.send_rows();
+                    self.started = Instant::now();
+                }
+                Err(RecvTimeoutError::Disconnected) => {
+                    self.send_rows();
+                    return;
+                }
+            }
+        }
+    }
+
+    fn send_rows(&mut self) {
+        if !self.rows.is_empty() {
+            let rows = std::mem::take(&mut self.rows);
+            self.post(None, &rows);
+        }
+    }
+
+    /// POST `messages` as one JSON array, retrying transport errors and 5xx.
+    fn post(&self, subject: Option<&str>, messages: &[Vec<u8>]) {
+        let len: usize = messages.iter().map(|m| m.len() + 1).sum();
+        let mut body = Vec::with_capacity(len + 1);
+        body.push(b'[');
+        for (i, m) in messages.iter().enumerate() {
+            if i > 0 {
+                body.push(b',');
+            }
+            body.extend_from_slice(m);
+        }
+        body.push(b']');
+
+        let mut attempt = 0;
+        loop {
+            let mut req = self.agent.post(&self.config.url).set("Content-Type", "application/json");
+            if let Some(auth) = &self.config.auth {
+                req = req.set("Authorization", auth);
+            }
+            if let Some(subject) = subject {
+                req = req.set("X-Wallet-Subject", subject);
+            }
+            let retryable = match req.send_bytes(&body) {
+                Ok(_) => {
+                    COUNTERS.published.fetch_add(messages.len() as u64, Ordering::Relaxed);
+                    return;
+                }
+                Err(ureq::Error::Status(status, _)) => {
+                    eprintln!("[PLUGIN] webhook POST to {} failed: HTTP {status}", self.config.url);
+                    status >= 500
+                }
+                Err(e) => {
+                    eprintln!("[PLUGIN] webhook POST to {} failed: {e}", self.config.url);
+                    true
+                }
+            };
+            if !retryable || attempt >= self.config.retries {
+                COUNTERS.publish_errors.fetch_add(messages.len() as u64, Ordering::Relaxed);
+                eprintln!("[PLUGIN] ERROR: dropping {} webhook message(s) after {} attempt(s)", messages.len(), attempt + 1);
+                diagnostics::report(Kind::Dropped, || {
+                    format!("{} webhook message(s) dropped after {} attempt(s)", messages.len(), attempt + 1)
+                });
+                return;
+            }
+            thread::sleep(Duration::from_millis(200) * 2u32.pow(attempt.min(6)));
+            attempt += 1;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::io::{BufRead, BufReader, Read, Write};
+    use std::net::TcpListener;
+    use std::sync::Arc;
+
+    use super::*;
+
+    /// One request as the mock endpoint saw it.
+    struct Request {
+        // lower-cased names
+        headers: HashMap<String, String>,
+        body: String,
+    }
+
+    /// An HTTP endpoint answering with `statuses` in turn (200 once they run out).
+    fn mock_endpoint(statuses: Vec<u16>) -> (String, Arc<Mutex<Vec<Request>>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/rows", listener.local_addr().unwrap());
+        let requests = Arc::new(Mutex::new(Vec::new()));
+        let seen = requests.clone();
+        let statuses = Arc::new(Mutex::new(statuses.into_iter()));
+        thread::spawn(move || {
+            for conn in listener.incoming() {
+                let Ok(conn) = conn else { return };
+                let (seen, statuses) = (seen.clone(), statuses.clone());
+                thread::spawn(move || {
+                    let mut reader = BufReader::new(conn.try_clone().unwrap());
+                    let mut writer = conn;
+                    loop {
+                        let mut line = String::new();
+                        if reader.read_line(&mut line).unwrap_or(0) == 0 {
+                            return;
+                        }
+                        let mut headers = HashMap::new();
+                        loop {
+                            let mut line = String::new();
+                            reader.read_line(&mut line).unwrap();
+                            let Some((name, value)) = line.trim_end().split_once(':') else { break };
+                            headers.insert(name.to_ascii_lowercase(), value.trim().to_string());
+                        }
+                        let len = headers.get("content-length").map_or(0, |v| v.parse().unwrap());
+                        let mut body = vec![0; len];
+                        reader.read_exact(&mut body).unwrap();
+                        seen.lock().unwrap().push(Request { headers, body: String::from_utf8(body).unwrap() });
+                        let status = statuses.lock().unwrap().next().unwrap_or(200);
+                        let _ = writer.write_all(format!("HTTP/1.1 {status} Mock\r\ncontent-length: 0\r\n\r\n").as_bytes());
+                    }
+                });
+            }
+        });
+        (url, requests)
+    }
+
+    #[test]
+    fn batches_are_posted_with_auth_and_retried_on_5xx() {
+        let (url, requests) = mock_endpoint(vec![503]);
+        let sink = WebhookSink::start(WebhookConfig {
+            url,
+            auth: Some("Bearer secret".into()),
+            max_rows: 2,
+            max_age: Duration::from_secs(60),
+            retries: 1,
+            queue: 16,
+        })
+        .unwrap();
+        sink.publish(br#"{"slot":1}"#);
+        sink.publish(br#"{"slot":2}"#);
+        sink.publish_to("WALLET.closed", br#"{"type":"closed"}"#);
+        sink.shutdown();
+
+        let requests = requests.lock().unwrap();
+        let bodies: Vec<&str> = requests.iter().map(|r| r.body.as_str()).collect();
+        // the 503 is retried with the same batch
+        assert_eq!(bodies, [r#"[{"slot":1},{"slot":2}]"#, r#"[{"slot":1},{"slot":2}]"#, r#"[{"type":"closed"}]"#]);
+        for r in requests.iter() {
+            assert_eq!(r.headers["authorization"], "Bearer secret");
+            assert_eq!(r.headers["content-type"], "application/json");
+        }
+        assert!(!requests[0].headers.contains_key("x-wallet-subject"));
+        assert_eq!(requests[2].headers["x-wallet-subject"], "WALLET.closed");
+    }
+}
@@ -0,0 +1,188 @@
+//! Bounded per-key state for change tracking (close detection and friends).
+
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+/// A map that never holds more than `cap` entries. Each entry remembers the
+/// slot it was last touched in; when full, the least recently seen quarter is
+/// evicted in one pass, keeping inserts amortised O(1).
+#[derive(Debug)]
+pub(crate) struct BoundedMap<K, V> {
+    cap: usize,
+    entries: HashMap<K, (V, u64)>,
+}
+
+impl<K: Eq + Hash + Clone, V> BoundedMap<K, V> {
+    pub(crate) fn new(cap: usize) -> Self {
+        BoundedMap { cap: cap.max(1), entries: HashMap::new() }
+    }
+
+    /// Insert or update `key`, returning the previous value.
+    pub(crate) fn insert(&mut self, key: K, value: V, slot: u64) -> Option<V> {
+        if !self.entries.contains_key(&key) && self.entries.len() >= self.cap {
+            self.evict_oldest();
+        }
+        self.entries.insert(key, (value, slot)).map(|(v, _)| v)
+    }
+
+    pub(crate) fn clear(&mut self) {
+        self.entries.clear();
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Drop entries last touched before `slot`; returns how many were dropped.
+    pub(crate) fn evict_before(&mut self, slot: u64) -> usize {
+        let before = self.entries.len();
+        self.entries.retain(|_, (_, s)| *s >= slot);
+        before - self.entries.len()
+    }
+
+    fn evict_oldest(&mut self) {
+        let drop_n = (self.cap / 4).max(1);
+        let mut slots: Vec<u64> = self.entries.values().map(|(_, s)| *s).collect();
+        let (_, cutoff, _) = slots.select_nth_unstable(drop_n - 1);
+        let cutoff = *cutoff;
+        let mut dropped = 0;
+        self.entries.retain(|_, (_, s)| {
+            if *s <= cutoff && dropped < drop_n {
+                dropped += 1;
+                false
+            } else {
+                true
+            }
+        });
+    }
+}
+
+/// Lets at most one event through per interval (for log warnings), counting
+/// the ones it swallows in between.
+#[derive(Debug)]
+pub(crate) struct RateLimit {
+    every_ms: u64,
+    last_ms: AtomicU64,
+    suppressed: AtomicU64,
+}
+
+impl RateLimit {
+    pub(crate) const fn new(every: Duration) -> Self {
+        RateLimit { every_ms: every.as_millis() as u64, last_ms: AtomicU64::new(0), suppressed: AtomicU64::new(0) }
+    }
+
+    /// `Some(n)` if this event may be reported, with `n` events suppressed since
+    /// the last one that was; `None` if it should be dropped.
+    pub(crate) fn allow(&self) -> Option<u64> {
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_millis() as u64;
+        let last = self.last_ms.load(Ordering::Relaxed);
+        if now.saturating_sub(last) >= self.every_ms
+            && self.last_ms.compare_exchange(last, now, Ordering::Relaxed, Ordering::Relaxed).is_ok()
+        {
+            return Some(self.suppressed.swap(0, Ordering::Relaxed));
+        }
+        self.suppressed.fetch_add(1, Ordering::Relaxed);
+        None
+    }
+}
+
+/// Holds callers (on any thread) to at most `per_sec` passes per second by sleeping
+/// whoever runs ahead of schedule. After an idle spell, up to a second's worth may
+/// pass at once.
+#[derive(Debug)]
+pub(crate) struct Pacer {
+    interval: Duration,
+    // when the next pass is due
+    next: Mutex<Instant>,
+    waited_us: AtomicU64,
+}
+
+impl Pacer {
+    const BURST: Duration = Duration::from_secs(1);
+
+    pub(crate) fn new(per_sec: u64) -> Self {
+        Pacer {
+            interval: Duration::from_nanos(1_000_000_000 / per_sec.max(1)),
+            next: Mutex::new(Instant::now()),
+            waited_us: AtomicU64::new(0),
+        }
+    }
+
+    /// Wait for this caller's turn.
+    pub(crate) fn pass(&self) {
+        let now = Instant::now();
+        let at = {
+            let mut next = self.next.lock().unwrap_or_else(|e| e.into_inner());
+            let at = (*next).max(now.checked_sub(Self::BURST).unwrap_or(now));
+            *next = at + self.interval;
+            at
+        };
+        if at > now {
+            let wait = at - now;
+            self.waited_us.fetch_add(u64::try_from(wait.as_micros()).unwrap_or(u64::MAX), Ordering::Relaxed);
+            thread::sleep(wait);
+        }
+    }
+
+    /// Total time callers were held back so far.
+    pub(crate) fn waited(&self) -> Duration {
+        Duration::from_micros(self.waited_us.load(Ordering::Relaxed))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pacer_spreads_a_startup_burst_over_the_cap() {
+        let pacer = Pacer::new(200);
+        let started = Instant::now();
+        for _ in 0..41 {
+            pacer.pass();
+        }
+        // 40 intervals of 5ms after the first pass
+        assert!(started.elapsed() >= Duration::from_millis(195), "{:?}", started.elapsed());
+        assert!(pacer.waited() >= Duration::from_millis(150), "{:?}", pacer.waited());
+    }
+
+    #[test]
+    fn full_map_evicts_the_least_recently_seen() {
+        let mut map = BoundedMap::new(8);
+        for key in 0..8u64 {
+            assert_eq!(map.insert(key, key, key), None);
+        }
+        // key 0 is touched again, so keys 1 and 2 are now the oldest quarter
+        assert_eq!(map.insert(0, 10, 20), Some(0));
+        assert_eq!(map.insert(100, 100, 21), None);
+        assert_eq!(map.len(), 7);
+        for kept in [0, 3, 7, 100] {
+            assert!(map.insert(kept, 0, 22).is_some(), "{kept} was evicted");
+        }
+        assert_eq!(map.evict_before(22), 3, "4, 5 and 6 were last seen before slot 22");
+        assert_eq!(map.len(), 4);
+
+        // under pressure it never outgrows its cap
+        let mut map = BoundedMap::new(100);
+        for key in 0..100_000u64 {
+            map.insert(key, (), key);
+            assert!(map.len() <= 100);
+        }
+        assert!(map.insert(99_999, (), 100_000).is_some(), "the newest key survives");
+    }
+
+    #[test]
+    fn rate_limit_reports_what_it_swallowed() {
+        let limit = RateLimit::new(Duration::from_millis(50));
+        assert_eq!(limit.allow(), Some(0));
+        assert_eq!(limit.allow(), None);
+        assert_eq!(limit.allow(), None);
+        thread::sleep(Duration::from_millis(60));
+        assert_eq!(limit.allow(), Some(2));
+        assert_eq!(limit.allow(), None);
+    }
+}
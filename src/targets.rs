@@ -0,0 +1,228 @@
+//! Target wallets refreshed from ClickHouse (`target_source = "clickhouse"`) or a
+//! local file (`target_source = "file"`), so systems outside the validator can
+//! change what is indexed without a restart.
+
+use std::collections::HashSet;
+use std::fmt;
+use std::sync::{Arc, Mutex, atomic::{AtomicBool, Ordering}};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
+
+use anyhow::{Context, Result};
+use arc_swap::ArcSwap;
+use base64::{Engine as _, prelude::BASE64_STANDARD};
+
+use crate::decode_pubkey;
+
+/// The live target set; readers load it lock-free on every account update.
+pub(crate) type TargetSet = Arc<ArcSwap<HashSet<[u8; 32]>>>;
+
+/// Where and how often to fetch the target set.
+#[derive(Debug)]
+pub(crate) struct TargetSource {
+    pub origin: Origin,
+    pub refresh: Duration,
+}
+
+pub(crate) enum Origin {
+    ClickHouse {
+        url: String,
+        user: Option<String>,
+        password: Option<String>,
+        /// A SELECT returning one base58 address per row (first column).
+        query: String,
+    },
+    /// One address per line, see [`parse_target_file`]; re-read when its mtime changes.
+    File { path: String, modified: Mutex<Option<SystemTime>> },
+}
+
+// by hand to keep the password out of logs
+impl fmt::Debug for Origin {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Origin::ClickHouse { url, user, query, .. } => f
+                .debug_struct("ClickHouse")
+                .field("url", url)
+                .field("user", user)
+                .field("query", query)
+                .finish_non_exhaustive(),
+            Origin::File { path, .. } => f.debug_struct("File").field("path", path).finish_non_exhaustive(),
+        }
+    }
+}
+
+impl fmt::Display for Origin {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Origin::ClickHouse { url, .. } => write!(f, "{url}"),
+            Origin::File { path, .. } => write!(f, "{path}"),
+        }
+    }
+}
+
+/// A target file: one address per line, base58 or base64 after a `b64:` prefix.
+/// Blank lines and `#` comments (whole-line or trailing) are skipped. Returns the
+/// set and the number of skipped and invalid lines.
+pub(crate) fn parse_target_file(text: &str) -> (HashSet<[u8; 32]>, usize, usize) {
+    let mut set = HashSet::new();
+    let (mut skipped, mut invalid) = (0, 0);
+    for line in text.lines() {
+        let addr = line.split('#').next().unwrap_or_default().trim();
+        if addr.is_empty() {
+            skipped += 1;
+            continue;
+        }
+        let key = match addr.strip_prefix("b64:") {
+            Some(b64) => BASE64_STANDARD.decode(b64.trim()).ok().and_then(|bytes| <[u8; 32]>::try_from(bytes).ok()),
+            None => decode_pubkey(addr).ok(),
+        };
+        match key {
+            Some(key) => {
+                set.insert(key);
+            }
+            None => invalid += 1,
+        }
+    }
+    (set, skipped, invalid)
+}
+
+impl TargetSource {
+    /// The current target set, or `None` if the source hasn't changed since the last fetch.
+    fn fetch(&self) -> Result<Option<HashSet<[u8; 32]>>> {
+        match &self.origin {
+            Origin::ClickHouse { url, user, password, query } => {
+                Self::fetch_clickhouse(url, user.as_deref(), password.as_deref(), query).map(Some)
+            }
+            Origin::File { path, modified } => {
+                let mtime = std::fs::metadata(path).and_then(|m| m.modified()).with_context(|| format!("stat {path}"))?;
+                let mut last = modified.lock().unwrap_or_else(|e| e.into_inner());
+                if *last == Some(mtime) {
+                    return Ok(None);
+                }
+                let text = std::fs::read_to_string(path).with_context(|| format!("read {path}"))?;
+                let (set, skipped, invalid) = parse_target_file(&text);
+                eprintln!(
+                    "[PLUGIN] target file {path}: {} addresses loaded, {skipped} blank/comment lines skipped",
+                    set.len()
+                );
+                if invalid > 0 {
+                    eprintln!("[PLUGIN] WARNING: target file {path} has {invalid} line(s) that aren't pubkeys; ignored");
+                }
+                *last = Some(mtime);
+                Ok(Some(set))
+            }
+        }
+    }
+
+    fn fetch_clickhouse(url: &str, user: Option<&str>, password: Option<&str>, query: &str) -> Result<HashSet<[u8; 32]>> {
+        let mut req = ureq::post(url)
+            .query("default_format", "TabSeparated")
+            .timeout(Duration::from_secs(10));
+        if let Some(user) = user {
+            req = req.set("X-ClickHouse-User", user);
+        }
+        if let Some(password) = password {
+            req = req.set("X-ClickHouse-Key", password);
+        }
+        let body = req
+            .send_string(query)
+            .with_context(|| format!("query {url}"))?
+            .into_string()
+            .context("read ClickHouse response")?;
+
+        let mut set = HashSet::new();
+        let mut invalid = 0usize;
+        for line in body.lines() {
+            let addr = line.split('\t').next().unwrap_or_default().trim();
+            match decode_pubkey(addr) {
+                Ok(key) => {
+                    set.insert(key);
+                }
+                Err(_) if addr.is_empty() => {}
+                Err(_) => invalid += 1,
+            }
+        }
+        if invalid > 0 {
+            eprintln!("[PLUGIN] WARNING: target query returned {invalid} row(s) that aren't base58 pubkeys; ignored");
+        }
+        Ok(set)
+    }
+
+    /// Replace the set with a fresh fetch; on failure the last known-good set stays.
+    fn refresh(&self, targets: &TargetSet) {
+        match self.fetch() {
+            Ok(None) => {}
+            Ok(Some(set)) => {
+                let old = targets.load().len();
+                if set.len() != old {
+                    eprintln!("[PLUGIN] target set refreshed: {old} → {} wallets", set.len());
+                }
+                targets.store(Arc::new(set));
+            }
+            Err(e) => eprintln!(
+                "[PLUGIN] WARNING: target refresh failed ({e:#}); keeping {} known wallets",
+                targets.load().len()
+            ),
+        }
+    }
+}
+
+/// Background refresh thread; stopped and joined by `on_unload`.
+#[derive(Debug)]
+pub(crate) struct Refresher {
+    stop: Arc<AtomicBool>,
+    handle: thread::JoinHandle<()>,
+}
+
+impl Refresher {
+    /// Fetch once synchronously (so matching starts with real targets when
+    /// the source is up), then re-fetch every `refresh` in the background.
+    pub(crate) fn start(source: TargetSource, targets: TargetSet) -> std::io::Result<Self> {
+        source.refresh(&targets);
+        let stop = Arc::new(AtomicBool::new(false));
+        let flag = stop.clone();
+        let handle = thread::Builder::new().name("target-refresh".into()).spawn(move || {
+            let mut next = Instant::now() + source.refresh;
+            // park can wake early (unpark from stop, or spuriously), hence the deadline
+            while !flag.load(Ordering::SeqCst) {
+                let now = Instant::now();
+                if now < next {
+                    thread::park_timeout(next - now);
+                    continue;
+                }
+                source.refresh(&targets);
+                next = Instant::now() + source.refresh;
+            }
+        })?;
+        Ok(Refresher { stop, handle })
+    }
+
+    pub(crate) fn stop(self) {
+        self.stop.store(true, Ordering::SeqCst);
+        self.handle.thread().unpark();
+        if self.handle.join().is_err() {
+            eprintln!("[PLUGIN] WARNING: target refresh thread panicked");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn mixed_content_target_file_parses() {
+        let (a, b) = ([1u8; 32], [2u8; 32]);
+        let text = format!(
+            "# wallets to watch\n\n{}\n  {}  # trailing comment\nb64:{}\nb64: {}\nnot-an-address\nb64:AAAA\n   \n",
+            bs58::encode(a).into_string(),
+            bs58::encode(b).into_string(),
+            BASE64_STANDARD.encode([3u8; 32]),
+            BASE64_STANDARD.encode(a),
+        );
+        let (set, skipped, invalid) = parse_target_file(&text);
+        assert_eq!(set, HashSet::from([a, b, [3; 32]]));
+        // the header comment and two blank lines; an invalid line and a 3-byte b64 key
+        assert_eq!((skipped, invalid), (3, 2));
+    }
+}
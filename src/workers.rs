@@ -0,0 +1,77 @@
+//! `worker_threads`: a small pool that takes row encoding (base58/base64, JSON)
+//! and the publish call off the Geyser thread.
+//!
+//! Each worker has its own bounded queue and an account always goes to the same
+//! worker, so updates of one account are published in order; different accounts
+//! may interleave differently than they were notified. A full queue blocks the
+//! callback rather than dropping rows, so a stalled sink still applies backpressure.
+
+use std::sync::mpsc::{self, SyncSender};
+use std::thread::JoinHandle;
+
+use crate::diagnostics::{self, Kind};
+
+type Job = Box<dyn FnOnce() + Send>;
+
+#[derive(Debug)]
+pub(crate) struct WorkerPool {
+    senders: Vec<SyncSender<Job>>,
+    handles: Vec<JoinHandle<()>>,
+}
+
+impl WorkerPool {
+    /// Start `threads` workers, each queueing up to `queue` jobs.
+    pub(crate) fn start(threads: usize, queue: usize) -> std::io::Result<Self> {
+        let mut senders = Vec::with_capacity(threads);
+        let mut handles = Vec::with_capacity(threads);
+        for i in 0..threads {
+            let (tx, rx) = mpsc::sync_channel::<Job>(queue);
+            let handle = std::thread::Builder::new().name(format!("wallet-worker-{i}")).spawn(move || {
+                for job in rx {
+                    // a panicking job must not take the worker (and its queue) down
+                    if std::panic::catch_unwind(std::panic::AssertUnwindSafe(job)).is_err() {
+                        eprintln!("[PLUGIN] ERROR: panic in row worker; row dropped");
+                        diagnostics::report(Kind::Panic, || "panic in row worker; row dropped".to_string());
+                    }
+                }
+            })?;
+            senders.push(tx);
+            handles.push(handle);
+        }
+        Ok(WorkerPool { senders, handles })
+    }
+
+    /// Queue `job` on the worker that owns `key` (an account pubkey).
+    pub(crate) fn submit(&self, key: &[u8], job: Job) {
+        let mut head = [0u8; 8];
+        let n = key.len().min(8);
+        head[..n].copy_from_slice(&key[..n]);
+        let worker = (u64::from_le_bytes(head) % self.senders.len() as u64) as usize;
+        if self.senders[worker].send(job).is_err() {
+            eprintln!("[PLUGIN] ERROR: row worker {worker} is gone; row dropped");
+            diagnostics::report(Kind::Dropped, || format!("row worker {worker} is gone; row dropped"));
+        }
+    }
+
+    /// Block until every job queued so far has run.
+    pub(crate) fn drain(&self) {
+        let (done_tx, done_rx) = mpsc::channel();
+        for tx in &self.senders {
+            let done = done_tx.clone();
+            let _ = tx.send(Box::new(move || {
+                let _ = done.send(());
+            }));
+        }
+        drop(done_tx);
+        // ends early only if a worker is gone
+        for _ in done_rx {}
+    }
+
+    /// Run the queued jobs and stop the workers.
+    pub(crate) fn shutdown(self) {
+        drop(self.senders);
+        for handle in self.handles {
+            let _ = handle.join();
+        }
+    }
+}